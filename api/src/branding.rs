@@ -0,0 +1,329 @@
+use crate::config::{BrandingConfig, WatermarkPosition};
+use chrono::NaiveDate;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Size gource renders at, which the cards are drawn at too.
+pub const VIDEO_SIZE: &str = "1920x1200";
+
+/// Distance of the watermark from the edges of the video, in pixels.
+const WATERMARK_MARGIN: u32 = 24;
+const CARD_FADE_SECS: f64 = 0.5;
+
+/// A full-screen card of centred lines of text on black.
+pub struct Card {
+    lines: Vec<(String, u32)>,
+}
+
+impl Card {
+    /// The repository names, the days the video covers, and how many commits
+    /// and contributors it shows.
+    pub fn intro(
+        names: &str,
+        span: Option<(NaiveDate, NaiveDate)>,
+        commits: u32,
+        contributors: usize,
+    ) -> Card {
+        let mut lines = vec![(names.to_string(), 72)];
+        if let Some((first, last)) = span {
+            lines.push((
+                format!(
+                    "{} – {}",
+                    first.format("%-d %b %Y"),
+                    last.format("%-d %b %Y")
+                ),
+                40,
+            ));
+        }
+        lines.push((
+            format!(
+                "{} {} · {} {}",
+                commits,
+                if commits == 1 { "commit" } else { "commits" },
+                contributors,
+                if contributors == 1 {
+                    "contributor"
+                } else {
+                    "contributors"
+                }
+            ),
+            40,
+        ));
+        Card { lines }
+    }
+
+    /// The repository names, followed by the title suffix when there is one.
+    pub fn outro(names: &str, suffix: &str) -> Card {
+        let mut lines = vec![(names.to_string(), 72)];
+        if !suffix.is_empty() {
+            lines.push((suffix.to_string(), 40));
+        }
+        Card { lines }
+    }
+}
+
+/// The title and captions gource draws, and what is composited around and
+/// over its output in the ffmpeg stage.
+pub struct Composition {
+    pub title: String,
+    /// Gource caption file.
+    pub captions: Option<PathBuf>,
+    pub intro: Option<Card>,
+    pub outro: Option<Card>,
+    pub card_duration_secs: f64,
+    pub watermark: Option<Watermark>,
+    /// ASS subtitles drawn over the gource output, but not the cards.
+    pub overlay: Option<PathBuf>,
+    /// Where the card text is written, so it needs no escaping.
+    pub work_dir: PathBuf,
+}
+
+pub struct Watermark {
+    pub path: PathBuf,
+    pub position: WatermarkPosition,
+    pub opacity: f64,
+}
+
+impl Composition {
+    pub fn new(
+        config: &BrandingConfig,
+        title: String,
+        intro: Option<Card>,
+        outro: Option<Card>,
+        work_dir: &Path,
+    ) -> Composition {
+        Composition {
+            title,
+            captions: None,
+            overlay: None,
+            work_dir: work_dir.to_path_buf(),
+            intro,
+            outro,
+            card_duration_secs: config.card_duration_secs,
+            watermark: config.watermark.as_ref().map(|path| Watermark {
+                path: path.clone(),
+                position: config.watermark_position,
+                opacity: config.watermark_opacity,
+            }),
+        }
+    }
+
+    /// Seconds the cards add to the video.
+    pub fn cards_duration(&self) -> f64 {
+        let cards = self.intro.iter().chain(&self.outro).count();
+        cards as f64 * self.card_duration_secs
+    }
+
+    /// ffmpeg arguments, quoted for the shell, that follow the gource input:
+    /// the extra inputs and the filter graph.
+    pub fn ffmpeg_args(&self) -> io::Result<String> {
+        const PAD: &str = "pad=ceil(iw/2)*2:ceil(ih/2)*2";
+        let overlay = self
+            .overlay
+            .as_ref()
+            .map(|path| format!(",ass={}", path.display()))
+            .unwrap_or_default();
+        if self.intro.is_none() && self.outro.is_none() && self.watermark.is_none() {
+            return Ok(format!("-vf \"{}{}\"", PAD, overlay));
+        }
+
+        let mut inputs = Vec::new();
+        let mut filters = vec![format!("[0:v]{},setsar=1{}[main]", PAD, overlay)];
+        let mut segments = Vec::new();
+        // Drawn in the order they are shown.
+        for (name, card) in [("intro", &self.intro), ("outro", &self.outro)] {
+            let Some(card) = card else {
+                continue;
+            };
+            inputs.push(format!(
+                "-f lavfi -i color=c=black:s={}:r=30:d={}",
+                VIDEO_SIZE, self.card_duration_secs
+            ));
+            filters.push(format!(
+                "[{}:v]{}[{}]",
+                inputs.len(),
+                self.card_filter(name, card)?,
+                name
+            ));
+            segments.push(name);
+        }
+
+        let mut video = "main";
+        if !segments.is_empty() {
+            // The intro was added first and the outro last.
+            let main_index = usize::from(self.intro.is_some());
+            segments.insert(main_index, "main");
+            filters.push(format!(
+                "{}concat=n={}:v=1:a=0[joined]",
+                segments
+                    .iter()
+                    .map(|segment| format!("[{}]", segment))
+                    .collect::<String>(),
+                segments.len()
+            ));
+            video = "joined";
+        }
+
+        if let Some(watermark) = &self.watermark {
+            inputs.push(format!(
+                "-i {}",
+                shell_quote(&watermark.path.to_string_lossy())
+            ));
+            filters.push(format!(
+                "[{}:v]format=rgba,colorchannelmixer=aa={}[logo]",
+                inputs.len(),
+                watermark.opacity
+            ));
+            filters.push(format!(
+                "[{}][logo]overlay={}[watermarked]",
+                video,
+                watermark.position.overlay_expression(WATERMARK_MARGIN)
+            ));
+            video = "watermarked";
+        }
+
+        Ok(format!(
+            "{} -filter_complex {} -map \"[{}]\"",
+            inputs.join(" "),
+            shell_quote(&filters.join(";")),
+            video
+        ))
+    }
+
+    fn card_filter(&self, name: &str, card: &Card) -> io::Result<String> {
+        let line_height = |size: u32| size * 3 / 2;
+        let total_height: u32 = card.lines.iter().map(|(_, size)| line_height(*size)).sum();
+
+        let mut filters = Vec::new();
+        let mut offset = 0;
+        for (index, (text, size)) in card.lines.iter().enumerate() {
+            let text_file = self.work_dir.join(format!("{}_{}.txt", name, index));
+            fs::write(&text_file, text)?;
+            filters.push(format!(
+                "drawtext=textfile={}:expansion=none:fontcolor=white:fontsize={}:\
+                x=(w-text_w)/2:y=(h-{})/2+{}",
+                text_file.display(),
+                size,
+                total_height,
+                offset
+            ));
+            offset += line_height(*size);
+        }
+        filters.push(format!("fade=t=in:st=0:d={}", CARD_FADE_SECS));
+        filters.push(format!(
+            "fade=t=out:st={}:d={}",
+            (self.card_duration_secs - CARD_FADE_SECS).max(0.0),
+            CARD_FADE_SECS
+        ));
+        filters.push("setsar=1".to_string());
+        Ok(filters.join(","))
+    }
+}
+
+impl WatermarkPosition {
+    fn overlay_expression(self, margin: u32) -> String {
+        let (x, y) = match self {
+            WatermarkPosition::TopLeft => (format!("{}", margin), format!("{}", margin)),
+            WatermarkPosition::TopRight => (format!("W-w-{}", margin), format!("{}", margin)),
+            WatermarkPosition::BottomLeft => (format!("{}", margin), format!("H-h-{}", margin)),
+            WatermarkPosition::BottomRight => {
+                (format!("W-w-{}", margin), format!("H-h-{}", margin))
+            }
+        };
+        format!("{}:{}", x, y)
+    }
+}
+
+/// Quotes `value` as a single `sh` word.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn composition(work_dir: &Path, intro: bool, outro: bool, watermark: bool) -> Composition {
+        let config = BrandingConfig {
+            watermark: watermark.then(|| PathBuf::from("/branding/it's.png")),
+            card_duration_secs: 2.0,
+            ..BrandingConfig::default()
+        };
+        Composition::new(
+            &config,
+            "owner/repo".to_string(),
+            intro.then(|| Card::intro("owner/repo", None, 1, 1)),
+            outro.then(|| Card::outro("owner/repo", "")),
+            work_dir,
+        )
+    }
+
+    const CARD_INPUT: &str = "-f lavfi -i color=c=black:s=1920x1200:r=30:d=2";
+
+    #[test]
+    fn only_pads_a_plain_video() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut plain = composition(dir.path(), false, false, false);
+        assert_eq!(
+            plain.ffmpeg_args().unwrap(),
+            "-vf \"pad=ceil(iw/2)*2:ceil(ih/2)*2\""
+        );
+
+        plain.overlay = Some(PathBuf::from("/work/stats.ass"));
+        assert_eq!(
+            plain.ffmpeg_args().unwrap(),
+            "-vf \"pad=ceil(iw/2)*2:ceil(ih/2)*2,ass=/work/stats.ass\""
+        );
+    }
+
+    #[test]
+    fn puts_cards_around_the_video() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = |intro, outro| {
+            let composition = composition(dir.path(), intro, outro, false);
+            composition.ffmpeg_args().unwrap()
+        };
+
+        let intro = args(true, false);
+        assert!(intro.starts_with(&format!("{} -filter_complex '", CARD_INPUT)));
+        assert!(intro.contains("[1:v]drawtext=textfile="));
+        assert!(intro.contains(";[intro][main]concat=n=2:v=1:a=0[joined]'"));
+        assert!(intro.ends_with("-map \"[joined]\""));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("intro_1.txt")).unwrap(),
+            "1 commit · 1 contributor"
+        );
+
+        let outro = args(false, true);
+        assert!(outro.contains("[1:v]drawtext="));
+        assert!(outro.contains(";[main][outro]concat=n=2:v=1:a=0[joined]'"));
+
+        let both = args(true, true);
+        assert!(both.starts_with(&format!("{} {} -filter_complex", CARD_INPUT, CARD_INPUT)));
+        assert!(both.contains("[2:v]drawtext="));
+        assert!(both.contains(";[intro][main][outro]concat=n=3:v=1:a=0[joined]'"));
+        assert!(both.contains("fade=t=out:st=1.5:d=0.5"));
+    }
+
+    #[test]
+    fn overlays_the_watermark_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let watermarked = composition(dir.path(), false, false, true);
+        assert_eq!(
+            watermarked.ffmpeg_args().unwrap(),
+            "-i '/branding/it'\\''s.png' -filter_complex \
+             '[0:v]pad=ceil(iw/2)*2:ceil(ih/2)*2,setsar=1[main];\
+             [1:v]format=rgba,colorchannelmixer=aa=0.8[logo];\
+             [main][logo]overlay=W-w-24:H-h-24[watermarked]' \
+             -map \"[watermarked]\""
+        );
+
+        let with_cards = composition(dir.path(), true, true, true);
+        let args = with_cards.ffmpeg_args().unwrap();
+        assert!(args.contains("-i '/branding/it'\\''s.png'"));
+        assert!(args.contains("[3:v]format=rgba"));
+        assert!(args.contains("[joined][logo]overlay="));
+        assert!(args.ends_with("-map \"[watermarked]\""));
+    }
+}
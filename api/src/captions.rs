@@ -0,0 +1,218 @@
+use crate::vcs::{DateRange, Tag};
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+const MAX_EVENTS: usize = 100;
+const MAX_TEXT_CHARS: usize = 200;
+
+/// Captions gource shows as the history reaches them.
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+pub struct CaptionSettings {
+    /// Adds a caption for every tag, such as a release.
+    #[serde(default)]
+    pub tags: bool,
+    #[serde(default)]
+    pub events: Vec<CaptionEvent>,
+    /// Font size, 8 to 72.
+    pub size: Option<u32>,
+    /// Six hex digits.
+    pub colour: Option<String>,
+    /// Seconds each caption is shown for.
+    pub duration: Option<f64>,
+}
+
+/// A caption at a point in time: a day (`YYYY-MM-DD`, shown from the start
+/// of the day in UTC) or an RFC 3339 time.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CaptionEvent {
+    pub date: String,
+    pub text: String,
+}
+
+impl CaptionEvent {
+    fn timestamp(&self) -> Option<i64> {
+        if let Ok(day) = NaiveDate::parse_from_str(&self.date, "%Y-%m-%d") {
+            return Some(day.and_hms_opt(0, 0, 0)?.and_utc().timestamp());
+        }
+        DateTime::parse_from_rfc3339(&self.date)
+            .ok()
+            .map(|time| time.timestamp())
+    }
+}
+
+impl CaptionSettings {
+    pub fn size(&self) -> u32 {
+        self.size.unwrap_or(32)
+    }
+
+    pub fn colour(&self) -> &str {
+        self.colour.as_deref().unwrap_or("FFFFFF")
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.duration.unwrap_or(4.0)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(8..=72).contains(&self.size()) {
+            return Err("captions.size must be between 8 and 72".into());
+        }
+        let colour = self.colour();
+        if colour.len() != 6 || !colour.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("captions.colour must be six hex digits".into());
+        }
+        if !(self.duration() >= 0.5 && self.duration() <= 30.0) {
+            return Err("captions.duration must be between 0.5 and 30 seconds".into());
+        }
+        if self.events.len() > MAX_EVENTS {
+            return Err(format!(
+                "captions.events may hold at most {} events",
+                MAX_EVENTS
+            ));
+        }
+        for event in &self.events {
+            if event.timestamp().is_none() {
+                return Err("captions.events dates must be YYYY-MM-DD or RFC 3339 times".into());
+            }
+            let text = event.text.trim();
+            if text.is_empty() || text.chars().count() > MAX_TEXT_CHARS || text.contains('\n') {
+                return Err(format!(
+                    "captions.events text must be a single line of 1 to {} characters",
+                    MAX_TEXT_CHARS
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether there may be anything to caption.
+    pub fn is_enabled(&self) -> bool {
+        self.tags || !self.events.is_empty()
+    }
+
+    /// A gource caption file (`timestamp|text` lines, oldest first) of the
+    /// events and, if enabled, `tags` that fall within `range`.
+    pub fn caption_file(&self, tags: &[Tag], range: &DateRange) -> String {
+        let tag_captions = tags
+            .iter()
+            .filter(|_| self.tags)
+            .map(|tag| (tag.timestamp, tag.name.as_str()));
+        let event_captions = self
+            .events
+            .iter()
+            .filter_map(|event| Some((event.timestamp()?, event.text.trim())));
+        let mut captions: Vec<(i64, &str)> = tag_captions
+            .chain(event_captions)
+            .filter(|(timestamp, _)| range.contains_time(*timestamp))
+            .collect();
+        captions.sort_by_key(|(timestamp, _)| *timestamp);
+
+        captions
+            .iter()
+            .map(|(timestamp, text)| format!("{}|{}\n", timestamp, text.replace('\r', " ")))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(date: &str, text: &str) -> CaptionEvent {
+        CaptionEvent {
+            date: date.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    fn day(date: &str) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+    }
+
+    #[test]
+    fn rejects_invalid_captions() {
+        let invalid = |settings: CaptionSettings| settings.validate().unwrap_err();
+        let with_event = |event| CaptionSettings {
+            events: vec![event],
+            ..CaptionSettings::default()
+        };
+
+        assert!(CaptionSettings::default().validate().is_ok());
+        assert_eq!(
+            invalid(CaptionSettings {
+                size: Some(7),
+                ..CaptionSettings::default()
+            }),
+            "captions.size must be between 8 and 72"
+        );
+        assert_eq!(
+            invalid(CaptionSettings {
+                colour: Some("#FFFFF".to_string()),
+                ..CaptionSettings::default()
+            }),
+            "captions.colour must be six hex digits"
+        );
+        assert_eq!(
+            invalid(CaptionSettings {
+                duration: Some(f64::NAN),
+                ..CaptionSettings::default()
+            }),
+            "captions.duration must be between 0.5 and 30 seconds"
+        );
+        assert_eq!(
+            invalid(CaptionSettings {
+                events: vec![event("2024-01-01", "launch"); MAX_EVENTS + 1],
+                ..CaptionSettings::default()
+            }),
+            "captions.events may hold at most 100 events"
+        );
+        assert_eq!(
+            invalid(with_event(event("01/02/2024", "launch"))),
+            "captions.events dates must be YYYY-MM-DD or RFC 3339 times"
+        );
+        for text in ["  ", "two\nlines", &"x".repeat(MAX_TEXT_CHARS + 1)] {
+            assert_eq!(
+                invalid(with_event(event("2024-01-01", text))),
+                "captions.events text must be a single line of 1 to 200 characters"
+            );
+        }
+    }
+
+    #[test]
+    fn lists_captions_in_the_range_oldest_first() {
+        let tags = [
+            Tag {
+                name: "v2.0".to_string(),
+                timestamp: 1704412800,
+            },
+            Tag {
+                name: "v0.1".to_string(),
+                timestamp: 1672531200,
+            },
+        ];
+        let mut settings = CaptionSettings {
+            tags: true,
+            events: vec![
+                event("2024-01-03T12:00:00+02:00", " Launch\r day "),
+                event("2024-01-02", "Beta"),
+            ],
+            ..CaptionSettings::default()
+        };
+        let range = DateRange {
+            start: day("2024-01-01"),
+            stop: None,
+        };
+
+        assert_eq!(
+            settings.caption_file(&tags, &range),
+            "1704153600|Beta\n1704276000|Launch  day\n1704412800|v2.0\n"
+        );
+
+        settings.tags = false;
+        let range = DateRange {
+            start: None,
+            stop: day("2024-01-02"),
+        };
+        assert_eq!(settings.caption_file(&tags, &range), "1704153600|Beta\n");
+    }
+}
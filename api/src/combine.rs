@@ -0,0 +1,192 @@
+use crate::process::JobProcesses;
+use crate::vcs::VcsKind;
+use crate::{repo_owner_and_name, GourceError};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+/// Colours given to the repositories of a combined render, in request order.
+const REPOSITORY_COLOURS: &[&str] = &[
+    "4E79A7", "F28E2B", "E15759", "76B7B2", "59A14F", "EDC948", "B07AA1", "FF9DA7", "9C755F",
+    "BAB0AC",
+];
+
+/// One repository of a combined render, cloned into its own directory.
+pub struct Checkout {
+    pub repo_url: String,
+    pub vcs: VcsKind,
+    /// Directory name inside the job's temporary directory, also used as the
+    /// top-level directory of the repository's files in the video.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Assigns each repository a distinct directory under `root`, named after the
+/// repository and prefixed with its owner when two share a name. `vcs`
+/// applies to every repository; otherwise each URL says which it uses.
+pub fn checkouts(repo_urls: &[String], vcs: Option<VcsKind>, root: &Path) -> Vec<Checkout> {
+    let names: Vec<(String, String)> = repo_urls
+        .iter()
+        .enumerate()
+        .map(|(index, repo_url)| {
+            let (owner, name) = repo_owner_and_name(repo_url).unwrap_or_default();
+            let name = safe_name(name.trim_end_matches(".git"))
+                .unwrap_or_else(|| format!("repository-{}", index + 1));
+            (safe_name(&owner).unwrap_or_default(), name)
+        })
+        .collect();
+
+    let mut used = HashSet::new();
+    repo_urls
+        .iter()
+        .zip(&names)
+        .enumerate()
+        .map(|(index, (repo_url, (owner, name)))| {
+            let shared = names.iter().filter(|(_, other)| other == name).count() > 1;
+            let mut name = if shared && !owner.is_empty() {
+                format!("{}-{}", owner, name)
+            } else {
+                name.clone()
+            };
+            if !used.insert(name.clone()) {
+                name = format!("{}-{}", name, index + 1);
+                used.insert(name.clone());
+            }
+            Checkout {
+                repo_url: repo_url.clone(),
+                vcs: VcsKind::resolve(vcs, repo_url),
+                path: root.join(&name),
+                name,
+            }
+        })
+        .collect()
+}
+
+/// Keeps only the characters of `name` that are safe in a path and a shell
+/// command, or `None` when nothing usable is left.
+fn safe_name(name: &str) -> Option<String> {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        .collect();
+    let name = name.trim_start_matches(['.', '-']);
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Writes one gource custom log covering every checkout, with each file moved
+/// under a directory named after its repository and entries from all
+/// repositories in chronological order. A single checkout keeps its paths.
+pub fn write_combined_log(
+    checkouts: &[Checkout],
+    color_by_repository: bool,
+    output: &Path,
+    processes: &JobProcesses,
+) -> Result<(), GourceError> {
+    let mut entries: Vec<(i64, String)> = Vec::new();
+    for (index, checkout) in checkouts.iter().enumerate() {
+        let log = repository_log(checkout, processes)?;
+        let colour =
+            color_by_repository.then(|| REPOSITORY_COLOURS[index % REPOSITORY_COLOURS.len()]);
+        let before = entries.len();
+        let name = (checkouts.len() > 1).then_some(checkout.name.as_str());
+        entries.extend(
+            log.lines()
+                .filter_map(|line| prefix_entry(line, name, colour)),
+        );
+        info!(
+            "Read {} log entries from {}",
+            entries.len() - before,
+            checkout.repo_url
+        );
+    }
+
+    // Stable, so entries of one commit keep their order.
+    entries.sort_by_key(|(timestamp, _)| *timestamp);
+    let mut combined = String::new();
+    for (_, line) in entries {
+        combined.push_str(&line);
+        combined.push('\n');
+    }
+    fs::write(output, combined).map_err(|e| {
+        error!("Failed to write combined log: {}", e);
+        GourceError::CombinedLogFailed
+    })
+}
+
+fn repository_log(checkout: &Checkout, processes: &JobProcesses) -> Result<String, GourceError> {
+    checkout
+        .vcs
+        .implementation()
+        .custom_log(&checkout.path, processes)
+        .inspect_err(|_| error!("Failed to read the history of {}", checkout.repo_url))
+}
+
+/// Rewrites a `timestamp|user|type|path[|colour]` entry so its path sits under
+/// `/{name}` when a name is given, replacing the colour when one is given.
+fn prefix_entry(line: &str, name: Option<&str>, colour: Option<&str>) -> Option<(i64, String)> {
+    let mut fields = line.splitn(5, '|');
+    let timestamp = fields.next()?;
+    let user = fields.next()?;
+    let action = fields.next()?;
+    let path = fields.next()?;
+    let original_colour = fields.next();
+
+    let parsed: i64 = timestamp.trim().parse().ok()?;
+    let path = match name {
+        Some(name) => format!("/{}/{}", name, path.trim_start_matches('/')),
+        None => path.to_string(),
+    };
+    let mut entry = format!("{}|{}|{}|{}", timestamp, user, action, path);
+    if let Some(colour) = colour.or(original_colour) {
+        entry.push('|');
+        entry.push_str(colour);
+    }
+    Some((parsed, entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(repo_urls: &[&str]) -> Vec<String> {
+        let repo_urls: Vec<String> = repo_urls.iter().map(|url| url.to_string()).collect();
+        checkouts(&repo_urls, None, Path::new("/tmp/job"))
+            .into_iter()
+            .map(|checkout| checkout.name)
+            .collect()
+    }
+
+    #[test]
+    fn names_checkouts_after_their_repositories() {
+        assert_eq!(
+            names(&[
+                "https://github.com/a/api.git",
+                "https://github.com/b/web",
+                "https://gitlab.com/c/web",
+            ]),
+            ["api", "b-web", "c-web"]
+        );
+    }
+
+    #[test]
+    fn keeps_only_safe_characters_in_checkout_names() {
+        let checkouts = checkouts(
+            &[
+                "https://example.com/a/x$(touch%20pwned)".to_string(),
+                "https://example.com/a/...git".to_string(),
+                "https://example.com/a/$()".to_string(),
+            ],
+            None,
+            Path::new("/tmp/job"),
+        );
+        let names: Vec<&str> = checkouts
+            .iter()
+            .map(|checkout| checkout.name.as_str())
+            .collect();
+        assert_eq!(names, ["xtouch20pwned", "repository-2", "repository-3"]);
+        for checkout in &checkouts {
+            assert_eq!(checkout.path.parent(), Some(Path::new("/tmp/job")));
+        }
+    }
+}
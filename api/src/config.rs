@@ -0,0 +1,949 @@
+use crate::telemetry::LogFormat;
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Command line flags. Every flag can also be set through the environment
+/// variable listed next to it; flags win over the environment, which wins
+/// over the config file, which wins over the built-in defaults.
+#[derive(Parser, Debug, Default)]
+#[command(
+    name = "gitmotion-api",
+    version,
+    about = "Generates Gource visualizations of Git repositories"
+)]
+pub struct Cli {
+    /// Which parts of the service this process runs
+    #[arg(long, env = "GITMOTION_MODE", value_enum)]
+    pub mode: Option<Mode>,
+
+    /// Path to a TOML config file
+    #[arg(long, env = "GITMOTION_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to bind the HTTP server to
+    #[arg(long, env = "API_HOST")]
+    pub host: Option<String>,
+
+    /// Port to bind the HTTP server to
+    #[arg(long, env = "API_PORT")]
+    pub port: Option<u16>,
+
+    /// Externally reachable base URL of the API, used for links in webhooks
+    #[arg(long, env = "GITMOTION_PUBLIC_URL")]
+    pub public_url: Option<String>,
+
+    /// Where rendered artifacts are stored
+    #[arg(long, env = "GITMOTION_STORAGE_BACKEND", value_enum)]
+    pub storage_backend: Option<StorageBackend>,
+
+    /// Directory rendered videos are written to by the local backend
+    #[arg(long, env = "GITMOTION_VIDEO_DIR")]
+    pub video_dir: Option<PathBuf>,
+
+    /// Bucket used by the S3 backend
+    #[arg(long, env = "S3_BUCKET")]
+    pub s3_bucket: Option<String>,
+
+    /// Region used by the S3 backend
+    #[arg(long, env = "S3_REGION")]
+    pub s3_region: Option<String>,
+
+    /// Endpoint of an S3-compatible server such as MinIO
+    #[arg(long, env = "S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+
+    /// Use path-style bucket addressing (required by MinIO)
+    #[arg(long, env = "S3_PATH_STYLE")]
+    pub s3_path_style: Option<bool>,
+
+    #[arg(long, env = "AWS_ACCESS_KEY_ID", hide_env_values = true)]
+    pub s3_access_key_id: Option<String>,
+
+    #[arg(long, env = "AWS_SECRET_ACCESS_KEY", hide_env_values = true)]
+    pub s3_secret_access_key: Option<String>,
+
+    /// Key used to decrypt access tokens sent by the website
+    #[arg(long, env = "SECRET_KEY", hide_env_values = true)]
+    pub secret_key: Option<String>,
+
+    /// Bearer token required by admin-only endpoints, which are disabled when unset
+    #[arg(long, env = "GITMOTION_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    /// Comma-separated directories whose repositories admins may render in place
+    #[arg(long, env = "GITMOTION_LOCAL_REPO_PATHS", value_delimiter = ',')]
+    pub local_repo_paths: Option<Vec<PathBuf>>,
+
+    /// Largest repository upload accepted, in bytes
+    #[arg(long, env = "GITMOTION_MAX_UPLOAD_BYTES")]
+    pub max_upload_bytes: Option<u64>,
+
+    /// Secret configured on GitHub push webhooks, used to verify their signature
+    #[arg(long, env = "GITHUB_WEBHOOK_SECRET", hide_env_values = true)]
+    pub github_webhook_secret: Option<String>,
+
+    /// How often expired videos are cleaned up, in seconds
+    #[arg(long, env = "GITMOTION_CLEANUP_INTERVAL_SECS")]
+    pub cleanup_interval_secs: Option<u64>,
+
+    /// How long rendered videos are kept by default, in seconds
+    #[arg(long, env = "GITMOTION_VIDEO_TTL_SECS")]
+    pub video_ttl_secs: Option<u64>,
+
+    /// Longest a video can be kept when its expiry is extended, in seconds
+    #[arg(long, env = "GITMOTION_MAX_VIDEO_TTL_SECS")]
+    pub max_video_ttl_secs: Option<u64>,
+
+    /// Size cap for the videos, uploads and images of jobs; the oldest
+    /// unpinned jobs are evicted first
+    #[arg(long, env = "GITMOTION_MAX_DISK_BYTES")]
+    pub max_disk_bytes: Option<u64>,
+
+    /// How long running jobs may take to finish on shutdown before they are
+    /// interrupted, in seconds
+    #[arg(long, env = "GITMOTION_DRAIN_TIMEOUT_SECS")]
+    pub drain_timeout_secs: Option<u64>,
+
+    /// Automatic retries of a job that failed for a transient reason
+    #[arg(long, env = "GITMOTION_MAX_RETRIES")]
+    pub max_retries: Option<u32>,
+
+    /// Redis server used to share jobs between API and worker processes
+    #[arg(long, env = "REDIS_URL")]
+    pub redis_url: Option<String>,
+
+    #[arg(long, env = "REDIS_PASSWORD", hide_env_values = true)]
+    pub redis_password: Option<String>,
+
+    /// Text after the repository name in the video title; empty to leave it out
+    #[arg(long, env = "GITMOTION_TITLE_SUFFIX")]
+    pub title_suffix: Option<String>,
+
+    /// PNG image composited over every video as a watermark
+    #[arg(long, env = "GITMOTION_WATERMARK")]
+    pub watermark: Option<PathBuf>,
+
+    /// Number of jobs a worker renders at the same time
+    #[arg(long, env = "GITMOTION_WORKER_CONCURRENCY")]
+    pub worker_concurrency: Option<usize>,
+
+    /// Log output format
+    #[arg(long, env = "LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// OTLP/HTTP collector endpoint spans are exported to
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub mode: Mode,
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub secret_key: Option<String>,
+    pub admin_token: Option<String>,
+    pub cleanup: CleanupConfig,
+    pub pacing: PacingConfig,
+    pub logging: LoggingConfig,
+    pub retry: RetryConfig,
+    pub shutdown: ShutdownConfig,
+    pub webhooks: WebhookConfig,
+    pub github: GithubConfig,
+    pub uploads: UploadConfig,
+    pub local_repos: LocalReposConfig,
+    pub branding: BrandingConfig,
+    pub previews: PreviewConfig,
+    pub redis: RedisConfig,
+    pub worker: WorkerConfig,
+}
+
+/// `api` only accepts and reports on jobs, `worker` only renders them, and
+/// `all` does both in one process.
+#[derive(Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    All,
+    Api,
+    Worker,
+}
+
+impl Mode {
+    pub fn serves_api(self) -> bool {
+        matches!(self, Mode::All | Mode::Api)
+    }
+
+    pub fn renders(self) -> bool {
+        matches!(self, Mode::All | Mode::Worker)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub public_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    pub video_dir: PathBuf,
+    pub s3: S3Config,
+}
+
+#[derive(Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Local,
+    S3,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    pub endpoint: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub path_style: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CleanupConfig {
+    pub interval_secs: u64,
+    pub video_ttl_secs: u64,
+    pub max_video_ttl_secs: u64,
+    pub max_disk_bytes: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PacingConfig {
+    pub min_duration_secs: f64,
+    pub max_duration_secs: f64,
+    pub active_days_threshold: i32,
+    pub min_seconds_per_day: f64,
+    pub max_seconds_per_day: f64,
+    /// Bounds on how long a quiet period stays on screen before gource skips
+    /// to the next commit.
+    pub min_auto_skip_secs: f64,
+    pub max_auto_skip_secs: f64,
+    /// How far a video fitted to `target_duration_seconds` may end up from
+    /// the target.
+    pub fit_tolerance_secs: f64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    pub drain_timeout_secs: u64,
+    /// Where queued jobs are saved on shutdown when job state is kept in
+    /// memory. Defaults to `queued-jobs.json` in `storage.video_dir`.
+    pub pending_jobs_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub max_attempts: u32,
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    pub timeout_secs: u64,
+    /// Hosts callbacks may be sent to even though they are, or resolve to,
+    /// loopback, private or other non-public addresses.
+    pub allowed_hosts: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct GithubConfig {
+    /// `/webhooks/github` rejects every delivery when unset.
+    pub webhook_secret: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+    pub max_bytes: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalReposConfig {
+    /// Repositories inside these directories can be rendered without cloning
+    /// them from a URL.
+    pub allowed_paths: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrandingConfig {
+    /// Shown after the repository name in the title and on the outro card.
+    pub title_suffix: String,
+    /// Image composited over every video.
+    pub watermark: Option<PathBuf>,
+    pub watermark_position: WatermarkPosition,
+    pub watermark_opacity: f64,
+    /// How long the intro and outro cards are shown.
+    pub card_duration_secs: f64,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreviewConfig {
+    /// Where previews keep their checkouts until they are promoted to a full
+    /// render or their video expires.
+    pub checkout_dir: PathBuf,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    pub url: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerConfig {
+    pub concurrency: usize,
+    pub lease_ttl_secs: u64,
+    pub heartbeat_interval_secs: u64,
+    pub reaper_interval_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "0.0.0.0".to_string(),
+            port: 8081,
+            public_url: None,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: StorageBackend::Local,
+            video_dir: PathBuf::from("/gource_videos"),
+            s3: S3Config::default(),
+        }
+    }
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            bucket: String::new(),
+            region: "us-east-1".to_string(),
+            endpoint: None,
+            access_key_id: None,
+            secret_access_key: None,
+            path_style: false,
+        }
+    }
+}
+
+impl Default for CleanupConfig {
+    fn default() -> Self {
+        CleanupConfig {
+            interval_secs: 3600,
+            video_ttl_secs: 3600,
+            max_video_ttl_secs: 7 * 24 * 3600,
+            max_disk_bytes: None,
+        }
+    }
+}
+
+impl Default for PacingConfig {
+    fn default() -> Self {
+        PacingConfig {
+            min_duration_secs: 40.0,
+            max_duration_secs: 80.0,
+            active_days_threshold: 1000,
+            min_seconds_per_day: 0.00001,
+            max_seconds_per_day: 1.0,
+            min_auto_skip_secs: 0.05,
+            max_auto_skip_secs: 3.0,
+            fit_tolerance_secs: 0.1,
+        }
+    }
+}
+
+impl Default for BrandingConfig {
+    fn default() -> Self {
+        BrandingConfig {
+            title_suffix: "gitmotion.app".to_string(),
+            watermark: None,
+            watermark_position: WatermarkPosition::BottomRight,
+            watermark_opacity: 0.8,
+            card_duration_secs: 3.0,
+        }
+    }
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        PreviewConfig {
+            checkout_dir: std::env::temp_dir().join("gitmotion-previews"),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Text,
+            otlp_endpoint: None,
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: 3,
+            initial_backoff_secs: 15,
+            max_backoff_secs: 600,
+        }
+    }
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            drain_timeout_secs: 60,
+            pending_jobs_file: None,
+        }
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            max_attempts: 5,
+            initial_backoff_secs: 5,
+            max_backoff_secs: 300,
+            timeout_secs: 10,
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            max_bytes: 512 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            concurrency: 1,
+            lease_ttl_secs: 30,
+            heartbeat_interval_secs: 10,
+            reaper_interval_secs: 15,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read config file {path:?}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse config file {path:?}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
+}
+
+impl Config {
+    /// Builds the configuration from defaults, the optional config file, the
+    /// environment and command line flags, then validates it.
+    pub fn load(cli: Cli) -> Result<Config, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        config.apply_cli(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    fn apply_cli(&mut self, cli: Cli) {
+        if let Some(mode) = cli.mode {
+            self.mode = mode;
+        }
+        if let Some(host) = cli.host {
+            self.server.host = host;
+        }
+        if let Some(port) = cli.port {
+            self.server.port = port;
+        }
+        if let Some(public_url) = cli.public_url {
+            self.server.public_url = Some(public_url);
+        }
+        if let Some(backend) = cli.storage_backend {
+            self.storage.backend = backend;
+        }
+        if let Some(video_dir) = cli.video_dir {
+            self.storage.video_dir = video_dir;
+        }
+        if let Some(bucket) = cli.s3_bucket {
+            self.storage.s3.bucket = bucket;
+        }
+        if let Some(region) = cli.s3_region {
+            self.storage.s3.region = region;
+        }
+        if let Some(endpoint) = cli.s3_endpoint {
+            self.storage.s3.endpoint = Some(endpoint);
+        }
+        if let Some(path_style) = cli.s3_path_style {
+            self.storage.s3.path_style = path_style;
+        }
+        if let Some(access_key_id) = cli.s3_access_key_id {
+            self.storage.s3.access_key_id = Some(access_key_id);
+        }
+        if let Some(secret_access_key) = cli.s3_secret_access_key {
+            self.storage.s3.secret_access_key = Some(secret_access_key);
+        }
+        if let Some(secret_key) = cli.secret_key {
+            self.secret_key = Some(secret_key);
+        }
+        if let Some(admin_token) = cli.admin_token {
+            self.admin_token = Some(admin_token);
+        }
+        if let Some(allowed_paths) = cli.local_repo_paths {
+            self.local_repos.allowed_paths = allowed_paths;
+        }
+        if let Some(max_bytes) = cli.max_upload_bytes {
+            self.uploads.max_bytes = max_bytes;
+        }
+        if let Some(webhook_secret) = cli.github_webhook_secret {
+            self.github.webhook_secret = Some(webhook_secret);
+        }
+        if let Some(interval_secs) = cli.cleanup_interval_secs {
+            self.cleanup.interval_secs = interval_secs;
+        }
+        if let Some(video_ttl_secs) = cli.video_ttl_secs {
+            self.cleanup.video_ttl_secs = video_ttl_secs;
+        }
+        if let Some(max_video_ttl_secs) = cli.max_video_ttl_secs {
+            self.cleanup.max_video_ttl_secs = max_video_ttl_secs;
+        }
+        if let Some(max_disk_bytes) = cli.max_disk_bytes {
+            self.cleanup.max_disk_bytes = Some(max_disk_bytes);
+        }
+        if let Some(drain_timeout_secs) = cli.drain_timeout_secs {
+            self.shutdown.drain_timeout_secs = drain_timeout_secs;
+        }
+        if let Some(max_retries) = cli.max_retries {
+            self.retry.max_retries = max_retries;
+        }
+        if let Some(url) = cli.redis_url {
+            self.redis.url = Some(url);
+        }
+        if let Some(password) = cli.redis_password {
+            self.redis.password = Some(password);
+        }
+        if let Some(title_suffix) = cli.title_suffix {
+            self.branding.title_suffix = title_suffix;
+        }
+        if let Some(watermark) = cli.watermark {
+            self.branding.watermark = Some(watermark);
+        }
+        if let Some(concurrency) = cli.worker_concurrency {
+            self.worker.concurrency = concurrency;
+        }
+        if let Some(format) = cli.log_format {
+            self.logging.format = format;
+        }
+        if let Some(otlp_endpoint) = cli.otlp_endpoint {
+            self.logging.otlp_endpoint = Some(otlp_endpoint);
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.port == 0 {
+            return Err(ConfigError::Invalid("server.port must not be 0".into()));
+        }
+        if !self.storage.video_dir.is_absolute() {
+            return Err(ConfigError::Invalid(
+                "storage.video_dir must be an absolute path".into(),
+            ));
+        }
+        if self.storage.backend == StorageBackend::S3 && self.storage.s3.bucket.is_empty() {
+            return Err(ConfigError::Invalid(
+                "storage.s3.bucket must be set when storage.backend is \"s3\"".into(),
+            ));
+        }
+        if self.secret_key.as_deref() == Some("") {
+            return Err(ConfigError::Invalid("secret_key must not be empty".into()));
+        }
+        if self.cleanup.interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "cleanup.interval_secs must be greater than 0".into(),
+            ));
+        }
+        if self.cleanup.video_ttl_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "cleanup.video_ttl_secs must be greater than 0".into(),
+            ));
+        }
+        if self.cleanup.max_video_ttl_secs < self.cleanup.video_ttl_secs {
+            return Err(ConfigError::Invalid(
+                "cleanup.max_video_ttl_secs must not be less than cleanup.video_ttl_secs".into(),
+            ));
+        }
+        if self.cleanup.max_disk_bytes == Some(0) {
+            return Err(ConfigError::Invalid(
+                "cleanup.max_disk_bytes must be greater than 0".into(),
+            ));
+        }
+
+        let pacing = &self.pacing;
+        if !(pacing.min_duration_secs > 0.0 && pacing.min_duration_secs <= pacing.max_duration_secs)
+        {
+            return Err(ConfigError::Invalid(
+                "pacing.min_duration_secs must be positive and not exceed pacing.max_duration_secs"
+                    .into(),
+            ));
+        }
+        if pacing.active_days_threshold <= 0 {
+            return Err(ConfigError::Invalid(
+                "pacing.active_days_threshold must be greater than 0".into(),
+            ));
+        }
+        if !(pacing.min_seconds_per_day > 0.0
+            && pacing.min_seconds_per_day <= pacing.max_seconds_per_day)
+        {
+            return Err(ConfigError::Invalid(
+                "pacing.min_seconds_per_day must be positive and not exceed pacing.max_seconds_per_day"
+                    .into(),
+            ));
+        }
+        if !(pacing.min_auto_skip_secs > 0.0
+            && pacing.min_auto_skip_secs <= pacing.max_auto_skip_secs)
+        {
+            return Err(ConfigError::Invalid(
+                "pacing.min_auto_skip_secs must be positive and not exceed pacing.max_auto_skip_secs"
+                    .into(),
+            ));
+        }
+        if !(pacing.fit_tolerance_secs > 0.0 && pacing.fit_tolerance_secs.is_finite()) {
+            return Err(ConfigError::Invalid(
+                "pacing.fit_tolerance_secs must be greater than 0".into(),
+            ));
+        }
+
+        if !(self.retry.initial_backoff_secs > 0
+            && self.retry.initial_backoff_secs <= self.retry.max_backoff_secs)
+        {
+            return Err(ConfigError::Invalid(
+                "retry.initial_backoff_secs must be positive and not exceed retry.max_backoff_secs"
+                    .into(),
+            ));
+        }
+        if let Some(public_url) = &self.server.public_url {
+            if url::Url::parse(public_url).is_err() {
+                return Err(ConfigError::Invalid(
+                    "server.public_url must be an absolute URL".into(),
+                ));
+            }
+        }
+        let webhooks = &self.webhooks;
+        if webhooks.max_attempts == 0 || webhooks.timeout_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "webhooks.max_attempts and webhooks.timeout_secs must be greater than 0".into(),
+            ));
+        }
+        if !(webhooks.initial_backoff_secs > 0
+            && webhooks.initial_backoff_secs <= webhooks.max_backoff_secs)
+        {
+            return Err(ConfigError::Invalid(
+                "webhooks.initial_backoff_secs must be positive and not exceed webhooks.max_backoff_secs"
+                    .into(),
+            ));
+        }
+        if self.github.webhook_secret.as_deref() == Some("") {
+            return Err(ConfigError::Invalid(
+                "github.webhook_secret must not be empty".into(),
+            ));
+        }
+        if self.admin_token.as_deref() == Some("") {
+            return Err(ConfigError::Invalid("admin_token must not be empty".into()));
+        }
+        if self.uploads.max_bytes == 0 {
+            return Err(ConfigError::Invalid(
+                "uploads.max_bytes must be greater than 0".into(),
+            ));
+        }
+        if self
+            .local_repos
+            .allowed_paths
+            .iter()
+            .any(|path| !path.is_absolute())
+        {
+            return Err(ConfigError::Invalid(
+                "local_repos.allowed_paths must only contain absolute paths".into(),
+            ));
+        }
+        let branding = &self.branding;
+        if let Some(watermark) = &branding.watermark {
+            if !watermark.is_file() {
+                return Err(ConfigError::Invalid(format!(
+                    "branding.watermark {:?} is not a file",
+                    watermark
+                )));
+            }
+        }
+        if !(branding.watermark_opacity > 0.0 && branding.watermark_opacity <= 1.0) {
+            return Err(ConfigError::Invalid(
+                "branding.watermark_opacity must be greater than 0 and at most 1".into(),
+            ));
+        }
+        if !(branding.card_duration_secs >= 1.0 && branding.card_duration_secs <= 10.0) {
+            return Err(ConfigError::Invalid(
+                "branding.card_duration_secs must be between 1 and 10".into(),
+            ));
+        }
+        if !self.previews.checkout_dir.is_absolute() {
+            return Err(ConfigError::Invalid(
+                "previews.checkout_dir must be an absolute path".into(),
+            ));
+        }
+        if !self.pending_jobs_file().is_absolute() {
+            return Err(ConfigError::Invalid(
+                "shutdown.pending_jobs_file must be an absolute path".into(),
+            ));
+        }
+        if self.mode != Mode::All && self.redis.url.is_none() {
+            return Err(ConfigError::Invalid(
+                "redis.url must be set when mode is \"api\" or \"worker\"".into(),
+            ));
+        }
+        let worker = &self.worker;
+        if worker.concurrency == 0 {
+            return Err(ConfigError::Invalid(
+                "worker.concurrency must be greater than 0".into(),
+            ));
+        }
+        if !(worker.heartbeat_interval_secs > 0
+            && worker.heartbeat_interval_secs < worker.lease_ttl_secs)
+        {
+            return Err(ConfigError::Invalid(
+                "worker.heartbeat_interval_secs must be positive and less than worker.lease_ttl_secs"
+                    .into(),
+            ));
+        }
+        if worker.reaper_interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "worker.reaper_interval_secs must be greater than 0".into(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn cleanup_interval(&self) -> Duration {
+        Duration::from_secs(self.cleanup.interval_secs)
+    }
+
+    pub fn pending_jobs_file(&self) -> PathBuf {
+        self.shutdown
+            .pending_jobs_file
+            .clone()
+            .unwrap_or_else(|| self.storage.video_dir.join("queued-jobs.json"))
+    }
+
+    pub fn video_ttl(&self) -> Duration {
+        Duration::from_secs(self.cleanup.video_ttl_secs)
+    }
+
+    pub fn max_video_ttl(&self) -> Duration {
+        Duration::from_secs(self.cleanup.max_video_ttl_secs)
+    }
+
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown.drain_timeout_secs)
+    }
+
+    /// Delay before automatic retry number `attempt` (starting at 1).
+    pub fn retry_backoff(&self, attempt: u32) -> Duration {
+        exponential_backoff(
+            self.retry.initial_backoff_secs,
+            self.retry.max_backoff_secs,
+            attempt,
+        )
+    }
+
+    /// Delay after failed webhook delivery attempt number `attempt` (starting at 1).
+    pub fn webhook_backoff(&self, attempt: u32) -> Duration {
+        exponential_backoff(
+            self.webhooks.initial_backoff_secs,
+            self.webhooks.max_backoff_secs,
+            attempt,
+        )
+    }
+}
+
+/// Starts at `initial_secs` and doubles with each attempt, up to `max_secs`.
+fn exponential_backoff(initial_secs: u64, max_secs: u64, attempt: u32) -> Duration {
+    let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+    Duration::from_secs(initial_secs.saturating_mul(factor).min(max_secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(args: &[&str]) -> Result<Config, ConfigError> {
+        Config::load(Cli::try_parse_from([&["gitmotion-api"], args].concat()).unwrap())
+    }
+
+    #[test]
+    fn flags_override_the_environment_which_overrides_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "[server]\nport = 1000\npublic_url = \"https://file.example\"\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        // No other test reads this variable.
+        std::env::set_var("GITMOTION_PUBLIC_URL", "https://env.example");
+        let from_env = load(&["--config", path]);
+        let from_flag = load(&["--config", path, "--public-url", "https://flag.example"]);
+        std::env::remove_var("GITMOTION_PUBLIC_URL");
+        let from_file = load(&["--config", path]).unwrap();
+
+        assert_eq!(from_file.server.port, 1000);
+        assert_eq!(
+            from_file.server.public_url.as_deref(),
+            Some("https://file.example")
+        );
+        assert_eq!(
+            from_env.unwrap().server.public_url.as_deref(),
+            Some("https://env.example")
+        );
+        assert_eq!(
+            from_flag.unwrap().server.public_url.as_deref(),
+            Some("https://flag.example")
+        );
+        assert_eq!(
+            load(&[]).unwrap().cleanup.interval_secs,
+            Config::default().cleanup.interval_secs
+        );
+    }
+
+    #[test]
+    fn loads_the_example_config() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/config.example.toml");
+        let config = load(&["--config", path]).unwrap();
+        assert_eq!(config.cleanup.interval_secs, 3600);
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let invalid = |args: &[&str]| match load(args) {
+            Err(ConfigError::Invalid(message)) => message,
+            other => panic!("expected an invalid config, got {:?}", other.map(|_| ())),
+        };
+        assert_eq!(invalid(&["--port", "0"]), "server.port must not be 0");
+        assert_eq!(
+            invalid(&["--video-dir", "videos"]),
+            "storage.video_dir must be an absolute path"
+        );
+        assert_eq!(
+            invalid(&["--cleanup-interval-secs", "0"]),
+            "cleanup.interval_secs must be greater than 0"
+        );
+        assert_eq!(
+            invalid(&["--secret-key", ""]),
+            "secret_key must not be empty"
+        );
+
+        let missing = load(&["--config", "/nonexistent/gitmotion.toml"]);
+        assert!(matches!(missing, Err(ConfigError::Read { .. })));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let delays: Vec<u64> = (1..=6)
+            .map(|attempt| exponential_backoff(10, 60, attempt).as_secs())
+            .collect();
+        assert_eq!(delays, [10, 20, 40, 60, 60, 60]);
+        assert_eq!(exponential_backoff(10, 60, 0), Duration::from_secs(10));
+        assert_eq!(
+            exponential_backoff(10, 60, u32::MAX),
+            Duration::from_secs(60)
+        );
+
+        let config = Config::default();
+        assert_eq!(
+            config.retry_backoff(1),
+            Duration::from_secs(config.retry.initial_backoff_secs)
+        );
+        assert_eq!(
+            config.webhook_backoff(100),
+            Duration::from_secs(config.webhooks.max_backoff_secs)
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use crate::vcs::{DateRange, HistoryStats};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+/// Lines longer than this are rejected rather than handed to gource.
+const MAX_LINE_BYTES: usize = 4096;
+
+#[derive(Error, Debug)]
+pub enum CustomLogError {
+    #[error("Failed to read the log: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("The log is not UTF-8 text")]
+    NotText,
+    #[error("The log has no entries")]
+    Empty,
+    #[error("Line {line} of the log is invalid: {reason}")]
+    InvalidLine { line: usize, reason: &'static str },
+}
+
+/// One `timestamp|username|type|file[|colour]` line of a gource custom log.
+struct LogEntry<'a> {
+    timestamp: i64,
+    username: &'a str,
+    line: &'a str,
+}
+
+/// Parses and checks a single line. `type` is one of `A`, `M` or `D`, and the
+/// optional colour is six hex digits.
+fn parse_line(line: &str) -> Result<LogEntry<'_>, &'static str> {
+    if line.len() > MAX_LINE_BYTES {
+        return Err("line is too long");
+    }
+    let fields: Vec<&str> = line.split('|').collect();
+    if !(4..=5).contains(&fields.len()) {
+        return Err("expected timestamp|username|type|file with an optional |colour");
+    }
+    let timestamp = fields[0]
+        .trim()
+        .parse()
+        .map_err(|_| "timestamp must be a Unix time in seconds")?;
+    if fields[1].trim().is_empty() {
+        return Err("username must not be empty");
+    }
+    if !matches!(fields[2], "A" | "M" | "D") {
+        return Err("type must be A, M or D");
+    }
+    if fields[3].trim().is_empty() {
+        return Err("file must not be empty");
+    }
+    if let Some(colour) = fields.get(4) {
+        if colour.len() != 6 || !colour.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("colour must be six hex digits");
+        }
+    }
+    Ok(LogEntry {
+        timestamp,
+        username: fields[1],
+        line,
+    })
+}
+
+/// Checks every line of the log at `path`.
+pub fn validate(path: &Path) -> Result<(), CustomLogError> {
+    let contents = read(path)?;
+    entries(&contents).map(|_| ())
+}
+
+/// Writes the log at `source` to `output` in chronological order, as gource
+/// expects, and returns the number of commits within `range` by UTC day and by
+/// username. Lines with the same timestamp and username count as one commit.
+pub fn prepare(
+    source: &Path,
+    output: &Path,
+    range: &DateRange,
+) -> Result<HistoryStats, CustomLogError> {
+    let contents = read(source)?;
+    let mut entries = entries(&contents)?;
+    // Stable, so the lines of one commit keep their order.
+    entries.sort_by_key(|entry| entry.timestamp);
+
+    let mut sorted = String::with_capacity(contents.len());
+    let mut stats = HistoryStats::default();
+    let mut commits = HashSet::new();
+    for entry in &entries {
+        sorted.push_str(entry.line);
+        sorted.push('\n');
+
+        if range.contains_time(entry.timestamp) && commits.insert((entry.timestamp, entry.username))
+        {
+            stats.add_commit(entry.timestamp, entry.username);
+        }
+    }
+    fs::write(output, sorted)?;
+
+    Ok(stats)
+}
+
+fn read(path: &Path) -> Result<String, CustomLogError> {
+    String::from_utf8(fs::read(path)?).map_err(|_| CustomLogError::NotText)
+}
+
+fn entries(contents: &str) -> Result<Vec<LogEntry<'_>>, CustomLogError> {
+    let mut entries = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = parse_line(line).map_err(|reason| CustomLogError::InvalidLine {
+            line: index + 1,
+            reason,
+        })?;
+        entries.push(entry);
+    }
+    if entries.is_empty() {
+        return Err(CustomLogError::Empty);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn invalid_line(log: &str) -> (usize, &'static str) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gource.log");
+        fs::write(&path, log).unwrap();
+        match validate(&path) {
+            Err(CustomLogError::InvalidLine { line, reason }) => (line, reason),
+            other => panic!("{:?} was accepted as {:?}", log, other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let valid = "1700000000|alice|A|/src/main.rs\n";
+        for (line, reason) in [
+            (
+                "1700000000|alice|A",
+                "expected timestamp|username|type|file with an optional |colour",
+            ),
+            (
+                "1700000000|alice|A|/a|FF0000|extra",
+                "expected timestamp|username|type|file with an optional |colour",
+            ),
+            (
+                "yesterday|alice|A|/a",
+                "timestamp must be a Unix time in seconds",
+            ),
+            ("1.5|alice|A|/a", "timestamp must be a Unix time in seconds"),
+            ("1700000000|alice|R|/a", "type must be A, M or D"),
+            ("1700000000|alice|a|/a", "type must be A, M or D"),
+            ("1700000000| |M|/a", "username must not be empty"),
+            ("1700000000|alice|D| ", "file must not be empty"),
+            ("1700000000|alice|M|/a|red", "colour must be six hex digits"),
+        ] {
+            assert_eq!(
+                invalid_line(&format!("{}{}\n", valid, line)),
+                (2, reason),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_logs_without_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gource.log");
+        fs::write(&path, "\n  \n").unwrap();
+        assert!(matches!(validate(&path), Err(CustomLogError::Empty)));
+        fs::write(&path, [0xff, 0xfe, b'|']).unwrap();
+        assert!(matches!(validate(&path), Err(CustomLogError::NotText)));
+    }
+
+    #[test]
+    fn sorts_stably_and_counts_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("gource.log");
+        let output = dir.path().join("sorted.log");
+        // 2023-11-14 22:13:20 UTC, then the next day.
+        fs::write(
+            &source,
+            "1700086400|bob|M|/b\r\n\
+             1700000000|alice|A|/one\n\
+             1700000000|alice|A|/two|00FF00\n\
+             \n\
+             1700000000|carol|D|/three\n\
+             1700086400|alice|M|/a\n",
+        )
+        .unwrap();
+
+        let stats = prepare(&source, &output, &DateRange::default()).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "1700000000|alice|A|/one\n\
+             1700000000|alice|A|/two|00FF00\n\
+             1700000000|carol|D|/three\n\
+             1700086400|bob|M|/b\n\
+             1700086400|alice|M|/a\n"
+        );
+        let first = NaiveDate::from_ymd_opt(2023, 11, 14).unwrap();
+        let second = NaiveDate::from_ymd_opt(2023, 11, 15).unwrap();
+        assert_eq!(stats.total_commits(), 4);
+        assert_eq!(stats.commits_per_day[&first], 2);
+        assert_eq!(stats.commits_per_day[&second], 2);
+        assert_eq!(stats.commits_per_author["alice"], 2);
+        assert_eq!(stats.commits_per_author["bob"], 1);
+
+        let range = DateRange {
+            start: Some(second),
+            stop: None,
+        };
+        let stats = prepare(&source, &output, &range).unwrap();
+        assert_eq!(stats.total_commits(), 2);
+        assert_eq!(stats.span(), Some((second, second)));
+    }
+}
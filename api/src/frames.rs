@@ -0,0 +1,157 @@
+use crate::process::JobProcesses;
+use crate::GourceError;
+use std::path::Path;
+use std::process::Command;
+use tracing::error;
+
+pub const DEFAULT_CONTACT_SHEET_FRAMES: u32 = 9;
+pub const MAX_CONTACT_SHEET_FRAMES: u32 = 36;
+
+/// Width frames are scaled to on a contact sheet.
+const SHEET_FRAME_WIDTH: u32 = 640;
+const SHEET_PADDING: u32 = 4;
+/// One frame at the 30 fps gource renders at.
+const FRAME_SECS: f64 = 1.0 / 30.0;
+
+/// The part of a video frames are taken from, in seconds.
+#[derive(Clone, Copy, Debug)]
+pub struct Section {
+    pub start: f64,
+    pub duration: f64,
+}
+
+/// Where the frames of a contact sheet are taken from and how they are tiled.
+#[derive(Debug, PartialEq)]
+struct SheetLayout {
+    columns: u32,
+    rows: u32,
+    /// Seconds between two frames.
+    interval: f64,
+    /// Time of the first frame.
+    start: f64,
+}
+
+impl SheetLayout {
+    /// About as many columns as rows, with each frame from the middle of its
+    /// share of the section, which skips the empty first frame. Frames are
+    /// at least one video frame apart, even in a shorter section.
+    fn new(section: Section, count: u32) -> SheetLayout {
+        let columns = f64::from(count).sqrt().ceil() as u32;
+        let interval = section.duration.max(FRAME_SECS * f64::from(count)) / f64::from(count);
+        SheetLayout {
+            columns,
+            rows: count.div_ceil(columns),
+            interval,
+            start: section.start + interval / 2.0,
+        }
+    }
+}
+
+/// Tiles `count` frames, taken evenly across `section`, into one PNG with
+/// about as many columns as rows.
+pub fn contact_sheet(
+    video: &Path,
+    section: Section,
+    count: u32,
+    output: &Path,
+    processes: &JobProcesses,
+) -> Result<(), GourceError> {
+    let layout = SheetLayout::new(section, count);
+    let filter = format!(
+        "fps=1/{},scale='min({},iw)':-2,tile={}x{}:padding={}",
+        layout.interval, SHEET_FRAME_WIDTH, layout.columns, layout.rows, SHEET_PADDING
+    );
+    extract(
+        Command::new("ffmpeg")
+            .args(["-y", "-v", "error", "-ss"])
+            .arg(layout.start.to_string())
+            .arg("-i")
+            .arg(video)
+            .args(["-vf", &filter, "-frames:v", "1"])
+            .arg(output),
+        processes,
+    )
+}
+
+/// The last frame of `section` as a PNG, at the size of the video.
+pub fn final_frame(
+    video: &Path,
+    section: Section,
+    output: &Path,
+    processes: &JobProcesses,
+) -> Result<(), GourceError> {
+    let end = (section.start + section.duration - FRAME_SECS).max(section.start);
+    extract(
+        Command::new("ffmpeg")
+            .args(["-y", "-v", "error", "-ss"])
+            .arg(end.to_string())
+            .arg("-i")
+            .arg(video)
+            .args(["-frames:v", "1"])
+            .arg(output),
+        processes,
+    )
+}
+
+fn extract(command: &mut Command, processes: &JobProcesses) -> Result<(), GourceError> {
+    let output = processes
+        .output(command)
+        .map_err(|_| GourceError::FrameExtractionFailed)?;
+    if !output.status.success() {
+        error!(
+            "Extracting frames failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(GourceError::FrameExtractionFailed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(start: f64, duration: f64, count: u32) -> SheetLayout {
+        SheetLayout::new(Section { start, duration }, count)
+    }
+
+    #[test]
+    fn tiles_frames_about_as_wide_as_tall() {
+        let grids: Vec<(u32, u32, u32)> = [2, 4, 5, 9, 10, 36]
+            .into_iter()
+            .map(|count| {
+                let layout = layout(0.0, 60.0, count);
+                (count, layout.columns, layout.rows)
+            })
+            .collect();
+        assert_eq!(
+            grids,
+            [
+                (2, 2, 1),
+                (4, 2, 2),
+                (5, 3, 2),
+                (9, 3, 3),
+                (10, 4, 3),
+                (36, 6, 6)
+            ]
+        );
+    }
+
+    #[test]
+    fn takes_frames_from_the_middle_of_each_share() {
+        assert_eq!(
+            layout(5.0, 60.0, 4),
+            SheetLayout {
+                columns: 2,
+                rows: 2,
+                interval: 15.0,
+                start: 12.5,
+            }
+        );
+
+        // Too short for one frame each: frames are still one frame apart.
+        let short = layout(0.0, 0.0, 9);
+        assert!((short.interval - FRAME_SECS).abs() < 1e-9);
+        assert!((short.start - FRAME_SECS / 2.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::webhook;
+use crypto::util::fixed_time_eq;
+use serde::Deserialize;
+
+/// `sha256=` followed by the hex HMAC-SHA256 of the body, keyed with the
+/// webhook secret configured on GitHub.
+pub const SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+pub const EVENT_HEADER: &str = "X-GitHub-Event";
+
+/// The parts of a `push` event needed to match it to a subscription.
+#[derive(Deserialize)]
+pub struct PushEvent {
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    #[serde(default)]
+    pub deleted: bool,
+    pub repository: PushRepository,
+}
+
+#[derive(Deserialize)]
+pub struct PushRepository {
+    pub full_name: String,
+}
+
+impl PushEvent {
+    /// The branch pushed to, or `None` for tags.
+    pub fn branch(&self) -> Option<&str> {
+        self.git_ref.strip_prefix("refs/heads/")
+    }
+
+    /// Whether the push leaves new commits on `branch`: not a tag, another
+    /// branch or a deletion.
+    pub fn updates(&self, branch: &str) -> bool {
+        !self.deleted && self.branch() == Some(branch)
+    }
+}
+
+/// Checks `signature` against the body in constant time.
+pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let expected = webhook::sign(secret, body);
+    fixed_time_eq(expected.as_bytes(), signature.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example from GitHub's documentation on validating deliveries.
+    const SECRET: &str = "It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn verifies_signatures() {
+        assert!(verify_signature(SECRET, BODY, SIGNATURE));
+        assert!(!verify_signature(SECRET, b"Hello, World?", SIGNATURE));
+        assert!(!verify_signature("another secret", BODY, SIGNATURE));
+        assert!(!verify_signature(SECRET, BODY, &SIGNATURE.to_uppercase()));
+        assert!(!verify_signature(SECRET, BODY, ""));
+    }
+
+    fn push(git_ref: &str, deleted: bool) -> PushEvent {
+        serde_json::from_value(serde_json::json!({
+            "ref": git_ref,
+            "deleted": deleted,
+            "repository": { "full_name": "Owner/Repo" },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn matches_pushes_to_the_branch() {
+        assert!(push("refs/heads/main", false).updates("main"));
+        assert!(push("refs/heads/release/1.0", false).updates("release/1.0"));
+        assert!(!push("refs/heads/main", true).updates("main"));
+        assert!(!push("refs/heads/develop", false).updates("main"));
+        assert!(!push("refs/heads/main-next", false).updates("main"));
+        assert!(!push("refs/tags/main", false).updates("main"));
+        assert_eq!(push("refs/tags/v1.0", false).branch(), None);
+    }
+}
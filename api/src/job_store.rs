@@ -0,0 +1,245 @@
+use crate::queue::QueuedJob;
+use crate::JobStatus;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+const JOBS_KEY: &str = "gitmotion:jobs";
+/// The request each job was started with, kept so it can be retried.
+const REQUESTS_KEY: &str = "gitmotion:requests";
+
+/// Attempts made by `update` before giving up when other processes keep
+/// modifying the same job concurrently.
+pub const MAX_UPDATE_ATTEMPTS: usize = 10;
+
+/// Replaces a hash field only if it still holds the value it was read with.
+pub static COMPARE_AND_SET: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        if redis.call('HGET', KEYS[1], ARGV[1]) == ARGV[2] then
+            redis.call('HSET', KEYS[1], ARGV[1], ARGV[3])
+            return 1
+        end
+        return 0
+        ",
+    )
+});
+
+/// Job statuses, kept in process memory or shared between processes in Redis.
+#[derive(Clone)]
+pub enum JobStore {
+    Memory(Arc<Mutex<MemoryJobs>>),
+    Redis(ConnectionManager),
+}
+
+#[derive(Default)]
+pub struct MemoryJobs {
+    statuses: HashMap<String, JobStatus>,
+    requests: HashMap<String, QueuedJob>,
+}
+
+impl JobStore {
+    pub fn memory() -> JobStore {
+        JobStore::Memory(Arc::default())
+    }
+
+    pub async fn insert(&self, job_id: &str, status: JobStatus) {
+        match self {
+            JobStore::Memory(jobs) => {
+                jobs.lock()
+                    .await
+                    .statuses
+                    .insert(job_id.to_string(), status);
+            }
+            JobStore::Redis(connection) => {
+                let Some(value) = encode(&status) else {
+                    return;
+                };
+                let mut connection = connection.clone();
+                if let Err(e) = connection
+                    .hset::<_, _, _, ()>(JOBS_KEY, job_id, value)
+                    .await
+                {
+                    error!("Failed to store job {}: {}", job_id, e);
+                }
+            }
+        }
+    }
+
+    pub async fn get(&self, job_id: &str) -> Option<JobStatus> {
+        match self {
+            JobStore::Memory(jobs) => jobs.lock().await.statuses.get(job_id).cloned(),
+            JobStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                match connection
+                    .hget::<_, _, Option<String>>(JOBS_KEY, job_id)
+                    .await
+                {
+                    Ok(value) => value.and_then(|value| decode(job_id, &value)),
+                    Err(e) => {
+                        error!("Failed to load job {}: {}", job_id, e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Applies `f` to the job's status and returns its result, or `None` when
+    /// the job does not exist. With Redis, `f` may run more than once if another
+    /// process modifies the job at the same time.
+    pub async fn update<R>(
+        &self,
+        job_id: &str,
+        mut f: impl FnMut(&mut JobStatus) -> R,
+    ) -> Option<R> {
+        match self {
+            JobStore::Memory(jobs) => jobs.lock().await.statuses.get_mut(job_id).map(f),
+            JobStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                for _ in 0..MAX_UPDATE_ATTEMPTS {
+                    let current: String = match connection.hget(JOBS_KEY, job_id).await {
+                        Ok(Some(value)) => value,
+                        Ok(None) => return None,
+                        Err(e) => {
+                            error!("Failed to load job {}: {}", job_id, e);
+                            return None;
+                        }
+                    };
+                    let mut status = decode(job_id, &current)?;
+                    let result = f(&mut status);
+                    let updated = encode(&status)?;
+
+                    let swapped: Result<i32, _> = COMPARE_AND_SET
+                        .key(JOBS_KEY)
+                        .arg(job_id)
+                        .arg(&current)
+                        .arg(&updated)
+                        .invoke_async(&mut connection)
+                        .await;
+                    match swapped {
+                        Ok(1) => return Some(result),
+                        Ok(_) => continue,
+                        Err(e) => {
+                            error!("Failed to update job {}: {}", job_id, e);
+                            return None;
+                        }
+                    }
+                }
+                warn!("Gave up updating job {} after repeated conflicts", job_id);
+                None
+            }
+        }
+    }
+
+    pub async fn remove(&self, job_id: &str) {
+        match self {
+            JobStore::Memory(jobs) => {
+                let mut jobs = jobs.lock().await;
+                jobs.statuses.remove(job_id);
+                jobs.requests.remove(job_id);
+            }
+            JobStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                if let Err(e) = redis::pipe()
+                    .hdel(JOBS_KEY, job_id)
+                    .hdel(REQUESTS_KEY, job_id)
+                    .exec_async(&mut connection)
+                    .await
+                {
+                    error!("Failed to remove job {}: {}", job_id, e);
+                }
+            }
+        }
+    }
+
+    pub async fn list(&self) -> Vec<(String, JobStatus)> {
+        match self {
+            JobStore::Memory(jobs) => jobs
+                .lock()
+                .await
+                .statuses
+                .iter()
+                .map(|(job_id, status)| (job_id.clone(), status.clone()))
+                .collect(),
+            JobStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                match connection
+                    .hgetall::<_, HashMap<String, String>>(JOBS_KEY)
+                    .await
+                {
+                    Ok(jobs) => jobs
+                        .into_iter()
+                        .filter_map(|(job_id, value)| {
+                            let status = decode(&job_id, &value)?;
+                            Some((job_id, status))
+                        })
+                        .collect(),
+                    Err(e) => {
+                        error!("Failed to list jobs: {}", e);
+                        Vec::new()
+                    }
+                }
+            }
+        }
+    }
+
+    pub async fn save_request(&self, job: &QueuedJob) {
+        match self {
+            JobStore::Memory(jobs) => {
+                jobs.lock()
+                    .await
+                    .requests
+                    .insert(job.job_id.clone(), job.clone());
+            }
+            JobStore::Redis(connection) => {
+                let value = serde_json::to_string(job).expect("queued job serializes");
+                let mut connection = connection.clone();
+                if let Err(e) = connection
+                    .hset::<_, _, _, ()>(REQUESTS_KEY, &job.job_id, value)
+                    .await
+                {
+                    error!("Failed to store request for job {}: {}", job.job_id, e);
+                }
+            }
+        }
+    }
+
+    pub async fn request(&self, job_id: &str) -> Option<QueuedJob> {
+        match self {
+            JobStore::Memory(jobs) => jobs.lock().await.requests.get(job_id).cloned(),
+            JobStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                match connection
+                    .hget::<_, _, Option<String>>(REQUESTS_KEY, job_id)
+                    .await
+                {
+                    Ok(value) => value.and_then(|value| {
+                        serde_json::from_str(&value)
+                            .map_err(|e| error!("Failed to deserialize request {}: {}", job_id, e))
+                            .ok()
+                    }),
+                    Err(e) => {
+                        error!("Failed to load request for job {}: {}", job_id, e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn encode(status: &JobStatus) -> Option<String> {
+    serde_json::to_string(status)
+        .map_err(|e| error!("Failed to serialize job status: {}", e))
+        .ok()
+}
+
+fn decode(job_id: &str, value: &str) -> Option<JobStatus> {
+    serde_json::from_str(value)
+        .map_err(|e| error!("Failed to deserialize job {}: {}", job_id, e))
+        .ok()
+}
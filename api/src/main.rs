@@ -1,36 +1,95 @@
 use actix_cors::Cors;
 use actix_files::NamedFile;
+use actix_multipart::Multipart;
+use actix_web::http::header::{self, ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
 use actix_web::Result;
 use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
-use chrono::NaiveDate;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use clap::Parser;
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
 use crypto::symmetriccipher::Decryptor;
 use crypto::{aes, buffer};
 use dotenv::dotenv;
-use env_logger::Builder;
-use hex;
-use log::{error, info, LevelFilter};
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::env;
 use std::fs::{self};
-use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
-use std::sync::Arc;
+use std::process::Command;
 use std::time::{Duration, Instant, SystemTime};
 use thiserror::Error;
-use tokio::sync::Mutex;
 use tokio::time::interval;
+use tokio_util::io::ReaderStream;
+use tracing::{error, info, info_span, instrument, warn, Instrument, Span};
 use url::Url;
 use uuid::Uuid;
 
+mod branding;
+mod captions;
+mod combine;
+mod config;
+mod custom_log;
+mod frames;
+mod github;
+mod job_store;
+mod metrics;
+mod overlay;
+mod pacing;
+mod preview;
+mod process;
+mod queue;
+mod schedules;
+mod shutdown;
+mod storage;
+mod subscriptions;
+mod telemetry;
+mod upload;
+mod vcs;
+mod webhook;
+
+use branding::{shell_quote, Card, Composition, VIDEO_SIZE};
+use captions::CaptionSettings;
+use combine::Checkout;
+use config::{Cli, Config};
+use job_store::JobStore;
+use pacing::{Pacing, TargetDuration};
+use preview::PreviewMode;
+use process::JobProcesses;
+use queue::{JobQueue, QueuedJob, RepositorySource};
+use schedules::{Schedule, ScheduleStore};
+use shutdown::Shutdown;
+use storage::{ArtifactStore, Image};
+use subscriptions::{subscription_key, Subscription, SubscriptionStore};
+use upload::UploadKind;
+use vcs::{DateRange, HistoryStats, Tag, VcsKind};
+use webhook::{Callback, WebhookDelivery, WebhookSender};
+
 #[derive(Deserialize)]
 struct GourceRequest {
-    repo_url: String,
+    repo_url: Option<String>,
+    /// Renders several repositories together in one video, instead of
+    /// `repo_url`.
+    repo_urls: Option<Vec<String>>,
+    /// Version control system of the repositories, when their URLs do not
+    /// say. Defaults to git.
+    vcs: Option<VcsKind>,
     access_token: Option<String>,
     settings: Option<GourceSettings>,
+    /// Receives a signed POST when the job completes, fails or is cancelled.
+    callback_url: Option<String>,
+    callback_secret: Option<String>,
+}
+
+/// Renders a repository already on the server, for administrators.
+#[derive(Deserialize)]
+struct LocalRepositoryRequest {
+    path: PathBuf,
+    settings: Option<GourceSettings>,
+    callback_url: Option<String>,
+    callback_secret: Option<String>,
 }
 
 #[derive(Serialize, Clone)]
@@ -38,20 +97,247 @@ struct GourceResponse {
     job_id: String,
 }
 
-#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[derive(Deserialize)]
+struct SubscriptionRequest {
+    repo_url: String,
+    /// Pushes to other branches are ignored.
+    branch: Option<String>,
+    access_token: Option<String>,
+    settings: Option<GourceSettings>,
+}
+
+#[derive(Deserialize)]
+struct ScheduleRequest {
+    repo_url: String,
+    cron: String,
+    /// Render only the commits from this many days before each run.
+    window_days: Option<u32>,
+    access_token: Option<String>,
+    settings: Option<GourceSettings>,
+}
+
+#[derive(Serialize)]
+struct ScheduleResponse {
+    id: String,
+    repo_url: String,
+    cron: String,
+    window_days: Option<u32>,
+    settings: GourceSettings,
+    created_at: DateTime<Utc>,
+    next_run_at: DateTime<Utc>,
+    last_run_at: Option<DateTime<Utc>>,
+    last_job_id: Option<String>,
+}
+
+impl From<Schedule> for ScheduleResponse {
+    fn from(schedule: Schedule) -> Self {
+        ScheduleResponse {
+            id: schedule.id,
+            repo_url: schedule.repo_url,
+            cron: schedule.cron,
+            window_days: schedule.window_days,
+            settings: schedule.settings,
+            created_at: schedule.created_at,
+            next_run_at: schedule.next_run_at,
+            last_run_at: schedule.last_run_at,
+            last_job_id: schedule.last_job_id,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SubscriptionResponse {
+    repo: String,
+    repo_url: String,
+    branch: String,
+    settings: GourceSettings,
+    created_at: DateTime<Utc>,
+    latest_job_id: Option<String>,
+    latest_url: String,
+    /// The render started when the subscription was created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queued_job_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
 enum ProgressStep {
     InitializingProject = 1,
     AnalyzingHistory = 2,
     GeneratingVisualization = 3,
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+enum JobState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+    Interrupted,
+}
+
+impl JobState {
+    fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            JobState::Completed | JobState::Failed | JobState::Cancelled | JobState::Interrupted
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum JobPhase {
+    Clone,
+    Analyze,
+    Render,
+}
+
+impl JobPhase {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobPhase::Clone => "clone",
+            JobPhase::Analyze => "analyze",
+            JobPhase::Render => "render",
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+struct JobTimings {
+    clone_ms: Option<u64>,
+    analyze_ms: Option<u64>,
+    render_ms: Option<u64>,
+    total_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct RepoStats {
+    total_commits: i32,
+    days_with_commits: i32,
+    seconds_per_day: f64,
+    #[serde(default)]
+    auto_skip_seconds: Option<f64>,
+    /// How long the pacing was expected to make the video.
+    #[serde(default)]
+    estimated_duration_seconds: Option<f64>,
+    hide_filenames: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct VideoInfo {
+    size_bytes: u64,
+    duration_seconds: Option<f64>,
+    /// The requested `target_duration_seconds`, if any.
+    #[serde(default)]
+    target_duration_seconds: Option<f64>,
+    /// How much the render was sped up to fit the target; absent when it
+    /// was not retimed.
+    #[serde(default)]
+    playback_speed: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 struct JobStatus {
     step: ProgressStep,
+    state: JobState,
     video_url: Option<String>,
+    /// Image rendered by a stills preview.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stills_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    poster_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    contact_sheet_url: Option<String>,
     repo_url: String,
+    /// Every repository of a combined render; `repo_url` is the first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    repo_urls: Vec<String>,
     error: Option<String>,
     settings: GourceSettings,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    timings: JobTimings,
+    stats: Option<RepoStats>,
+    video: Option<VideoInfo>,
+    expires_at: Option<DateTime<Utc>>,
+    pinned: bool,
+    #[serde(default)]
+    retry_count: u32,
+    next_retry_at: Option<DateTime<Utc>>,
+    callback_url: Option<String>,
+    #[serde(default)]
+    webhook_deliveries: Vec<WebhookDelivery>,
+}
+
+impl JobStatus {
+    fn image_url_mut(&mut self, image: Image) -> &mut Option<String> {
+        match image {
+            Image::Stills => &mut self.stills_url,
+            Image::Poster => &mut self.poster_url,
+            Image::ContactSheet => &mut self.contact_sheet_url,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ListJobsQuery {
+    state: Option<JobState>,
+    repo_url: Option<String>,
+    owner: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct JobSummary {
+    job_id: String,
+    state: JobState,
+    step: ProgressStep,
+    repo_url: String,
+    owner: Option<String>,
+    error: Option<String>,
+    video_url: Option<String>,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+    pinned: bool,
+    retry_count: u32,
+    timings: JobTimings,
+}
+
+#[derive(Serialize)]
+struct JobListResponse {
+    jobs: Vec<JobSummary>,
+    page: usize,
+    per_page: usize,
+    total: usize,
+}
+
+#[derive(Deserialize)]
+struct RetentionRequest {
+    extend_secs: Option<u64>,
+    pinned: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ArtifactQuery {
+    /// `1` or `true` to save the artifact rather than show it.
+    download: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct PromoteRequest {
+    /// Replaces the settings the preview was rendered with.
+    settings: Option<GourceSettings>,
+}
+
+#[derive(Serialize)]
+struct RetentionResponse {
+    job_id: String,
+    expires_at: Option<DateTime<Utc>>,
+    pinned: bool,
 }
 
 #[derive(Default, Serialize, Deserialize, Clone, Debug)]
@@ -62,6 +348,48 @@ struct GourceSettings {
     dir_font_size: u32,
     file_font_size: u32,
     user_font_size: u32,
+    /// Only commits made on or after this day are rendered.
+    start_date: Option<NaiveDate>,
+    /// Only commits made on or before this day are rendered.
+    stop_date: Option<NaiveDate>,
+    /// Colours files by the repository they belong to in a combined render,
+    /// rather than by extension.
+    #[serde(default)]
+    color_by_repository: bool,
+    /// How long the video should run, in seconds or as `short`, `medium` or
+    /// `long`. Defaults to a length based on how many days have commits.
+    duration: Option<TargetDuration>,
+    /// Exact length of the finished video, in seconds. The render is retimed
+    /// to it after gource has finished.
+    target_duration_seconds: Option<f64>,
+    /// Opens the video with a card showing the repository, the dates covered
+    /// and the number of commits and contributors.
+    #[serde(default)]
+    intro_card: bool,
+    /// Ends the video with a card showing the repository and title suffix.
+    #[serde(default)]
+    outro_card: bool,
+    /// Draws the running commit count, the top contributors and a caption at
+    /// each tag over the video.
+    #[serde(default)]
+    stats_overlay: bool,
+    /// How many contributors the overlay lists.
+    top_contributors: Option<usize>,
+    /// Captions from tags and the events given here, drawn by gource.
+    captions: Option<CaptionSettings>,
+    /// Saves the final frame of the visualization as a PNG, at the size of
+    /// the video.
+    #[serde(default)]
+    poster: bool,
+    /// Saves a PNG of frames taken evenly across the visualization.
+    #[serde(default)]
+    contact_sheet: bool,
+    /// How many frames the contact sheet shows.
+    contact_sheet_frames: Option<u32>,
+    /// Renders a quick preview instead of the video: a short, small clip or
+    /// an image of stills. Cards, the watermark, `target_duration_seconds`,
+    /// the poster and the contact sheet are left for the full render.
+    preview: Option<PreviewMode>,
 }
 
 #[derive(Error, Debug)]
@@ -76,15 +404,65 @@ enum GourceError {
         "Failed to clone repository. Your repository may be private and requires an access token."
     )]
     CloneFailed,
+    #[error("Failed to clone repository because of a network error")]
+    CloneNetworkError,
+    #[error("The uploaded repository could not be read")]
+    InvalidUpload,
+    #[error("The repository path is not allowed on this server")]
+    LocalPathNotAllowed,
     #[error("Failed to count commits")]
     CommitCountFailed,
+    #[error("Failed to combine the histories of the repositories")]
+    CombinedLogFailed,
+    #[error("The repository has no commits in the selected date range")]
+    NoCommitsInRange,
     #[error("Failed to generate Gource visualization")]
     GourceGenerationFailed,
+    #[error("Gource visualization was interrupted before it finished")]
+    RenderKilled,
     #[error("Failed to decrypt access token")]
     DecryptionFailed,
+    #[error("Private repositories are not enabled on this server")]
+    AccessTokensDisabled,
+    #[error("Failed to store the rendered video")]
+    StorageFailed,
+    #[error("Failed to fit the video to the requested duration")]
+    VideoFitFailed,
+    #[error("Failed to extract still images from the video")]
+    FrameExtractionFailed,
 }
 
-type JobStore = Arc<Mutex<HashMap<String, JobStatus>>>;
+impl GourceError {
+    fn kind(&self) -> &'static str {
+        match self {
+            GourceError::InvalidUrl => "invalid_url",
+            GourceError::UnsupportedRepository => "unsupported_repository",
+            GourceError::TempDirCreationFailed => "temp_dir_creation_failed",
+            GourceError::CloneFailed => "clone_failed",
+            GourceError::CloneNetworkError => "clone_network_error",
+            GourceError::InvalidUpload => "invalid_upload",
+            GourceError::LocalPathNotAllowed => "local_path_not_allowed",
+            GourceError::CommitCountFailed => "commit_count_failed",
+            GourceError::CombinedLogFailed => "combined_log_failed",
+            GourceError::NoCommitsInRange => "no_commits_in_range",
+            GourceError::GourceGenerationFailed => "gource_generation_failed",
+            GourceError::RenderKilled => "render_killed",
+            GourceError::DecryptionFailed => "decryption_failed",
+            GourceError::AccessTokensDisabled => "access_tokens_disabled",
+            GourceError::StorageFailed => "storage_failed",
+            GourceError::VideoFitFailed => "video_fit_failed",
+            GourceError::FrameExtractionFailed => "frame_extraction_failed",
+        }
+    }
+
+    /// Whether the same request may succeed if it is run again.
+    fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            GourceError::CloneNetworkError | GourceError::RenderKilled | GourceError::StorageFailed
+        )
+    }
+}
 
 fn derive_key(secret_key: &str) -> Vec<u8> {
     let mut hasher = Sha256::new();
@@ -117,511 +495,2202 @@ fn decrypt_token(encrypted_token: &str, secret_key: &str) -> Result<String, Gour
     String::from_utf8(buffer).map_err(|_| GourceError::DecryptionFailed)
 }
 
-fn log_message(level: log::Level, message: &str, job_id: Option<&str>) {
-    let target = job_id.map_or("gitmotion_api".to_string(), |id| format!("job-{}", id));
-    match level {
-        log::Level::Error => log::error!(target: &target, "{}", message),
-        log::Level::Warn => log::warn!(target: &target, "{}", message),
-        log::Level::Info => log::info!(target: &target, "{}", message),
-        log::Level::Debug => log::debug!(target: &target, "{}", message),
-        log::Level::Trace => log::trace!(target: &target, "{}", message),
-    }
-}
-
 async fn start_gource(
     repo_request: web::Json<GourceRequest>,
     job_store: web::Data<JobStore>,
+    queue: web::Data<JobQueue>,
+    config: web::Data<Config>,
 ) -> impl Responder {
     let job_id = Uuid::new_v4().to_string();
-    log_message(
-        log::Level::Info,
-        &format!("Starting new job with ID: {}", job_id),
-        None,
-    );
+    info!("Starting new job with ID: {}", job_id);
 
-    let repo_url = repo_request.repo_url.clone();
+    let repo_urls = match requested_repositories(&repo_request) {
+        Ok(repo_urls) => repo_urls,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({ "error": message }));
+        }
+    };
+    let repo_url = repo_urls[0].clone();
+    // Single-repository jobs keep their original shape.
+    let repo_urls = if repo_urls.len() > 1 {
+        repo_urls
+    } else {
+        Vec::new()
+    };
     let access_token = repo_request.access_token.clone();
     let settings = repo_request.settings.clone().unwrap_or_default();
-
-    {
-        let mut store = job_store.lock().await;
-        store.insert(
-            job_id.clone(),
-            JobStatus {
-                step: ProgressStep::InitializingProject,
-                video_url: None,
-                repo_url: repo_url.clone(),
-                error: None,
-                settings: settings.clone(),
-            },
-        );
+    if let Err(message) = validate_settings(&settings) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": message }));
     }
-
-    let job_store_clone = job_store.clone();
-    let job_id_clone = job_id.clone();
-
-    tokio::spawn(async move {
-        if let Err(e) = process_gource(
-            repo_url,
-            access_token,
-            Some(settings),
-            job_id_clone.clone(),
-            job_store_clone.clone(),
-        )
-        .await
-        {
-            log_message(
-                log::Level::Error,
-                &format!("Job failed: {}", e),
-                Some(&job_id_clone),
-            );
-            let mut store = job_store_clone.lock().await;
-            if let Some(status) = store.get_mut(&job_id_clone) {
-                status.step = ProgressStep::GeneratingVisualization;
-                status.error = Some(e.to_string());
-            }
+    let callback = match parse_callback(
+        &repo_request.callback_url,
+        &repo_request.callback_secret,
+        &config,
+    ) {
+        Ok(callback) => callback,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({ "error": message }));
         }
-    });
+    };
+
+    let mut status = queued_status(&repo_url, &settings, callback.as_ref());
+    status.repo_urls = repo_urls.clone();
+    let job = QueuedJob {
+        job_id: job_id.clone(),
+        repo_url,
+        repo_urls,
+        access_token,
+        settings,
+        callback,
+        subscription: None,
+        source: RepositorySource::Clone,
+        vcs: repo_request.vcs,
+    };
+    if submit_job(&job_store, &queue, job, status).await.is_err() {
+        return queue_unavailable();
+    }
 
     HttpResponse::Ok().json(GourceResponse { job_id })
 }
 
-async fn process_gource(
-    repo_url: String,
-    access_token: Option<String>,
+/// Form fields of `/start-gource/upload` other than the file itself.
+#[derive(Default)]
+struct UploadForm {
+    file_name: Option<String>,
     settings: Option<GourceSettings>,
-    job_id: String,
-    job_store: web::Data<JobStore>,
-) -> Result<(), GourceError> {
-    let job_id_clone = job_id.clone();
-    log_message(
-        log::Level::Info,
-        "Starting process_gource",
-        Some(&job_id_clone),
-    );
-    let start_time = Instant::now();
+    callback_url: Option<String>,
+    callback_secret: Option<String>,
+}
 
-    update_job_status(&job_store, &job_id, ProgressStep::InitializingProject).await;
-    log_message(
-        log::Level::Info,
-        "Updated job status to InitializingProject",
-        Some(&job_id_clone),
-    );
+/// Largest text field accepted alongside an upload.
+const MAX_FORM_FIELD_BYTES: usize = 64 * 1024;
 
-    let url = Url::parse(&repo_url).map_err(|_| GourceError::InvalidUrl)?;
-    let host = url.host_str().unwrap_or("");
-    if !host.ends_with("github.com") {
-        if access_token.is_some() {
-            return Err(GourceError::UnsupportedRepository);
-        }
+/// Starts a job for a repository sent as multipart form data: a `file` part
+/// holding a git bundle or a tarball of a `.git` directory, plus optional
+/// `settings` (JSON), `callback_url` and `callback_secret` parts.
+async fn start_gource_upload(
+    mut payload: Multipart,
+    job_store: web::Data<JobStore>,
+    queue: web::Data<JobQueue>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn ArtifactStore>,
+) -> impl Responder {
+    let job_id = Uuid::new_v4().to_string();
+    let upload_key = storage::upload_key(&job_id);
+    let staging_path = storage.staging_path(&upload_key);
+
+    let (file_name, settings, callback, kind) =
+        match receive_upload(&mut payload, &staging_path, &config).await {
+            Ok(upload) => upload,
+            Err(response) => {
+                let _ = tokio::fs::remove_file(&staging_path).await;
+                return response;
+            }
+        };
+    if let Err(e) = storage
+        .put(&upload_key, &staging_path, "application/octet-stream")
+        .await
+    {
+        error!("Failed to store upload for job {}: {}", job_id, e);
+        let _ = tokio::fs::remove_file(&staging_path).await;
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": "Failed to store the upload"
+        }));
     }
-    log_message(
-        log::Level::Info,
-        &format!("Validated repository URL: {}", repo_url),
-        Some(&job_id_clone),
-    );
-
-    let temp_dir = tempfile::TempDir::new().map_err(|_| GourceError::TempDirCreationFailed)?;
-    log_message(
-        log::Level::Info,
-        "Created temporary directory",
-        Some(&job_id_clone),
+    info!(
+        "Starting new job with ID: {} for an uploaded {:?}",
+        job_id, kind
     );
 
-    let clone_start = Instant::now();
-    log_message(
-        log::Level::Info,
-        "Attempting to decrypt token",
-        Some(&job_id_clone),
-    );
-    let decrypted_token = if let Some(encrypted_token) = access_token {
-        log_message(
-            log::Level::Info,
-            "Access token provided, attempting decryption",
-            Some(&job_id_clone),
-        );
-        let secret_key = dotenv::var("SECRET_KEY")
-            .or_else(|_| std::env::var("SECRET_KEY"))
-            .expect("SECRET_KEY must be set in .env file or environment");
-        log_message(
-            log::Level::Info,
-            &format!("SECRET_KEY found, length: {}", secret_key.len()),
-            Some(&job_id_clone),
-        );
-        match decrypt_token(&encrypted_token, &secret_key) {
-            Ok(token) => {
-                log_message(
-                    log::Level::Info,
-                    "Token decrypted successfully",
-                    Some(&job_id_clone),
-                );
-                Some(token)
-            }
-            Err(e) => {
-                log_message(
-                    log::Level::Error,
-                    &format!("Failed to decrypt token: {:?}", e),
-                    Some(&job_id_clone),
-                );
-                return Err(e);
-            }
-        }
-    } else {
-        None
+    let repo_url = format!("upload:{}", file_name);
+    let status = queued_status(&repo_url, &settings, callback.as_ref());
+    let job = QueuedJob {
+        job_id: job_id.clone(),
+        repo_url,
+        repo_urls: Vec::new(),
+        access_token: None,
+        settings,
+        callback,
+        subscription: None,
+        source: RepositorySource::Upload { kind },
+        vcs: None,
     };
-
-    log_message(
-        log::Level::Info,
-        "Attempting to clone repository",
-        Some(&job_id_clone),
-    );
-
-    // Offload the blocking clone operation to a separate thread
-    let temp_dir_path = temp_dir.path().to_path_buf();
-    let repo_url_clone = repo_url.clone();
-    let clone_result = tokio::task::spawn_blocking(move || {
-        clone_repository(&repo_url_clone, &temp_dir_path, decrypted_token.as_deref())
-    })
-    .await
-    .map_err(|_| GourceError::CloneFailed)??;
-
-    let clone_duration = clone_start.elapsed();
-    log_message(
-        log::Level::Info,
-        &format!("Repository cloning took {:?}", clone_duration),
-        Some(&job_id_clone),
-    );
-
-    update_job_status(&job_store, &job_id, ProgressStep::AnalyzingHistory).await;
-    let count_start = Instant::now();
-    let (days_with_commits, total_commits) =
-        count_days_and_commits(temp_dir.path(), Some(&job_id_clone))?;
-    let count_duration = count_start.elapsed();
-    log_message(
-        log::Level::Info,
-        &format!("Counting days with commits took {:?}", count_duration),
-        Some(&job_id_clone),
-    );
-
-    let seconds_per_day = calculate_seconds_per_day(days_with_commits, Some(&job_id_clone));
-    let hide_filenames = total_commits > 500;
-
-    update_job_status(&job_store, &job_id, ProgressStep::GeneratingVisualization).await;
-    let output_file = PathBuf::from(format!("/gource_videos/gource_{}.mp4", job_id));
-    let gource_start = Instant::now();
-
-    // Use tokio::task::spawn_blocking for CPU-intensive tasks
-    let job_id_for_closure = job_id_clone.clone();
-    let repo_url_for_closure = repo_url.clone();
-    let _gource_result = tokio::task::spawn_blocking(move || {
-        let result = generate_gource_visualization(
-            temp_dir.path(),
-            seconds_per_day,
-            hide_filenames,
-            &output_file,
-            &settings,
-            Some(&job_id_for_closure),
-            Some(&repo_url_for_closure),
-        );
-
-        // Explicitly close the temporary directory
-        if let Err(e) = temp_dir.close() {
-            log_message(
-                log::Level::Error,
-                &format!("Failed to remove temporary directory: {:?}", e),
-                Some(&job_id_for_closure),
-            );
-        } else {
-            log_message(
-                log::Level::Info,
-                "Temporary directory removed successfully",
-                Some(&job_id_for_closure),
-            );
+    if submit_job(&job_store, &queue, job, status).await.is_err() {
+        if let Err(e) = storage.delete(&upload_key).await {
+            error!("Failed to remove upload for job {}: {}", job_id, e);
         }
+        return queue_unavailable();
+    }
 
-        result
-    })
-    .await
-    .map_err(|_| GourceError::GourceGenerationFailed)??;
-
-    let gource_duration = gource_start.elapsed();
-    log_message(
-        log::Level::Info,
-        &format!("Gource visualization generation took {:?}", gource_duration),
-        Some(&job_id_clone),
-    );
-
-    let total_duration = start_time.elapsed();
-    log_message(
-        log::Level::Info,
-        &format!("Total process took {:?}", total_duration),
-        Some(&job_id_clone),
-    );
-
-    update_job_status(&job_store, &job_id, ProgressStep::GeneratingVisualization).await;
-    set_video_url(
-        &job_store,
-        &job_id,
-        &format!("/gource_videos/gource_{}.mp4", job_id),
-    )
-    .await;
+    HttpResponse::Ok().json(GourceResponse { job_id })
+}
 
-    Ok(())
+/// Reads the upload form into `destination` and validates it, returning the
+/// file name, settings, callback and kind of repository uploaded.
+async fn receive_upload(
+    payload: &mut Multipart,
+    destination: &Path,
+    config: &Config,
+) -> Result<(String, GourceSettings, Option<Callback>, UploadKind), HttpResponse> {
+    let max_bytes = config.uploads.max_bytes;
+    let bad_request =
+        |message: String| HttpResponse::BadRequest().json(serde_json::json!({ "error": message }));
+    let form = read_upload_form(payload, destination, max_bytes).await?;
+    let file_name = form
+        .file_name
+        .ok_or_else(|| bad_request("file is required".to_string()))?;
+    let settings = form.settings.unwrap_or_default();
+    validate_settings(&settings).map_err(bad_request)?;
+    let callback = parse_callback(&form.callback_url, &form.callback_secret, config)
+        .map_err(|message| bad_request(message.to_string()))?;
+
+    let path = destination.to_path_buf();
+    let kind = tokio::task::spawn_blocking(move || upload::inspect(&path, max_bytes))
+        .await
+        .map_err(|_| bad_request("Failed to read the upload".to_string()))?
+        .map_err(|e| bad_request(e.to_string()))?;
+    Ok((file_name, settings, callback, kind))
 }
 
-async fn stop_job(job_id: web::Path<String>, job_store: web::Data<JobStore>) -> impl Responder {
-    let mut store = job_store.lock().await;
-    let response = match store.get_mut(job_id.as_str()) {
-        Some(status) => {
-            if status.video_url.is_none() && status.error.is_none() {
-                status.error = Some("Job stopped by user".to_string());
-
-                log_message(
-                    log::Level::Info,
-                    &format!("Job {} stopped by user", job_id),
-                    Some(job_id.as_str()),
-                );
-                serde_json::json!({
-                    "message": "Job stopped successfully and temporary files cleaned up",
-                    "status": "stopped"
-                })
-            } else {
-                log_message(
-                    log::Level::Info,
-                    &format!("Cannot stop job {}: already completed or errored", job_id),
-                    Some(job_id.as_str()),
-                );
-                serde_json::json!({
-                    "error": "Cannot stop job: already completed or errored",
-                    "status": "unchanged"
-                })
+/// Streams the `file` part to `destination` and collects the other fields.
+async fn read_upload_form(
+    payload: &mut Multipart,
+    destination: &Path,
+    max_bytes: u64,
+) -> Result<UploadForm, HttpResponse> {
+    let bad_request =
+        |message: String| HttpResponse::BadRequest().json(serde_json::json!({ "error": message }));
+    let mut form = UploadForm::default();
+
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|e| bad_request(e.to_string()))?;
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "file" {
+            if form.file_name.is_some() {
+                return Err(bad_request("Only one file can be uploaded".to_string()));
             }
+            let file_name = field
+                .content_disposition()
+                .and_then(|disposition| disposition.get_filename())
+                .and_then(|file_name| Path::new(file_name).file_name())
+                .map(|file_name| file_name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "repository".to_string());
+
+            let mut file = tokio::fs::File::create(destination).await.map_err(|e| {
+                error!("Failed to create upload file: {}", e);
+                HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": "Failed to store the upload"
+                }))
+            })?;
+            let mut written: u64 = 0;
+            while let Some(chunk) = field.next().await {
+                let chunk = chunk.map_err(|e| bad_request(e.to_string()))?;
+                written += chunk.len() as u64;
+                if written > max_bytes {
+                    return Err(HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                        "error": format!("Uploads are limited to {} bytes", max_bytes)
+                    })));
+                }
+                tokio::io::AsyncWriteExt::write_all(&mut file, &chunk)
+                    .await
+                    .map_err(|e| {
+                        error!("Failed to write upload: {}", e);
+                        HttpResponse::InternalServerError().json(serde_json::json!({
+                            "error": "Failed to store the upload"
+                        }))
+                    })?;
+            }
+            form.file_name = Some(file_name);
+            continue;
         }
-        None => {
-            log_message(
-                log::Level::Info,
-                &format!("Job not found: {}", job_id),
-                Some(job_id.as_str()),
-            );
-            serde_json::json!({
-                "error": "Job not found",
-                "status": "not_found"
-            })
+
+        let mut value = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|e| bad_request(e.to_string()))?;
+            if value.len() + chunk.len() > MAX_FORM_FIELD_BYTES {
+                return Err(bad_request(format!("{} is too long", name)));
+            }
+            value.extend_from_slice(&chunk);
         }
-    };
+        let value = String::from_utf8(value)
+            .map_err(|_| bad_request(format!("{} must be UTF-8 text", name)))?;
+        match name.as_str() {
+            "settings" => {
+                let settings = serde_json::from_str(&value)
+                    .map_err(|e| bad_request(format!("Invalid settings: {}", e)))?;
+                form.settings = Some(settings);
+            }
+            "callback_url" => form.callback_url = Some(value),
+            "callback_secret" => form.callback_secret = Some(value),
+            _ => return Err(bad_request(format!("Unexpected form field {:?}", name))),
+        }
+    }
 
-    HttpResponse::Ok().json(response)
+    Ok(form)
 }
 
-async fn get_job_status(
-    job_id: web::Path<String>,
+/// Starts a job for a repository on the server itself. Requires the admin
+/// token, and the path must lie inside `local_repos.allowed_paths`.
+async fn start_gource_local(
+    req: HttpRequest,
+    request: web::Json<LocalRepositoryRequest>,
     job_store: web::Data<JobStore>,
+    queue: web::Data<JobQueue>,
+    config: web::Data<Config>,
 ) -> impl Responder {
-    let store = job_store.lock().await;
-    match store.get(job_id.as_str()) {
-        Some(status) => {
-            info!(
-                "Returning job status for {}: step={:?}, video_url={:?}",
-                job_id, status.step, status.video_url
-            );
-            HttpResponse::Ok().json(status.clone())
-        }
-        None => {
-            info!("Job not found: {}", job_id);
-            HttpResponse::NotFound().json(serde_json::json!({
-                "error": "Job not found"
-            }))
-        }
+    if let Some(response) = reject_unless_admin(&req, &config) {
+        return response;
     }
-}
+    let request = request.into_inner();
+    let Some(path) = upload::allowed_local_path(&request.path, &config.local_repos.allowed_paths)
+    else {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "The repository path is not allowed on this server"
+        }));
+    };
+    let settings = request.settings.unwrap_or_default();
+    if let Err(message) = validate_settings(&settings) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": message }));
+    }
+    let callback = match parse_callback(&request.callback_url, &request.callback_secret, &config) {
+        Ok(callback) => callback,
+        Err(message) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({ "error": message }));
+        }
+    };
 
-async fn update_job_status(job_store: &JobStore, job_id: &str, step: ProgressStep) {
-    let mut store = job_store.lock().await;
-    if let Some(job_status) = store.get_mut(job_id) {
-        job_status.step = step;
+    let job_id = Uuid::new_v4().to_string();
+    info!("Starting new job with ID: {} for {:?}", job_id, path);
+    let repo_url = format!("file://{}", path.display());
+    let status = queued_status(&repo_url, &settings, callback.as_ref());
+    let job = QueuedJob {
+        job_id: job_id.clone(),
+        repo_url,
+        repo_urls: Vec::new(),
+        access_token: None,
+        settings,
+        callback,
+        subscription: None,
+        source: RepositorySource::LocalPath { path },
+        vcs: None,
+    };
+    if submit_job(&job_store, &queue, job, status).await.is_err() {
+        return queue_unavailable();
     }
+
+    HttpResponse::Ok().json(GourceResponse { job_id })
 }
 
-async fn set_video_url(job_store: &JobStore, job_id: &str, video_url: &str) {
-    let mut store = job_store.lock().await;
-    if let Some(job_status) = store.get_mut(job_id) {
-        job_status.video_url = Some(video_url.to_string());
+/// Admin endpoints do not exist unless an admin token is configured, and
+/// otherwise need it as a bearer token. Returns the response refusing the
+/// request, if it is not allowed.
+fn reject_unless_admin(req: &HttpRequest, config: &Config) -> Option<HttpResponse> {
+    let Some(admin_token) = config.admin_token.as_deref() else {
+        return Some(HttpResponse::NotFound().finish());
+    };
+    let provided = req
+        .headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if crypto::util::fixed_time_eq(provided.as_bytes(), admin_token.as_bytes()) {
+        None
+    } else {
+        Some(HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "A valid admin token is required"
+        })))
     }
 }
 
-fn clone_repository(
+fn queued_status(
     repo_url: &str,
-    temp_dir: &Path,
-    github_token: Option<&str>,
-) -> Result<(), GourceError> {
-    log_message(
-        log::Level::Info,
-        &format!("Cloning repository: {}", repo_url),
-        None,
-    );
-
-    let mut url = Url::parse(repo_url).map_err(|_| GourceError::InvalidUrl)?;
+    settings: &GourceSettings,
+    callback: Option<&Callback>,
+) -> JobStatus {
+    JobStatus {
+        step: ProgressStep::InitializingProject,
+        state: JobState::Queued,
+        video_url: None,
+        stills_url: None,
+        poster_url: None,
+        contact_sheet_url: None,
+        repo_url: repo_url.to_string(),
+        repo_urls: Vec::new(),
+        error: None,
+        settings: settings.clone(),
+        created_at: Utc::now(),
+        finished_at: None,
+        timings: JobTimings::default(),
+        stats: None,
+        video: None,
+        expires_at: None,
+        pinned: false,
+        retry_count: 0,
+        next_retry_at: None,
+        callback_url: callback.map(|callback| callback.url.clone()),
+        webhook_deliveries: Vec::new(),
+    }
+}
 
-    if let Some(token) = github_token {
-        url.set_username("oauth2")
-            .map_err(|_| GourceError::InvalidUrl)?;
-        url.set_password(Some(token))
-            .map_err(|_| GourceError::InvalidUrl)?;
+/// Records a new job and puts it on the queue. The job is forgotten again if
+/// it cannot be queued.
+async fn submit_job(
+    job_store: &JobStore,
+    queue: &JobQueue,
+    job: QueuedJob,
+    status: JobStatus,
+) -> redis::RedisResult<()> {
+    let job_id = job.job_id.clone();
+    job_store.insert(&job_id, status).await;
+    job_store.save_request(&job).await;
+    if let Err(e) = queue.enqueue(job).await {
+        error!("Failed to enqueue job {}: {}", job_id, e);
+        job_store.remove(&job_id).await;
+        return Err(e);
     }
+    metrics::JOBS_STARTED.inc();
+    Ok(())
+}
 
-    let child = Command::new("git")
-        .arg("clone")
-        .arg(url.as_str())
-        .arg(temp_dir)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|_| GourceError::CloneFailed)?;
+fn queue_unavailable() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(serde_json::json!({
+        "error": "Failed to queue the job, please try again later"
+    }))
+}
 
-    let output = child
-        .wait_with_output()
-        .map_err(|_| GourceError::CloneFailed)?;
+/// Most repositories accepted in one combined render.
+const MAX_COMBINED_REPOSITORIES: usize = 10;
 
-    if !output.status.success() {
-        let error_message = String::from_utf8_lossy(&output.stderr);
-        log_message(
-            log::Level::Error,
-            &format!("Git clone failed: {}", error_message),
-            None,
-        );
-        return Err(GourceError::CloneFailed);
+fn requested_repositories(request: &GourceRequest) -> Result<Vec<String>, &'static str> {
+    let repo_urls = match (&request.repo_url, &request.repo_urls) {
+        (Some(repo_url), None) => vec![repo_url.clone()],
+        (None, Some(repo_urls)) => repo_urls.clone(),
+        (Some(_), Some(_)) => return Err("Send either repo_url or repo_urls, not both"),
+        (None, None) => return Err("repo_url is required"),
+    };
+    if repo_urls.is_empty() {
+        return Err("repo_urls must not be empty");
     }
+    if repo_urls.len() > MAX_COMBINED_REPOSITORIES {
+        return Err("repo_urls has too many repositories");
+    }
+    let unique: HashSet<&String> = repo_urls.iter().collect();
+    if unique.len() != repo_urls.len() {
+        return Err("repo_urls must not repeat a repository");
+    }
+    if !repo_urls
+        .iter()
+        .all(|repo_url| vcs::is_network_url(repo_url))
+    {
+        return Err("Repository URLs must use https, http, ssh, git or svn");
+    }
+    Ok(repo_urls)
+}
 
-    log_message(log::Level::Info, "Successfully cloned repository", None);
+fn validate_settings(settings: &GourceSettings) -> Result<(), String> {
+    if let Some(duration) = settings.duration {
+        duration.validate()?;
+    }
+    if let Some(captions) = &settings.captions {
+        captions.validate()?;
+    }
+    if settings
+        .top_contributors
+        .is_some_and(|top| top == 0 || top > overlay::MAX_TOP_CONTRIBUTORS)
+    {
+        return Err(format!(
+            "top_contributors must be between 1 and {}",
+            overlay::MAX_TOP_CONTRIBUTORS
+        ));
+    }
+    if settings
+        .contact_sheet_frames
+        .is_some_and(|frames| !(2..=frames::MAX_CONTACT_SHEET_FRAMES).contains(&frames))
+    {
+        return Err(format!(
+            "contact_sheet_frames must be between 2 and {}",
+            frames::MAX_CONTACT_SHEET_FRAMES
+        ));
+    }
+    if let Some(seconds) = settings.target_duration_seconds {
+        if !(seconds > 0.0 && seconds <= pacing::MAX_TARGET_SECONDS) {
+            return Err(format!(
+                "target_duration_seconds must be between 0 and {}",
+                pacing::MAX_TARGET_SECONDS
+            ));
+        }
+    }
+    if let (Some(start_date), Some(stop_date)) = (settings.start_date, settings.stop_date) {
+        if start_date > stop_date {
+            return Err("start_date must not be after stop_date".into());
+        }
+    }
     Ok(())
 }
 
-fn count_days_and_commits(
-    repo_path: &Path,
-    job_id: Option<&str>,
-) -> Result<(i32, i32), GourceError> {
-    log_message(
-        log::Level::Info,
-        &format!(
-            "Counting days with commits and total commits in repository at: {:?}",
-            repo_path
-        ),
-        job_id,
-    );
+/// The callback of a job request. Its URL must be http or https and, unless
+/// listed in `webhooks.allowed_hosts`, on a public address.
+fn parse_callback(
+    url: &Option<String>,
+    secret: &Option<String>,
+    config: &Config,
+) -> Result<Option<Callback>, &'static str> {
+    let (url, secret) = match (url, secret) {
+        (None, None) => return Ok(None),
+        (Some(url), Some(secret)) if !secret.is_empty() => (url, secret),
+        (Some(_), _) => return Err("callback_secret is required with callback_url"),
+        (None, Some(_)) => return Err("callback_secret requires callback_url"),
+    };
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {
+            if !webhook::is_allowed_callback_url(&parsed, &config.webhooks.allowed_hosts) {
+                return Err("callback_url must not point at a private or local address");
+            }
+            Ok(Some(Callback {
+                url: url.clone(),
+                secret: secret.clone(),
+            }))
+        }
+        _ => Err("callback_url must be an http or https URL"),
+    }
+}
 
-    let commit_output = Command::new("git")
-        .args(&["rev-list", "--count", "HEAD"])
-        .current_dir(repo_path)
-        .output()
-        .map_err(|_| GourceError::CommitCountFailed)?;
-
-    if !commit_output.status.success() {
-        let error_message = String::from_utf8_lossy(&commit_output.stderr);
-        log_message(
-            log::Level::Error,
-            &format!("Git commit count failed: {}", error_message),
-            job_id,
+/// Renders queued jobs, either in the API process or in a worker.
+#[derive(Clone)]
+struct JobRunner {
+    job_store: JobStore,
+    config: web::Data<Config>,
+    storage: web::Data<dyn ArtifactStore>,
+    shutdown: Shutdown,
+    webhooks: WebhookSender,
+    subscriptions: SubscriptionStore,
+    /// Jobs run here rather than on the HTTP worker that accepted them, whose
+    /// runtime goes away as soon as the server stops.
+    runtime: tokio::runtime::Handle,
+}
+
+impl JobRunner {
+    /// Runs the job and returns how long to wait before running it again when
+    /// it failed for a reason worth retrying.
+    async fn run(&self, job: QueuedJob) -> Option<Duration> {
+        let job_span = info_span!(
+            parent: None,
+            "job",
+            job_id = %job.job_id,
+            repo_url = %job.repo_url,
+            repo_host = Url::parse(&job.repo_url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .unwrap_or_default(),
+            settings = ?job.settings,
+            commit_count = tracing::field::Empty,
+            days_with_commits = tracing::field::Empty,
         );
-        return Err(GourceError::CommitCountFailed);
+
+        async move {
+            let _running = self.shutdown.track_job();
+
+            // The job may have been stopped while it waited in the queue.
+            let retry_count = self
+                .job_store
+                .update(&job.job_id, |status| {
+                    (!status.state.is_terminal()).then(|| {
+                        status.next_retry_at = None;
+                        status.retry_count
+                    })
+                })
+                .await
+                .flatten();
+            let Some(retry_count) = retry_count else {
+                info!("Skipping job that is no longer pending");
+                return None;
+            };
+
+            let job_id = job.job_id.clone();
+            let callback = job.callback.clone();
+            let subscription = job.subscription.clone();
+            let processes = JobProcesses::default();
+            let result = tokio::select! {
+                result = process_gource(
+                    job,
+                    self.job_store.clone(),
+                    self.config.clone(),
+                    self.storage.clone(),
+                    processes.clone(),
+                ) => result,
+                _ = self.shutdown.interrupted() => {
+                    self.interrupt(&job_id, &processes).await;
+                    self.notify(&job_id, callback).await;
+                    return None;
+                }
+            };
+
+            if let Err(e) = &result {
+                error!(error_kind = e.kind(), "Job failed: {}", e);
+
+                if e.is_retryable() && retry_count < self.config.retry.max_retries {
+                    let attempt = retry_count + 1;
+                    let backoff = self.config.retry_backoff(attempt);
+                    let next_retry_at = Utc::now() + backoff;
+                    let rescheduled = self
+                        .job_store
+                        .update(&job_id, |status| {
+                            // Stopped by the user while it was running.
+                            if status.state.is_terminal() {
+                                return false;
+                            }
+                            reset_for_retry(status);
+                            status.next_retry_at = Some(next_retry_at);
+                            true
+                        })
+                        .await;
+                    if rescheduled == Some(true) {
+                        info!(
+                            "Retrying in {:?} (attempt {} of {})",
+                            backoff, attempt, self.config.retry.max_retries
+                        );
+                        metrics::JOBS_RETRIED
+                            .with_label_values(&["automatic"])
+                            .inc();
+                        return Some(backoff);
+                    }
+                    return None;
+                }
+
+                metrics::JOBS_FAILED.with_label_values(&[e.kind()]).inc();
+                let message = e.to_string();
+                self.job_store
+                    .update(&job_id, |status| {
+                        status.step = ProgressStep::GeneratingVisualization;
+                        status.error = Some(message.clone());
+                    })
+                    .await;
+                finish_job(
+                    &self.job_store,
+                    &job_id,
+                    JobState::Failed,
+                    self.config.video_ttl(),
+                )
+                .await;
+            }
+            if let (Ok(()), Some(subscription)) = (&result, &subscription) {
+                self.publish_latest(subscription, &job_id).await;
+            }
+            self.notify(&job_id, callback).await;
+            None
+        }
+        .instrument(job_span)
+        .await
     }
 
-    let total_commits: i32 = String::from_utf8_lossy(&commit_output.stdout)
-        .trim()
-        .parse()
-        .map_err(|_| GourceError::CommitCountFailed)?;
-
-    // Count days with commits
-    let log_output = Command::new("git")
-        .args(&["log", "--format=%ad", "--date=short"])
-        .current_dir(repo_path)
-        .output()
-        .map_err(|_| GourceError::CommitCountFailed)?;
-
-    if !log_output.status.success() {
-        let error_message = String::from_utf8_lossy(&log_output.stderr);
-        log_message(
-            log::Level::Error,
-            &format!("Git log failed: {}", error_message),
+    /// Makes a completed render the subscription's latest video. The video is
+    /// pinned until a newer render replaces it.
+    async fn publish_latest(&self, subscription: &str, job_id: &str) {
+        if self.subscriptions.get(subscription).await.is_none() {
+            info!("Subscription {} was removed while rendering", subscription);
+            return;
+        }
+        let pinned = self
+            .job_store
+            .update(job_id, |status| {
+                let completed = status.state == JobState::Completed;
+                status.pinned |= completed;
+                completed
+            })
+            .await;
+        if pinned != Some(true) {
+            return;
+        }
+
+        let previous = self.subscriptions.set_latest(subscription, job_id).await;
+        let Some(previous) = previous.filter(|previous| previous != job_id) else {
+            return;
+        };
+        // Renders can finish out of order; keep whichever was requested last.
+        let created_at = |status: Option<JobStatus>| status.map(|status| status.created_at);
+        let previous_created = created_at(self.job_store.get(&previous).await);
+        let this_created = created_at(self.job_store.get(job_id).await);
+        let (newer, older) = if previous_created > this_created {
+            self.subscriptions.set_latest(subscription, &previous).await;
+            (previous.as_str(), job_id)
+        } else {
+            (job_id, previous.as_str())
+        };
+        info!(
+            "Latest video of {} is now from job {}, replacing job {}",
+            subscription, newer, older
+        );
+        self.job_store
+            .update(older, |status| status.pinned = false)
+            .await;
+    }
+
+    async fn notify(&self, job_id: &str, callback: Option<Callback>) {
+        let Some(callback) = callback else {
+            return;
+        };
+        // Cancellation is reported by `stop_job` when it happens.
+        let cancelled = self
+            .job_store
+            .get(job_id)
+            .await
+            .is_some_and(|status| status.state == JobState::Cancelled);
+        if !cancelled {
+            self.webhooks.send(job_id, callback);
+        }
+    }
+
+    /// Stops a job the server cannot wait for: kills its processes, removes
+    /// the partial video and images and tells the client it will not finish.
+    async fn interrupt(&self, job_id: &str, processes: &JobProcesses) {
+        warn!("Interrupting job because the server is shutting down");
+        processes.kill_all();
+
+        let video_path = self.storage.staging_path(&storage::video_key(job_id));
+        let mut partial_files = vec![fitted_path(&video_path), video_path];
+        // The image of a stills preview being promoted is kept.
+        let keeps_stills = self
+            .job_store
+            .get(job_id)
+            .await
+            .is_some_and(|status| status.stills_url.is_some());
+        partial_files.extend(
+            Image::ALL
+                .into_iter()
+                .filter(|image| *image != Image::Stills || !keeps_stills)
+                .map(|image| {
+                    self.storage
+                        .staging_path(&storage::image_key(job_id, image))
+                }),
+        );
+        for path in partial_files {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => info!("Removed partial file {:?}", path),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => error!("Failed to remove partial file {:?}: {}", path, e),
+            }
+        }
+
+        self.job_store
+            .update(job_id, |status| {
+                if !status.state.is_terminal() {
+                    status.error = Some("The server shut down before the job finished".to_string());
+                }
+            })
+            .await;
+        finish_job(
+            &self.job_store,
             job_id,
+            JobState::Interrupted,
+            self.config.video_ttl(),
+        )
+        .await;
+        metrics::JOBS_INTERRUPTED.inc();
+    }
+}
+
+/// Clears the outcome of a failed run so the job can be queued again.
+fn reset_for_retry(status: &mut JobStatus) {
+    clear_outcome(status);
+    status.retry_count += 1;
+}
+
+/// Puts a job back in the state it was queued in. The image of a stills
+/// preview is kept.
+fn clear_outcome(status: &mut JobStatus) {
+    status.state = JobState::Queued;
+    status.step = ProgressStep::InitializingProject;
+    status.error = None;
+    status.video_url = None;
+    status.finished_at = None;
+    status.expires_at = None;
+    status.timings = JobTimings::default();
+    status.stats = None;
+    status.video = None;
+    status.poster_url = None;
+    status.contact_sheet_url = None;
+    status.next_retry_at = None;
+}
+
+async fn process_gource(
+    job: QueuedJob,
+    job_store: JobStore,
+    config: web::Data<Config>,
+    storage: web::Data<dyn ArtifactStore>,
+    processes: JobProcesses,
+) -> Result<(), GourceError> {
+    let repositories = job.repositories();
+    let QueuedJob {
+        job_id,
+        access_token,
+        settings,
+        source,
+        vcs,
+        ..
+    } = job;
+    let preview = settings.preview;
+    let settings = Some(settings);
+    info!("Starting process_gource");
+    let start_time = Instant::now();
+
+    update_job_status(&job_store, &job_id, ProgressStep::InitializingProject).await;
+    set_job_state(&job_store, &job_id, JobState::Running).await;
+    info!("Updated job status to InitializingProject");
+
+    if source == RepositorySource::Clone {
+        for repository in &repositories {
+            if !vcs::is_network_url(repository) {
+                return Err(GourceError::InvalidUrl);
+            }
+            let url = Url::parse(repository).map_err(|_| GourceError::InvalidUrl)?;
+            let host = url.host_str().unwrap_or("");
+            let is_git = VcsKind::resolve(vcs, repository) == VcsKind::Git;
+            if (!host.ends_with("github.com") || !is_git) && access_token.is_some() {
+                return Err(GourceError::UnsupportedRepository);
+            }
+            info!("Validated repository URL: {}", repository);
+        }
+    }
+
+    let temp_dir = tempfile::TempDir::new().map_err(|_| GourceError::TempDirCreationFailed)?;
+    info!("Created temporary directory");
+
+    let clone_start = Instant::now();
+    info!("Attempting to decrypt token");
+    let decrypted_token = if let Some(encrypted_token) = access_token {
+        info!("Access token provided, attempting decryption");
+        let secret_key = config
+            .secret_key
+            .as_deref()
+            .ok_or(GourceError::AccessTokensDisabled)?;
+        match decrypt_token(&encrypted_token, secret_key) {
+            Ok(token) => {
+                info!("Token decrypted successfully");
+                Some(token)
+            }
+            Err(e) => {
+                error!("Failed to decrypt token: {:?}", e);
+                return Err(e);
+            }
+        }
+    } else {
+        None
+    };
+
+    info!("Attempting to clone repository");
+
+    // The clone below starts from a path on this host for anything not
+    // fetched from a URL.
+    let upload_path = temp_dir.path().join("upload");
+    let sorted_log = temp_dir.path().join("upload.log");
+    let is_custom_log = matches!(
+        source,
+        RepositorySource::Upload {
+            kind: UploadKind::Log
+        }
+    );
+
+    // A preview keeps its checkouts so the full render can start from them
+    // when it runs on the same host.
+    let kept_checkouts = preview::checkout_dir(&config.previews, &job_id);
+    let reuse_checkouts = !is_custom_log && preview::reuse_checkouts(&kept_checkouts);
+    let keep_checkouts = preview.is_some() && !is_custom_log && !reuse_checkouts;
+    let checkout_root = if reuse_checkouts {
+        info!("Reusing the checkouts kept by the preview");
+        kept_checkouts.clone()
+    } else if keep_checkouts {
+        preview::prepare_checkouts(&kept_checkouts).map_err(|e| {
+            error!("Failed to create the preview checkout directory: {}", e);
+            GourceError::TempDirCreationFailed
+        })?;
+        kept_checkouts.clone()
+    } else {
+        temp_dir.path().to_path_buf()
+    };
+
+    let origin = match &source {
+        _ if reuse_checkouts => None,
+        RepositorySource::Clone => None,
+        RepositorySource::Upload { kind } => {
+            if let Err(e) = storage
+                .get(&storage::upload_key(&job_id), &upload_path)
+                .await
+            {
+                error!("Failed to fetch the uploaded repository: {}", e);
+                return Err(GourceError::StorageFailed);
+            }
+            Some((upload_path.clone(), Some(*kind)))
+        }
+        RepositorySource::LocalPath { path } => {
+            let path = upload::allowed_local_path(path, &config.local_repos.allowed_paths)
+                .ok_or(GourceError::LocalPathNotAllowed)?;
+            Some((path, None))
+        }
+    };
+
+    // Offload the blocking clone operation to a separate thread
+    let checkouts = combine::checkouts(&repositories, vcs, &checkout_root);
+    let span = Span::current();
+    let clone_processes = processes.clone();
+    let extract_dir = temp_dir.path().join("extracted");
+    let max_upload_bytes = config.uploads.max_bytes;
+    let checkouts = tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        if reuse_checkouts {
+            return Ok(checkouts);
+        }
+        match origin {
+            None => {
+                for checkout in &checkouts {
+                    clone_repository(
+                        &checkout.repo_url,
+                        &checkout.path,
+                        decrypted_token.as_deref(),
+                        checkout.vcs,
+                        &clone_processes,
+                    )?;
+                }
+            }
+            Some((path, None)) => clone_local(&path, &checkouts[0].path, &clone_processes)?,
+            // Logs are rendered as they are, with nothing to clone.
+            Some((_, Some(UploadKind::Log))) => return Ok(Vec::new()),
+            Some((path, Some(kind))) => {
+                let path = match kind {
+                    UploadKind::Bundle | UploadKind::Log => path,
+                    UploadKind::Tarball => {
+                        upload::extract_tarball(&path, &extract_dir, max_upload_bytes).map_err(
+                            |e| {
+                                error!("Failed to extract the uploaded repository: {}", e);
+                                GourceError::InvalidUpload
+                            },
+                        )?
+                    }
+                };
+                clone_local(&path, &checkouts[0].path, &clone_processes)
+                    .map_err(|_| GourceError::InvalidUpload)?;
+            }
+        }
+        Ok(checkouts)
+    })
+    .await
+    .map_err(|_| GourceError::CloneFailed)??;
+
+    if keep_checkouts {
+        if let Err(e) = preview::mark_complete(&kept_checkouts) {
+            error!("Failed to keep the checkouts for the full render: {}", e);
+        }
+    }
+
+    let clone_duration = clone_start.elapsed();
+    info!("Repository cloning took {:?}", clone_duration);
+    record_phase_duration(&job_store, &job_id, JobPhase::Clone, clone_duration).await;
+
+    update_job_status(&job_store, &job_id, ProgressStep::AnalyzingHistory).await;
+    let count_start = Instant::now();
+    let history = if is_custom_log {
+        count_log_commits(&upload_path, &sorted_log, &settings)?
+    } else {
+        count_commits(&checkouts, &settings)?
+    };
+    let days_with_commits = history.commits_per_day.len() as i32;
+    let total_commits = history.total_commits() as i32;
+    Span::current().record("commit_count", total_commits);
+    Span::current().record("days_with_commits", days_with_commits);
+    let count_duration = count_start.elapsed();
+    info!("Counting days with commits took {:?}", count_duration);
+    record_phase_duration(&job_store, &job_id, JobPhase::Analyze, count_duration).await;
+
+    let names = repository_names(&repositories);
+    let branding = &config.branding;
+    let intro = settings.as_ref().filter(|s| s.intro_card).map(|s| {
+        let span = history.span();
+        let first = s.start_date.or(span.map(|(first, _)| first));
+        let last = s.stop_date.or(span.map(|(_, last)| last));
+        Card::intro(
+            &names,
+            first.zip(last),
+            history.total_commits(),
+            history.commits_per_author.len(),
+        )
+    });
+    let outro = settings
+        .as_ref()
+        .filter(|s| s.outro_card)
+        .map(|_| Card::outro(&names, &branding.title_suffix));
+    let title = generate_repo_title(&names, &branding.title_suffix);
+    let mut composition = Composition::new(branding, title, intro, outro, temp_dir.path());
+    if preview.is_some() {
+        composition.intro = None;
+        composition.outro = None;
+        composition.watermark = None;
+    }
+
+    // The cards take their share of a requested length before gource's.
+    let target_duration = settings
+        .as_ref()
+        .and_then(|s| s.target_duration_seconds)
+        .filter(|_| preview.is_none());
+    let requested_duration = settings
+        .as_ref()
+        .and_then(|s| s.duration)
+        .or(target_duration.map(TargetDuration::Seconds))
+        .map(|duration| {
+            TargetDuration::Seconds((duration.seconds() - composition.cards_duration()).max(1.0))
+        });
+    let requested_duration = match preview {
+        Some(_) => Some(TargetDuration::Seconds(preview::CLIP_SECONDS)),
+        None => requested_duration,
+    };
+    let pacing = pacing::plan(&history.commits_per_day, requested_duration, &config.pacing);
+    info!(
+        "Pacing {} days with commits at {} seconds per day, skipping quiet periods after {}s. \
+        Target duration: {}s, estimated: {}s",
+        days_with_commits,
+        pacing.seconds_per_day,
+        pacing.auto_skip_seconds,
+        pacing.target_duration,
+        pacing.estimated_duration
+    );
+    let range = date_range(&settings);
+    let captions = settings
+        .as_ref()
+        .and_then(|s| s.captions.as_ref())
+        .filter(|captions| captions.is_enabled());
+    let tag_captions = captions.is_some_and(|captions| captions.tags);
+    let stats_overlay = settings.as_ref().is_some_and(|s| s.stats_overlay);
+    let tags = if !is_custom_log && (tag_captions || stats_overlay) {
+        repository_tags(&checkouts, &range)
+    } else {
+        Vec::new()
+    };
+
+    if let Some(captions) = captions {
+        let caption_path = temp_dir.path().join("captions.txt");
+        if let Err(e) = fs::write(&caption_path, captions.caption_file(&tags, &range)) {
+            error!("Failed to write the caption file: {}", e);
+            return Err(GourceError::GourceGenerationFailed);
+        }
+        composition.captions = Some(caption_path);
+    }
+    if let Some(settings) = settings.as_ref().filter(|s| s.stats_overlay) {
+        let document = overlay::stats_overlay(
+            &history.commits,
+            // Tags already have a caption from gource when it draws them.
+            if tag_captions { &[] } else { &tags },
+            &pacing,
+            settings
+                .top_contributors
+                .unwrap_or(overlay::DEFAULT_TOP_CONTRIBUTORS),
         );
-        return Err(GourceError::CommitCountFailed);
+        let overlay_path = temp_dir.path().join("overlay.ass");
+        if let Err(e) = fs::write(&overlay_path, document) {
+            error!("Failed to write the statistics overlay: {}", e);
+            return Err(GourceError::GourceGenerationFailed);
+        }
+        composition.overlay = Some(overlay_path);
+    }
+
+    let hide_filenames = total_commits > 500;
+    set_repo_stats(
+        &job_store,
+        &job_id,
+        RepoStats {
+            total_commits,
+            days_with_commits,
+            seconds_per_day: pacing.seconds_per_day,
+            auto_skip_seconds: Some(pacing.auto_skip_seconds),
+            estimated_duration_seconds: Some(pacing.estimated_duration),
+            hide_filenames,
+        },
+    )
+    .await;
+
+    update_job_status(&job_store, &job_id, ProgressStep::GeneratingVisualization).await;
+    let video_key = storage::video_key(&job_id);
+    let output_file = storage.staging_path(&video_key);
+    let output_file_clone = output_file.clone();
+    let gource_start = Instant::now();
+
+    // Use tokio::task::spawn_blocking for CPU-intensive tasks
+    let span = Span::current();
+    metrics::ACTIVE_RENDERS.inc();
+    let fit_processes = processes.clone();
+    let frame_processes = processes.clone();
+    let estimated_duration = pacing.estimated_duration;
+    let card_secs = |card: &Option<Card>| {
+        card.as_ref()
+            .map_or(0.0, |_| composition.card_duration_secs)
+    };
+    let (intro_secs, outro_secs) = (card_secs(&composition.intro), card_secs(&composition.outro));
+    let mut images = Vec::new();
+    if preview == Some(PreviewMode::Stills) {
+        images.push(Image::Stills);
+    }
+    if let Some(settings) = settings.as_ref().filter(|_| preview.is_none()) {
+        if settings.poster {
+            images.push(Image::Poster);
+        }
+        if settings.contact_sheet {
+            images.push(Image::ContactSheet);
+        }
+    }
+    let contact_sheet_frames = settings
+        .as_ref()
+        .and_then(|s| s.contact_sheet_frames)
+        .unwrap_or(frames::DEFAULT_CONTACT_SHEET_FRAMES);
+    let render_result = tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let source = if is_custom_log {
+            Ok(sorted_log)
+        } else if checkouts.len() > 1 || checkouts[0].vcs != VcsKind::Git {
+            let log = temp_dir.path().join("combined.log");
+            let color_by_repository = settings.as_ref().is_some_and(|s| s.color_by_repository);
+            combine::write_combined_log(&checkouts, color_by_repository, &log, &processes)
+                .map(|()| log)
+        } else {
+            Ok(checkouts[0].path.clone())
+        };
+        let result = source.and_then(|source| {
+            generate_gource_visualization(
+                &source,
+                &pacing,
+                hide_filenames,
+                &output_file_clone,
+                &settings,
+                &composition,
+                &processes,
+            )
+        });
+
+        // Explicitly close the temporary directory
+        if let Err(e) = temp_dir.close() {
+            error!("Failed to remove temporary directory: {:?}", e);
+        } else {
+            info!("Temporary directory removed successfully");
+        }
+
+        result
+    })
+    .await;
+    metrics::ACTIVE_RENDERS.dec();
+    render_result.map_err(|_| GourceError::GourceGenerationFailed)??;
+
+    // Frames are taken before the fit, while the cards still last
+    // `card_duration_secs`.
+    let image_files: Vec<(Image, PathBuf)> = images
+        .into_iter()
+        .map(|image| {
+            let key = storage::image_key(&job_id, image);
+            (image, storage.staging_path(&key))
+        })
+        .collect();
+    if !image_files.is_empty() {
+        let video = output_file.clone();
+        let files = image_files.clone();
+        tokio::task::spawn_blocking(move || {
+            let duration = probe_video(&video, &frame_processes)
+                .and_then(|info| info.duration_seconds)
+                .unwrap_or(intro_secs + estimated_duration + outro_secs);
+            let section = frames::Section {
+                start: intro_secs,
+                duration: (duration - intro_secs - outro_secs).max(0.0),
+            };
+            for (image, path) in &files {
+                match image {
+                    Image::Stills => frames::contact_sheet(
+                        &video,
+                        section,
+                        preview::STILLS,
+                        path,
+                        &frame_processes,
+                    )?,
+                    Image::Poster => frames::final_frame(&video, section, path, &frame_processes)?,
+                    Image::ContactSheet => frames::contact_sheet(
+                        &video,
+                        section,
+                        contact_sheet_frames,
+                        path,
+                        &frame_processes,
+                    )?,
+                }
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|_| GourceError::FrameExtractionFailed)??;
+    }
+
+    let output_file_for_probe = output_file.clone();
+    let fit_tolerance = config.pacing.fit_tolerance_secs;
+    let video_info = tokio::task::spawn_blocking(move || {
+        fit_video(
+            &output_file_for_probe,
+            target_duration,
+            fit_tolerance,
+            &fit_processes,
+        )
+    })
+    .await
+    .map_err(|_| GourceError::VideoFitFailed)??;
+
+    let gource_duration = gource_start.elapsed();
+    info!("Gource visualization generation took {:?}", gource_duration);
+    record_phase_duration(&job_store, &job_id, JobPhase::Render, gource_duration).await;
+
+    let mut video_url = None;
+    if preview == Some(PreviewMode::Stills) {
+        // Only the stills are kept.
+        if let Err(e) = fs::remove_file(&output_file) {
+            error!("Failed to remove the preview clip: {}", e);
+        }
+    } else {
+        if let Some(video_info) = video_info {
+            info!(
+                "Rendered video is {} bytes, duration {:?}s",
+                video_info.size_bytes, video_info.duration_seconds
+            );
+            set_video_info(&job_store, &job_id, video_info).await;
+        }
+        video_url =
+            Some(store_artifact(storage.get_ref(), &video_key, &output_file, &config).await?);
+    }
+    for (image, path) in image_files {
+        let key = storage::image_key(&job_id, image);
+        let url = store_artifact(storage.get_ref(), &key, &path, &config).await?;
+        set_image_url(&job_store, &job_id, image, &url).await;
+    }
+
+    if preview.is_none() {
+        if let Err(e) = preview::remove_checkouts(&kept_checkouts) {
+            error!("Failed to remove the checkouts kept by the preview: {}", e);
+        }
+    }
+
+    let total_duration = start_time.elapsed();
+    info!("Total process took {:?}", total_duration);
+    set_total_duration(&job_store, &job_id, total_duration).await;
+
+    update_job_status(&job_store, &job_id, ProgressStep::GeneratingVisualization).await;
+    if let Some(video_url) = video_url {
+        set_video_url(&job_store, &job_id, &video_url).await;
+    }
+    finish_job(&job_store, &job_id, JobState::Completed, config.video_ttl()).await;
+    metrics::JOBS_COMPLETED.inc();
+
+    if config.cleanup.max_disk_bytes.is_some() {
+        enforce_disk_quota(&job_store, &config, storage.get_ref()).await;
+    }
+
+    Ok(())
+}
+
+/// Stores a rendered file and returns the URL it can be fetched from.
+async fn store_artifact(
+    storage: &dyn ArtifactStore,
+    key: &str,
+    path: &Path,
+    config: &Config,
+) -> Result<String, GourceError> {
+    if let Err(e) = storage.put(key, path, storage::content_type(key)).await {
+        error!("Failed to store {}: {}", key, e);
+        return Err(GourceError::StorageFailed);
+    }
+    match storage.presigned_url(key, config.video_ttl()).await {
+        Ok(Some(url)) => Ok(url),
+        Ok(None) => Ok(path.to_string_lossy().into_owned()),
+        Err(e) => {
+            error!("Failed to presign the URL of {}: {}", key, e);
+            Err(GourceError::StorageFailed)
+        }
+    }
+}
+
+async fn stop_job(
+    job_id: web::Path<String>,
+    job_store: web::Data<JobStore>,
+    config: web::Data<Config>,
+    webhooks: web::Data<WebhookSender>,
+) -> impl Responder {
+    let stopped = job_store
+        .update(job_id.as_str(), |status| {
+            if status.state.is_terminal() || status.video_url.is_some() || status.error.is_some() {
+                return false;
+            }
+            status.error = Some("Job stopped by user".to_string());
+            status.state = JobState::Cancelled;
+            status.finished_at = Some(Utc::now());
+            status.expires_at = Some(Utc::now() + config.video_ttl());
+            true
+        })
+        .await;
+
+    let response = match stopped {
+        Some(true) => {
+            metrics::JOBS_CANCELLED.inc();
+            if let Some(callback) = job_store
+                .request(job_id.as_str())
+                .await
+                .and_then(|job| job.callback)
+            {
+                webhooks.send(job_id.as_str(), callback);
+            }
+            info!("Job {} stopped by user", job_id);
+            serde_json::json!({
+                "message": "Job stopped successfully and temporary files cleaned up",
+                "status": "stopped"
+            })
+        }
+        Some(false) => {
+            info!("Cannot stop job {}: already completed or errored", job_id);
+            serde_json::json!({
+                "error": "Cannot stop job: already completed or errored",
+                "status": "unchanged"
+            })
+        }
+        None => {
+            info!("Job not found: {}", job_id);
+            serde_json::json!({
+                "error": "Job not found",
+                "status": "not_found"
+            })
+        }
+    };
+
+    HttpResponse::Ok().json(response)
+}
+
+async fn get_job_status(
+    job_id: web::Path<String>,
+    job_store: web::Data<JobStore>,
+) -> impl Responder {
+    match job_store.get(job_id.as_str()).await {
+        Some(status) => {
+            info!(
+                "Returning job status for {}: step={:?}, video_url={:?}",
+                job_id, status.step, status.video_url
+            );
+            HttpResponse::Ok().json(status)
+        }
+        None => {
+            info!("Job not found: {}", job_id);
+            HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Job not found"
+            }))
+        }
+    }
+}
+
+async fn list_jobs(
+    req: HttpRequest,
+    query: web::Query<ListJobsQuery>,
+    job_store: web::Data<JobStore>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if let Some(response) = reject_unless_admin(&req, &config) {
+        return response;
     }
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(20).clamp(1, 100);
 
-    let days_with_commits: HashSet<NaiveDate> = String::from_utf8_lossy(&log_output.stdout)
-        .lines()
-        .filter_map(|line| NaiveDate::parse_from_str(line, "%Y-%m-%d").ok())
+    let mut jobs: Vec<JobSummary> = job_store
+        .list()
+        .await
+        .into_iter()
+        .filter(|(_, status)| query.state.is_none_or(|state| status.state == state))
+        .filter(|(_, status)| {
+            query.repo_url.as_deref().is_none_or(|repo_url| {
+                status.repo_url.contains(repo_url)
+                    || status.repo_urls.iter().any(|url| url.contains(repo_url))
+            })
+        })
+        .filter(|(_, status)| query.from.is_none_or(|from| status.created_at >= from))
+        .filter(|(_, status)| query.to.is_none_or(|to| status.created_at <= to))
+        .map(|(job_id, status)| JobSummary {
+            job_id,
+            state: status.state,
+            step: status.step,
+            repo_url: status.repo_url.clone(),
+            owner: repo_owner_and_name(&status.repo_url).map(|(owner, _)| owner),
+            error: status.error.clone(),
+            video_url: status.video_url.clone(),
+            created_at: status.created_at,
+            finished_at: status.finished_at,
+            expires_at: status.expires_at,
+            pinned: status.pinned,
+            retry_count: status.retry_count,
+            timings: status.timings.clone(),
+        })
+        .filter(|summary| {
+            query.owner.as_deref().is_none_or(|owner| {
+                summary
+                    .owner
+                    .as_deref()
+                    .is_some_and(|o| o.eq_ignore_ascii_case(owner))
+            })
+        })
         .collect();
 
-    let count_days = days_with_commits.len() as i32;
+    jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+    let total = jobs.len();
+    let jobs = jobs
+        .into_iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .collect();
 
-    Ok((count_days, total_commits))
+    HttpResponse::Ok().json(JobListResponse {
+        jobs,
+        page,
+        per_page,
+        total,
+    })
 }
 
-fn calculate_seconds_per_day(days_with_commits: i32, job_id: Option<&str>) -> f64 {
-    const MIN_DURATION: f64 = 40.0;
-    const MAX_DURATION: f64 = 80.0;
-    const THRESHOLD: i32 = 1000;
+async fn update_retention(
+    req: HttpRequest,
+    job_id: web::Path<String>,
+    request: web::Json<RetentionRequest>,
+    job_store: web::Data<JobStore>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if let Some(response) = reject_unless_admin(&req, &config) {
+        return response;
+    }
+    let updated = job_store
+        .update(job_id.as_str(), |status| {
+            let expires_at = status.expires_at?;
+            if let Some(extend_secs) = request.extend_secs {
+                let now = Utc::now();
+                let max_expires_at = now + config.max_video_ttl();
+                let extended = expires_at.max(now) + TimeDelta::seconds(extend_secs as i64);
+                status.expires_at = Some(extended.min(max_expires_at));
+            }
+            if let Some(pinned) = request.pinned {
+                status.pinned = pinned;
+            }
+            Some((status.expires_at, status.pinned))
+        })
+        .await;
+
+    match updated {
+        None => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Job not found"
+        })),
+        Some(None) => HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Job has not finished yet"
+        })),
+        Some(Some((expires_at, pinned))) => {
+            info!(
+                "Updated retention for job {}: expires_at={:?}, pinned={}",
+                job_id, expires_at, pinned
+            );
+            HttpResponse::Ok().json(RetentionResponse {
+                job_id: job_id.into_inner(),
+                expires_at,
+                pinned,
+            })
+        }
+    }
+}
 
-    let target_duration = if days_with_commits <= THRESHOLD {
-        // For repos with fewer commits, scale linearly from MIN_DURATION to MAX_DURATION
-        MIN_DURATION + (MAX_DURATION - MIN_DURATION) * (days_with_commits as f64 / THRESHOLD as f64)
+async fn retry_job(
+    job_id: web::Path<String>,
+    job_store: web::Data<JobStore>,
+    queue: web::Data<JobQueue>,
+) -> impl Responder {
+    let Some(job) = job_store.request(job_id.as_str()).await else {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Job not found"
+        }));
+    };
+
+    let reset = job_store
+        .update(job_id.as_str(), |status| {
+            if !matches!(status.state, JobState::Failed | JobState::Interrupted) {
+                return false;
+            }
+            reset_for_retry(status);
+            true
+        })
+        .await;
+    match reset {
+        None => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Job not found"
+            }))
+        }
+        Some(false) => {
+            return HttpResponse::Conflict().json(serde_json::json!({
+                "error": "Only failed or interrupted jobs can be retried"
+            }))
+        }
+        Some(true) => {}
+    }
+
+    if let Err(e) = queue.enqueue(job).await {
+        error!("Failed to enqueue retry of job {}: {}", job_id, e);
+        job_store
+            .update(job_id.as_str(), |status| {
+                status.state = JobState::Failed;
+                status.error = Some("Failed to queue the retry".to_string());
+            })
+            .await;
+        return HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "error": "Failed to queue the job, please try again later"
+        }));
+    }
+
+    metrics::JOBS_RETRIED.with_label_values(&["manual"]).inc();
+    info!("Job {} queued for a manual retry", job_id);
+    HttpResponse::Ok().json(GourceResponse {
+        job_id: job_id.into_inner(),
+    })
+}
+
+/// Renders a completed preview in full, from the checkouts it kept when the
+/// job runs on the same host. The job keeps its id.
+async fn promote_preview(
+    job_id: web::Path<String>,
+    body: web::Bytes,
+    job_store: web::Data<JobStore>,
+    queue: web::Data<JobQueue>,
+) -> impl Responder {
+    // The body is optional.
+    let request = if body.is_empty() {
+        PromoteRequest::default()
     } else {
-        // For repos with many commits, use MAX_DURATION
-        MAX_DURATION
+        match serde_json::from_slice::<PromoteRequest>(&body) {
+            Ok(request) => request,
+            Err(e) => {
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("Invalid request body: {}", e)
+                }))
+            }
+        }
+    };
+    let Some(mut job) = job_store.request(job_id.as_str()).await else {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Job not found"
+        }));
+    };
+    if job.settings.preview.is_none() {
+        return HttpResponse::Conflict().json(serde_json::json!({
+            "error": "Only previews can be promoted"
+        }));
+    }
+    if let Some(settings) = request.settings {
+        if let Err(message) = validate_settings(&settings) {
+            return HttpResponse::BadRequest().json(serde_json::json!({ "error": message }));
+        }
+        job.settings = settings;
+    }
+    job.settings.preview = None;
+
+    let settings = job.settings.clone();
+    let promoted = job_store
+        .update(job_id.as_str(), |status| {
+            if status.state != JobState::Completed || status.settings.preview.is_none() {
+                return false;
+            }
+            clear_outcome(status);
+            status.settings = settings.clone();
+            status.retry_count = 0;
+            true
+        })
+        .await;
+    match promoted {
+        None => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Job not found"
+            }))
+        }
+        Some(false) => {
+            return HttpResponse::Conflict().json(serde_json::json!({
+                "error": "Only completed previews can be promoted"
+            }))
+        }
+        Some(true) => {}
+    }
+
+    job_store.save_request(&job).await;
+    if let Err(e) = queue.enqueue(job).await {
+        error!("Failed to enqueue promotion of job {}: {}", job_id, e);
+        job_store
+            .update(job_id.as_str(), |status| {
+                status.state = JobState::Failed;
+                status.error = Some("Failed to queue the full render".to_string());
+            })
+            .await;
+        return queue_unavailable();
+    }
+
+    metrics::JOBS_STARTED.inc();
+    info!("Preview {} promoted to a full render", job_id);
+    HttpResponse::Ok().json(GourceResponse {
+        job_id: job_id.into_inner(),
+    })
+}
+
+async fn create_subscription(
+    req: HttpRequest,
+    request: web::Json<SubscriptionRequest>,
+    job_store: web::Data<JobStore>,
+    queue: web::Data<JobQueue>,
+    subscriptions: web::Data<SubscriptionStore>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if let Some(response) = reject_unless_admin(&req, &config) {
+        return response;
+    }
+    let is_github = Url::parse(&request.repo_url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host == "github.com"))
+        .unwrap_or(false);
+    let Some((owner, name)) = repo_owner_and_name(&request.repo_url).filter(|_| is_github) else {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Subscriptions require a https://github.com/{owner}/{name} repository URL"
+        }));
+    };
+    if request
+        .settings
+        .as_ref()
+        .is_some_and(|s| s.preview.is_some())
+    {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Subscriptions render full videos and cannot use preview"
+        }));
+    }
+    let settings = request.settings.clone().unwrap_or_default();
+    if let Err(message) = validate_settings(&settings) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": message }));
+    }
+
+    let subscription = Subscription {
+        owner,
+        name: name.trim_end_matches(".git").to_string(),
+        repo_url: request.repo_url.clone(),
+        branch: request.branch.clone().unwrap_or_else(|| "main".to_string()),
+        access_token: request.access_token.clone(),
+        settings,
+        created_at: Utc::now(),
     };
+    subscriptions.insert(&subscription).await;
+    info!(
+        "Subscribed to pushes to {} on {}",
+        subscription.key(),
+        subscription.branch
+    );
 
-    let seconds_per_day = target_duration / days_with_commits as f64;
+    // Render once now so the latest video exists before the first push.
+    let job_id = Uuid::new_v4().to_string();
+    if submit_subscription_job(&job_store, &queue, &subscription, &job_id)
+        .await
+        .is_err()
+    {
+        return queue_unavailable();
+    }
 
-    let clamped_seconds = seconds_per_day.clamp(0.00001, 1.0);
+    let mut response = subscription_response(subscription, None);
+    response.queued_job_id = Some(job_id);
+    HttpResponse::Created().json(response)
+}
 
-    log_message(
-        log::Level::Info,
-        &format!(
-            "Calculated seconds per day: {} for {} days with commits. Target duration: {}",
-            clamped_seconds, days_with_commits, target_duration
+async fn list_subscriptions(
+    req: HttpRequest,
+    subscriptions: web::Data<SubscriptionStore>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if let Some(response) = reject_unless_admin(&req, &config) {
+        return response;
+    }
+    let mut responses = Vec::new();
+    for subscription in subscriptions.list().await {
+        let latest = subscriptions.latest(&subscription.key()).await;
+        responses.push(subscription_response(subscription, latest));
+    }
+    responses.sort_by(|a, b| a.repo.cmp(&b.repo));
+    HttpResponse::Ok().json(responses)
+}
+
+async fn delete_subscription(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    job_store: web::Data<JobStore>,
+    subscriptions: web::Data<SubscriptionStore>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if let Some(response) = reject_unless_admin(&req, &config) {
+        return response;
+    }
+    let (owner, name) = path.into_inner();
+    let key = subscription_key(&owner, &name);
+    if subscriptions.get(&key).await.is_none() {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Subscription not found"
+        }));
+    }
+
+    // The latest video expires like any other once it is no longer published.
+    if let Some(latest) = subscriptions.remove(&key).await {
+        job_store
+            .update(&latest, |status| status.pinned = false)
+            .await;
+    }
+    info!("Removed subscription {}", key);
+    HttpResponse::NoContent().finish()
+}
+
+fn subscription_response(
+    subscription: Subscription,
+    latest_job_id: Option<String>,
+) -> SubscriptionResponse {
+    SubscriptionResponse {
+        repo: subscription.key(),
+        latest_url: format!(
+            "/repos/{}/{}/latest.mp4",
+            subscription.owner, subscription.name
         ),
-        job_id,
+        repo_url: subscription.repo_url,
+        branch: subscription.branch,
+        settings: subscription.settings,
+        created_at: subscription.created_at,
+        latest_job_id,
+        queued_job_id: None,
+    }
+}
+
+async fn submit_subscription_job(
+    job_store: &JobStore,
+    queue: &JobQueue,
+    subscription: &Subscription,
+    job_id: &str,
+) -> redis::RedisResult<()> {
+    let status = queued_status(&subscription.repo_url, &subscription.settings, None);
+    let job = QueuedJob {
+        job_id: job_id.to_string(),
+        repo_url: subscription.repo_url.clone(),
+        repo_urls: Vec::new(),
+        access_token: subscription.access_token.clone(),
+        settings: subscription.settings.clone(),
+        callback: None,
+        subscription: Some(subscription.key()),
+        source: RepositorySource::Clone,
+        vcs: None,
+    };
+    submit_job(job_store, queue, job, status).await
+}
+
+/// Receives GitHub push webhooks and re-renders the videos of subscribed
+/// repositories.
+async fn github_webhook(
+    req: HttpRequest,
+    body: web::Bytes,
+    config: web::Data<Config>,
+    job_store: web::Data<JobStore>,
+    queue: web::Data<JobQueue>,
+    subscriptions: web::Data<SubscriptionStore>,
+) -> impl Responder {
+    let Some(secret) = config.github.webhook_secret.as_deref() else {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": "GitHub webhooks are not enabled on this server"
+        }));
+    };
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let verified = header(github::SIGNATURE_HEADER)
+        .is_some_and(|signature| github::verify_signature(secret, &body, signature));
+    if !verified {
+        warn!("Rejected GitHub webhook with a missing or invalid signature");
+        return HttpResponse::Unauthorized().json(serde_json::json!({
+            "error": "Invalid signature"
+        }));
+    }
+
+    match header(github::EVENT_HEADER) {
+        Some("push") => {}
+        Some("ping") => return HttpResponse::Ok().json(serde_json::json!({ "status": "pong" })),
+        _ => return HttpResponse::Accepted().json(serde_json::json!({ "status": "ignored" })),
+    }
+    let push: github::PushEvent = match serde_json::from_slice(&body) {
+        Ok(push) => push,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid push event: {}", e)
+            }))
+        }
+    };
+
+    let key = push.repository.full_name.to_lowercase();
+    let subscription = subscriptions
+        .get(&key)
+        .await
+        .filter(|subscription| push.updates(&subscription.branch));
+    let Some(subscription) = subscription else {
+        info!("Ignoring push to {} {}", key, push.git_ref);
+        return HttpResponse::Accepted().json(serde_json::json!({ "status": "ignored" }));
+    };
+
+    let job_id = Uuid::new_v4().to_string();
+    if submit_subscription_job(&job_store, &queue, &subscription, &job_id)
+        .await
+        .is_err()
+    {
+        return queue_unavailable();
+    }
+    info!(
+        "Queued job {} for push to {} on {}",
+        job_id, key, subscription.branch
     );
+    HttpResponse::Accepted().json(GourceResponse { job_id })
+}
 
-    clamped_seconds
+/// Serves the most recent video of a subscribed repository at a stable URL.
+async fn latest_video(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    storage: web::Data<dyn ArtifactStore>,
+    subscriptions: web::Data<SubscriptionStore>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse> {
+    let (owner, name) = path.into_inner();
+    match subscriptions.latest(&subscription_key(&owner, &name)).await {
+        Some(job_id) => video_response(&req, storage.get_ref(), &config, &job_id).await,
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "No video has been rendered for this repository yet"
+        }))),
+    }
 }
 
-fn generate_gource_visualization(
+async fn create_schedule(
+    req: HttpRequest,
+    request: web::Json<ScheduleRequest>,
+    schedules: web::Data<ScheduleStore>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if let Some(response) = reject_unless_admin(&req, &config) {
+        return response;
+    }
+    if !vcs::is_network_url(&request.repo_url) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Repository URLs must use https, http, ssh, git or svn"
+        }));
+    }
+    if request.window_days == Some(0) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "window_days must be greater than 0"
+        }));
+    }
+    let settings = request.settings.clone().unwrap_or_default();
+    if let Err(message) = validate_settings(&settings) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": message }));
+    }
+    if settings.preview.is_some() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Schedules render full videos and cannot use preview"
+        }));
+    }
+    let now = Utc::now();
+    let next_run_at = match schedules::first_run(&request.cron, now) {
+        Ok(next_run_at) => next_run_at,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e.to_string()
+            }))
+        }
+    };
+
+    let schedule = Schedule {
+        id: Uuid::new_v4().to_string(),
+        repo_url: request.repo_url.clone(),
+        cron: request.cron.clone(),
+        window_days: request.window_days,
+        access_token: request.access_token.clone(),
+        settings,
+        created_at: now,
+        next_run_at,
+        last_run_at: None,
+        last_job_id: None,
+    };
+    schedules.insert(&schedule).await;
+    info!(
+        "Scheduled {} with \"{}\", first run at {}",
+        schedule.repo_url, schedule.cron, schedule.next_run_at
+    );
+    HttpResponse::Created().json(ScheduleResponse::from(schedule))
+}
+
+async fn list_schedules(
+    req: HttpRequest,
+    schedules: web::Data<ScheduleStore>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if let Some(response) = reject_unless_admin(&req, &config) {
+        return response;
+    }
+    let mut responses: Vec<ScheduleResponse> = schedules
+        .list()
+        .await
+        .into_iter()
+        .map(ScheduleResponse::from)
+        .collect();
+    responses.sort_by_key(|schedule| schedule.next_run_at);
+    HttpResponse::Ok().json(responses)
+}
+
+async fn delete_schedule(
+    req: HttpRequest,
+    id: web::Path<String>,
+    schedules: web::Data<ScheduleStore>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    if let Some(response) = reject_unless_admin(&req, &config) {
+        return response;
+    }
+    if !schedules.remove(&id).await {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Schedule not found"
+        }));
+    }
+    info!("Removed schedule {}", id);
+    HttpResponse::NoContent().finish()
+}
+
+/// Queues a job for every schedule that is due. With several API processes
+/// sharing Redis, each run is claimed by exactly one of them.
+async fn run_due_schedules(schedules: &ScheduleStore, job_store: &JobStore, queue: &JobQueue) {
+    let now = Utc::now();
+    for schedule in schedules.list().await {
+        if schedule.next_run_at > now {
+            continue;
+        }
+
+        let job_id = Uuid::new_v4().to_string();
+        let claimed = schedules
+            .update(&schedule.id, |schedule| {
+                if schedule.next_run_at > now {
+                    return None;
+                }
+                // A schedule missed while the server was down runs once, not
+                // once per missed occurrence.
+                schedule.next_run_at = match schedules::next_run(&schedule.cron, now) {
+                    Ok(next_run_at) => next_run_at,
+                    Err(e) => {
+                        error!(
+                            "Schedule {} has an invalid cron expression: {}",
+                            schedule.id, e
+                        );
+                        now + TimeDelta::days(365)
+                    }
+                };
+                schedule.last_run_at = Some(now);
+                schedule.last_job_id = Some(job_id.clone());
+                Some(schedule.clone())
+            })
+            .await
+            .flatten();
+        let Some(schedule) = claimed else {
+            continue;
+        };
+
+        let settings = schedule.settings_at(now);
+        let job = QueuedJob {
+            job_id: job_id.clone(),
+            repo_url: schedule.repo_url.clone(),
+            repo_urls: Vec::new(),
+            access_token: schedule.access_token.clone(),
+            settings: settings.clone(),
+            callback: None,
+            subscription: None,
+            source: RepositorySource::Clone,
+            vcs: None,
+        };
+        let status = queued_status(&schedule.repo_url, &settings, None);
+        if submit_job(job_store, queue, job, status).await.is_ok() {
+            info!(
+                "Queued job {} for schedule {}, next run at {}",
+                job_id, schedule.id, schedule.next_run_at
+            );
+        }
+    }
+}
+
+async fn update_job_status(job_store: &JobStore, job_id: &str, step: ProgressStep) {
+    job_store
+        .update(job_id, |job_status| job_status.step = step)
+        .await;
+}
+
+async fn set_job_state(job_store: &JobStore, job_id: &str, state: JobState) {
+    job_store
+        .update(job_id, |job_status| {
+            if !job_status.state.is_terminal() {
+                job_status.state = state;
+            }
+        })
+        .await;
+}
+
+async fn finish_job(job_store: &JobStore, job_id: &str, state: JobState, ttl: Duration) {
+    job_store
+        .update(job_id, |job_status| {
+            if !job_status.state.is_terminal() {
+                let now = Utc::now();
+                job_status.state = state;
+                job_status.finished_at = Some(now);
+                job_status.expires_at = Some(now + ttl);
+            }
+        })
+        .await;
+}
+
+async fn record_phase_duration(
+    job_store: &JobStore,
+    job_id: &str,
+    phase: JobPhase,
+    duration: Duration,
+) {
+    metrics::PHASE_DURATION
+        .with_label_values(&[phase.as_str()])
+        .observe(duration.as_secs_f64());
+
+    let millis = Some(duration.as_millis() as u64);
+    job_store
+        .update(job_id, |job_status| match phase {
+            JobPhase::Clone => job_status.timings.clone_ms = millis,
+            JobPhase::Analyze => job_status.timings.analyze_ms = millis,
+            JobPhase::Render => job_status.timings.render_ms = millis,
+        })
+        .await;
+}
+
+async fn set_total_duration(job_store: &JobStore, job_id: &str, duration: Duration) {
+    job_store
+        .update(job_id, |job_status| {
+            job_status.timings.total_ms = Some(duration.as_millis() as u64)
+        })
+        .await;
+}
+
+async fn set_repo_stats(job_store: &JobStore, job_id: &str, stats: RepoStats) {
+    job_store
+        .update(job_id, |job_status| job_status.stats = Some(stats.clone()))
+        .await;
+}
+
+async fn set_video_info(job_store: &JobStore, job_id: &str, video_info: VideoInfo) {
+    job_store
+        .update(job_id, |job_status| {
+            job_status.video = Some(video_info.clone())
+        })
+        .await;
+}
+
+async fn set_video_url(job_store: &JobStore, job_id: &str, video_url: &str) {
+    job_store
+        .update(job_id, |job_status| {
+            job_status.video_url = Some(video_url.to_string())
+        })
+        .await;
+}
+
+async fn set_image_url(job_store: &JobStore, job_id: &str, image: Image, url: &str) {
+    job_store
+        .update(job_id, |job_status| {
+            *job_status.image_url_mut(image) = Some(url.to_string())
+        })
+        .await;
+}
+
+#[instrument(skip_all)]
+fn clone_repository(
+    repo_url: &str,
     temp_dir: &Path,
-    seconds_per_day: f64,
+    github_token: Option<&str>,
+    vcs: VcsKind,
+    processes: &JobProcesses,
+) -> Result<(), GourceError> {
+    info!("Cloning {:?} repository: {}", vcs, repo_url);
+
+    let mut url = Url::parse(vcs::client_url(repo_url)).map_err(|_| GourceError::InvalidUrl)?;
+
+    if let Some(token) = github_token {
+        url.set_username("oauth2")
+            .map_err(|_| GourceError::InvalidUrl)?;
+        url.set_password(Some(token))
+            .map_err(|_| GourceError::InvalidUrl)?;
+    }
+
+    vcs.implementation()
+        .clone_repository(url.as_str(), temp_dir, processes)
+}
+
+/// Clones a repository that is already on this host: a bundle, a git
+/// directory or a working copy.
+fn clone_local(
+    source: &Path,
+    destination: &Path,
+    processes: &JobProcesses,
+) -> Result<(), GourceError> {
+    info!("Cloning local repository: {:?}", source);
+    let output = processes
+        .output(
+            Command::new("git")
+                .arg("clone")
+                .arg("--no-hardlinks")
+                .arg("--quiet")
+                .arg("--")
+                .arg(source)
+                .arg(destination),
+        )
+        .map_err(|_| GourceError::CloneFailed)?;
+    if !output.status.success() {
+        error!(
+            "Git clone failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(GourceError::CloneFailed);
+    }
+    Ok(())
+}
+
+#[instrument(skip_all)]
+fn count_commits(
+    checkouts: &[Checkout],
+    settings: &Option<GourceSettings>,
+) -> Result<HistoryStats, GourceError> {
+    let range = date_range(settings);
+    let mut stats = HistoryStats::default();
+    for checkout in checkouts {
+        info!(
+            "Counting days with commits and total commits in repository at: {:?}",
+            checkout.path
+        );
+        stats.merge(
+            checkout
+                .vcs
+                .implementation()
+                .history_stats(&checkout.path, &range)?,
+        );
+    }
+
+    if stats.is_empty() && range.is_bounded() {
+        return Err(GourceError::NoCommitsInRange);
+    }
+
+    Ok(stats)
+}
+
+/// Tags within `range` of every checkout, named after their repository in a
+/// combined render. Tags are only decoration, so a repository whose tags
+/// cannot be listed contributes none.
+fn repository_tags(checkouts: &[Checkout], range: &DateRange) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    for checkout in checkouts {
+        match checkout.vcs.implementation().tags(&checkout.path) {
+            Ok(repository_tags) => tags.extend(
+                repository_tags
+                    .into_iter()
+                    .filter(|tag| range.contains_time(tag.timestamp))
+                    .map(|tag| Tag {
+                        name: if checkouts.len() > 1 {
+                            format!("{} {}", checkout.name, tag.name)
+                        } else {
+                            tag.name
+                        },
+                        ..tag
+                    }),
+            ),
+            Err(e) => warn!("Failed to list the tags of {}: {}", checkout.name, e),
+        }
+    }
+    tags.sort_by_key(|tag| tag.timestamp);
+    tags
+}
+
+fn date_range(settings: &Option<GourceSettings>) -> DateRange {
+    settings
+        .as_ref()
+        .map_or_else(DateRange::default, |settings| DateRange {
+            start: settings.start_date,
+            stop: settings.stop_date,
+        })
+}
+
+/// Like `count_commits`, for an uploaded custom log, which is written to
+/// `sorted_log` in the order gource reads it.
+fn count_log_commits(
+    upload: &Path,
+    sorted_log: &Path,
+    settings: &Option<GourceSettings>,
+) -> Result<HistoryStats, GourceError> {
+    let range = date_range(settings);
+    let stats = custom_log::prepare(upload, sorted_log, &range).map_err(|e| {
+        error!("Failed to read the uploaded log: {}", e);
+        GourceError::InvalidUpload
+    })?;
+    if stats.is_empty() {
+        return Err(GourceError::NoCommitsInRange);
+    }
+    Ok(stats)
+}
+
+#[instrument(skip_all, fields(seconds_per_day, hide_filenames))]
+fn generate_gource_visualization(
+    source: &Path,
+    pacing: &Pacing,
     hide_filenames: bool,
     output_file: &Path,
     settings: &Option<GourceSettings>,
-    job_id: Option<&str>,
-    repo_url: Option<&str>,
+    composition: &Composition,
+    processes: &JobProcesses,
 ) -> Result<(), GourceError> {
-    let title = generate_repo_title(repo_url.unwrap_or(""));
-
+    let preview = settings.as_ref().and_then(|s| s.preview);
     let mut gource_command = format!(
-        "xvfb-run -a gource {} -1920x1200 \
+        "xvfb-run -a gource {} -{} \
         --seconds-per-day {} \
-        --auto-skip-seconds 0.001 \
+        --auto-skip-seconds {} \
         --max-user-speed 500 \
         --output-framerate 30 \
-        --multi-sampling \
+        {}\
         --bloom-intensity 0.35 \
         --user-scale 0.75 \
         --elasticity 0.01 \
         --background-colour 000000 \
         --font-size 20 \
-        --title \"{}\" \
+        --title {} \
         --dir-font-size {} \
         --file-font-size {} \
         --user-font-size {} \
         --stop-at-end",
-        temp_dir.to_str().unwrap(),
-        seconds_per_day,
-        title,
+        shell_quote(&source.to_string_lossy()),
+        if preview.is_some() {
+            preview::SIZE
+        } else {
+            VIDEO_SIZE
+        },
+        pacing.seconds_per_day,
+        pacing.auto_skip_seconds,
+        // Previews are drawn without antialiasing to render faster.
+        if preview.is_some() {
+            ""
+        } else {
+            "--multi-sampling "
+        },
+        shell_quote(&composition.title),
         settings.as_ref().map_or(11, |s| s.dir_font_size),
         settings.as_ref().map_or(10, |s| s.file_font_size),
         settings.as_ref().map_or(12, |s| s.user_font_size)
     );
 
+    // A combined render reads a merged log rather than a checkout.
+    if source.is_file() {
+        gource_command.push_str(" --log-format custom");
+    }
+
     let mut hide_elements = vec!["progress"];
     if hide_filenames {
         hide_elements.push("filenames");
@@ -637,149 +2706,548 @@ fn generate_gource_visualization(
         if !settings.show_dirnames {
             hide_elements.push("dirnames");
         }
+        if let Some(start_date) = settings.start_date {
+            gource_command.push_str(&format!(" --start-date \"{}\"", start_date));
+        }
+        if let Some(stop_date) = settings.stop_date {
+            gource_command.push_str(&format!(" --stop-date \"{} 23:59:59\"", stop_date));
+        }
+        if let (Some(caption_file), Some(captions)) = (&composition.captions, &settings.captions) {
+            gource_command.push_str(&format!(
+                " --caption-file {} --caption-size {} --caption-colour {} --caption-duration {}",
+                shell_quote(&caption_file.to_string_lossy()),
+                captions.size(),
+                captions.colour(),
+                captions.duration()
+            ));
+        }
     }
 
     if !hide_elements.is_empty() {
         gource_command.push_str(&format!(" --hide {}", hide_elements.join(",")));
     }
 
+    let composition_args = composition.ffmpeg_args().map_err(|e| {
+        error!("Failed to prepare the intro and outro cards: {}", e);
+        GourceError::GourceGenerationFailed
+    })?;
     gource_command.push_str(&format!(
         " -o - | \
-        ffmpeg -y -r 30 -f image2pipe -vcodec ppm -i - \
+        ffmpeg -y -r 30 -f image2pipe -vcodec ppm -i - {} \
         -vcodec libx264 -preset fast -crf 23 -movflags +faststart \
-        -pix_fmt yuv420p -vf \"pad=ceil(iw/2)*2:ceil(ih/2)*2\" \
+        -pix_fmt yuv420p \
         -acodec aac -b:a 128k -profile:v main \
-        {}",
-        output_file.to_str().unwrap()
+        {}{}",
+        composition_args,
+        preview.map_or(String::new(), |_| format!("-r {} ", preview::FRAME_RATE)),
+        shell_quote(&output_file.to_string_lossy())
     ));
 
-    log_message(
-        log::Level::Info,
-        &format!("Running gource command: {}", gource_command),
-        job_id,
-    );
+    info!("Running gource command: {}", gource_command);
 
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(&gource_command)
-        .output()
+    let output = processes
+        .output(Command::new("sh").arg("-c").arg(&gource_command))
         .map_err(|_| GourceError::GourceGenerationFailed)?;
 
     if !output.status.success() {
         let error_message = String::from_utf8_lossy(&output.stderr);
-        log_message(
-            log::Level::Error,
-            &format!("Gource generation failed: {}", error_message),
-            job_id,
-        );
+        error!("Gource generation failed: {}", error_message);
+        // The shell reports a child killed by SIGKILL or SIGTERM (e.g. by the
+        // OOM killer) as 128 + the signal number.
+        if output.status.signal().is_some() || matches!(output.status.code(), Some(137 | 143)) {
+            return Err(GourceError::RenderKilled);
+        }
         return Err(GourceError::GourceGenerationFailed);
     }
 
     Ok(())
 }
 
-fn generate_repo_title(repo_url: &str) -> String {
-    let url = Url::parse(repo_url).unwrap_or_else(|_| Url::parse("https://example.com").unwrap());
+fn probe_video(video_path: &Path, processes: &JobProcesses) -> Option<VideoInfo> {
+    let size_bytes = fs::metadata(video_path).ok()?.len();
+
+    let duration_seconds = processes
+        .output(
+            Command::new("ffprobe")
+                .args([
+                    "-v",
+                    "error",
+                    "-show_entries",
+                    "format=duration",
+                    "-of",
+                    "default=noprint_wrappers=1:nokey=1",
+                ])
+                .arg(video_path),
+        )
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .trim()
+                .parse::<f64>()
+                .ok()
+        });
+
+    Some(VideoInfo {
+        size_bytes,
+        duration_seconds,
+        target_duration_seconds: None,
+        playback_speed: None,
+    })
+}
+
+/// Where `fit_video` writes the retimed video before it replaces the render.
+fn fitted_path(video_path: &Path) -> PathBuf {
+    video_path.with_extension("fit.mp4")
+}
+
+/// The speed a `duration` long video must play at to last `target`, or
+/// `None` when it is already within `tolerance` of it.
+fn retime_speed(duration: f64, target: f64, tolerance: f64) -> Option<f64> {
+    ((duration - target).abs() > tolerance).then(|| duration / target)
+}
+
+/// Probes the rendered video and, when a target duration was requested and
+/// the video is further than `tolerance` from it, retimes it in place with
+/// ffmpeg's `setpts` filter.
+fn fit_video(
+    video_path: &Path,
+    target_duration: Option<f64>,
+    tolerance: f64,
+    processes: &JobProcesses,
+) -> Result<Option<VideoInfo>, GourceError> {
+    let Some(target) = target_duration else {
+        return Ok(probe_video(video_path, processes));
+    };
+    let Some(duration) = probe_video(video_path, processes).and_then(|info| info.duration_seconds)
+    else {
+        error!("Could not measure the rendered video to fit it");
+        return Err(GourceError::VideoFitFailed);
+    };
+
+    let playback_speed = retime_speed(duration, target, tolerance);
+    if let Some(speed) = playback_speed {
+        info!(
+            "Retiming video from {}s to {}s ({}x speed)",
+            duration, target, speed
+        );
+        let fitted = fitted_path(video_path);
+        // The frame rate is fixed again after setpts so a slowed down video
+        // repeats frames rather than playing at a lower rate.
+        let output = processes
+            .output(
+                Command::new("ffmpeg")
+                    .args(["-y", "-v", "error", "-i"])
+                    .arg(video_path)
+                    .args(["-filter:v", &format!("setpts={}*PTS", target / duration)])
+                    .args(["-r", "30", "-t", &target.to_string(), "-an"])
+                    .args(["-vcodec", "libx264", "-preset", "fast", "-crf", "23"])
+                    .args(["-pix_fmt", "yuv420p", "-profile:v", "main"])
+                    .args(["-movflags", "+faststart"])
+                    .arg(&fitted),
+            )
+            .map_err(|_| GourceError::VideoFitFailed)?;
+        if !output.status.success() {
+            error!(
+                "Retiming the video failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            let _ = fs::remove_file(&fitted);
+            return Err(GourceError::VideoFitFailed);
+        }
+        fs::rename(&fitted, video_path).map_err(|e| {
+            error!("Failed to replace the video with the retimed one: {}", e);
+            GourceError::VideoFitFailed
+        })?;
+    }
+
+    let video_info = probe_video(video_path, processes).map(|info| VideoInfo {
+        target_duration_seconds: Some(target),
+        playback_speed,
+        ..info
+    });
+    if let Some(actual) = video_info.as_ref().and_then(|info| info.duration_seconds) {
+        if (actual - target).abs() > tolerance {
+            warn!(
+                "Fitted video is {}s long, more than {}s from the {}s target",
+                actual, tolerance, target
+            );
+        }
+    }
+    Ok(video_info)
+}
+
+/// `owner/name` of every repository, joined with `+`.
+fn repository_names(repo_urls: &[String]) -> String {
+    let names: Option<Vec<String>> = repo_urls
+        .iter()
+        .map(|repo_url| {
+            repo_owner_and_name(repo_url).map(|(owner, name)| format!("{}/{}", owner, name))
+        })
+        .collect();
+    match names {
+        Some(names) if !names.is_empty() => names.join(" + "),
+        _ => "Repository Visualization".to_string(),
+    }
+}
+
+fn generate_repo_title(names: &str, suffix: &str) -> String {
+    if suffix.is_empty() {
+        names.to_string()
+    } else {
+        format!("{} ⋅ {}", names, suffix)
+    }
+}
+
+fn repo_owner_and_name(repo_url: &str) -> Option<(String, String)> {
+    let url = Url::parse(repo_url).ok()?;
 
-    let segments: Vec<&str> = url.path_segments().map(|c| c.collect()).unwrap_or_default();
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|c| c.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
 
     if segments.len() >= 2 {
-        format!(
-            "{}/{} ⋅ gitmotion.app",
-            segments[segments.len() - 2],
-            segments[segments.len() - 1]
-        )
+        Some((
+            segments[segments.len() - 2].to_string(),
+            segments[segments.len() - 1].to_string(),
+        ))
     } else {
-        "Repository Visualization ⋅ gitmotion.app".to_string()
+        None
     }
 }
 
 fn check_dependencies() -> Result<(), String> {
-    let dependencies = vec!["git", "gource", "ffmpeg", "xvfb-run"];
+    let dependencies = vec!["git", "gource", "ffmpeg", "ffprobe", "xvfb-run"];
     for dep in dependencies {
-        if let Err(_) = Command::new(dep).arg("--version").output() {
+        if Command::new(dep).arg("--version").output().is_err() {
             return Err(format!("{} is not available", dep));
         }
     }
     Ok(())
 }
 
-async fn serve_video(req: HttpRequest, job_id: web::Path<String>) -> Result<HttpResponse> {
-    let video_path = PathBuf::from(format!("/gource_videos/gource_{}.mp4", job_id));
-    if video_path.exists() {
-        Ok(NamedFile::open(video_path)?
-            .set_content_type(mime::APPLICATION_OCTET_STREAM)
-            .into_response(&req))
-    } else {
-        Ok(HttpResponse::NotFound().json(serde_json::json!({
+async fn serve_video(
+    req: HttpRequest,
+    job_id: web::Path<String>,
+    storage: web::Data<dyn ArtifactStore>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse> {
+    video_response(&req, storage.get_ref(), &config, &job_id).await
+}
+
+async fn serve_stills(
+    req: HttpRequest,
+    job_id: web::Path<String>,
+    storage: web::Data<dyn ArtifactStore>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse> {
+    image_response(&req, storage.get_ref(), &config, &job_id, Image::Stills).await
+}
+
+async fn serve_poster(
+    req: HttpRequest,
+    job_id: web::Path<String>,
+    storage: web::Data<dyn ArtifactStore>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse> {
+    image_response(&req, storage.get_ref(), &config, &job_id, Image::Poster).await
+}
+
+async fn serve_contact_sheet(
+    req: HttpRequest,
+    job_id: web::Path<String>,
+    storage: web::Data<dyn ArtifactStore>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse> {
+    image_response(
+        &req,
+        storage.get_ref(),
+        &config,
+        &job_id,
+        Image::ContactSheet,
+    )
+    .await
+}
+
+async fn image_response(
+    req: &HttpRequest,
+    storage: &dyn ArtifactStore,
+    config: &Config,
+    job_id: &str,
+    image: Image,
+) -> Result<HttpResponse> {
+    let key = storage::image_key(job_id, image);
+    match artifact_response(req, storage, config, &key).await? {
+        Some(response) => Ok(response),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Image not found"
+        }))),
+    }
+}
+
+async fn video_response(
+    req: &HttpRequest,
+    storage: &dyn ArtifactStore,
+    config: &Config,
+    job_id: &str,
+) -> Result<HttpResponse> {
+    let key = storage::video_key(job_id);
+    match artifact_response(req, storage, config, &key).await? {
+        Some(response) => Ok(response),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
             "error": "Video not found"
-        })))
+        }))),
+    }
+}
+
+/// Serves a stored artifact from disk, or redirects to it for backends that
+/// can presign URLs. Files on disk are served with their content type,
+/// byte ranges and `ETag`/`Last-Modified` validators, inline unless the
+/// request has `?download=1`.
+async fn artifact_response(
+    req: &HttpRequest,
+    storage: &dyn ArtifactStore,
+    config: &Config,
+    key: &str,
+) -> Result<Option<HttpResponse>> {
+    let download = web::Query::<ArtifactQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().download)
+        .is_some_and(|download| matches!(download.as_str(), "1" | "true"));
+    let Some(path) = storage.local_path(key) else {
+        let expires_in = config.video_ttl();
+        let url = if download {
+            storage.presigned_download_url(key, expires_in).await
+        } else {
+            storage.presigned_url(key, expires_in).await
+        };
+        return Ok(url.ok().flatten().map(|url| {
+            HttpResponse::Found()
+                .insert_header((header::LOCATION, url))
+                .finish()
+        }));
+    };
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let content_type: mime::Mime = storage::content_type(key)
+        .parse()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+    let disposition = ContentDisposition {
+        disposition: if download {
+            DispositionType::Attachment
+        } else {
+            DispositionType::Inline
+        },
+        parameters: vec![DispositionParam::Filename(key.to_string())],
+    };
+    let response = NamedFile::open(&path)?
+        .set_content_type(content_type)
+        .set_content_disposition(disposition)
+        .use_etag(true)
+        .use_last_modified(true)
+        .into_response(req);
+    let ranged = matches!(
+        response.status(),
+        StatusCode::PARTIAL_CONTENT | StatusCode::RANGE_NOT_SATISFIABLE
+    );
+    if !ranged || if_range_matches(req, &response) {
+        return Ok(Some(response));
+    }
+
+    // actix-files ignores If-Range, so a client resuming from a copy that has
+    // since changed gets the whole new file rather than a part of it.
+    let file = tokio::fs::File::open(&path).await?;
+    let length = file.metadata().await?.len();
+    let mut full = HttpResponse::Ok();
+    for name in [
+        header::CONTENT_TYPE,
+        header::CONTENT_DISPOSITION,
+        header::ETAG,
+        header::LAST_MODIFIED,
+        header::ACCEPT_RANGES,
+    ] {
+        if let Some(value) = response.headers().get(&name) {
+            full.insert_header((name, value.clone()));
+        }
     }
+    Ok(Some(
+        full.no_chunking(length).streaming(ReaderStream::new(file)),
+    ))
 }
 
-async fn clear_gource_videos(job_store: web::Data<JobStore>) {
-    let path = Path::new("/gource_videos");
-    let one_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+/// Whether a range request's `If-Range`, if it has one, names the current
+/// `ETag` or `Last-Modified` of `response`. Weak tags never match.
+fn if_range_matches(req: &HttpRequest, response: &HttpResponse) -> bool {
+    let Some(if_range) = req
+        .headers()
+        .get(header::IF_RANGE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return true;
+    };
+    let validator = if if_range.starts_with('"') {
+        header::ETAG
+    } else if if_range.starts_with("W/") {
+        return false;
+    } else {
+        header::LAST_MODIFIED
+    };
+    response
+        .headers()
+        .get(validator)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|current| current == if_range)
+}
 
-    let mut jobs_to_remove = Vec::new();
+async fn remove_video(storage: &dyn ArtifactStore, key: &str) {
+    match storage.delete(key).await {
+        Ok(()) => info!("Removed video: {}", key),
+        Err(e) => error!("Failed to remove video {}: {}", key, e),
+    }
+}
 
-    if let Err(e) = fs::read_dir(path).and_then(|entries| {
-        entries
-            .filter_map(Result::ok)
-            .filter(|entry| {
-                entry.path().extension().map_or(false, |ext| ext == "mp4")
-                    && entry.metadata().map_or(false, |m| m.is_file())
-            })
-            .try_for_each(|entry| {
-                let file_path = entry.path();
-                if entry
-                    .metadata()
-                    .and_then(|m| m.created())
-                    .map_or(false, |created| created < one_hour_ago)
-                {
-                    fs::remove_file(&file_path).map_err(|e| {
-                        log_message(
-                            log::Level::Error,
-                            &format!("Failed to remove file {:?}: {}", file_path, e),
-                            None,
-                        );
-                        e
-                    })?;
-                    log_message(
-                        log::Level::Info,
-                        &format!("Removed file: {:?}", file_path),
-                        None,
-                    );
-
-                    if let Some(file_name) = file_path.file_name() {
-                        if let Some(file_name_str) = file_name.to_str() {
-                            if let Some(job_id) = file_name_str
-                                .strip_prefix("gource_")
-                                .and_then(|s| s.strip_suffix(".mp4"))
-                            {
-                                jobs_to_remove.push(job_id.to_string());
-                            }
-                        }
-                    }
-                }
-                Ok(())
-            })
-    }) {
-        log_message(
-            log::Level::Error,
-            &format!("Failed to process directory: {}", e),
-            None,
-        );
+/// Removes the repository uploaded for a job and the images rendered from
+/// its video, if there were any.
+async fn remove_job_files(storage: &dyn ArtifactStore, job_id: &str) {
+    if let Err(e) = storage.delete(&storage::upload_key(job_id)).await {
+        error!("Failed to remove upload of job {}: {}", job_id, e);
+    }
+    for image in Image::ALL {
+        if let Err(e) = storage.delete(&storage::image_key(job_id, image)).await {
+            error!("Failed to remove {} of job {}: {}", image.name(), job_id, e);
+        }
     }
+}
 
-    let mut store = job_store.lock().await;
-    for job_id in jobs_to_remove {
-        if store.remove(&job_id).is_some() {
-            log_message(
-                log::Level::Info,
-                &format!("Removed job status for job_id: {}", job_id),
-                None,
-            );
+/// Lists the rendered videos held by the artifact store.
+async fn list_videos(storage: &dyn ArtifactStore) -> Vec<storage::StoredArtifact> {
+    match storage.list().await {
+        Ok(artifacts) => artifacts
+            .into_iter()
+            .filter(|artifact| storage::job_id_from_key(&artifact.key).is_some())
+            .collect(),
+        Err(e) => {
+            error!("Failed to list videos: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Lists the artifacts held for jobs: their videos, uploads and images.
+async fn list_job_artifacts(storage: &dyn ArtifactStore) -> Vec<storage::StoredArtifact> {
+    match storage.list().await {
+        Ok(artifacts) => artifacts
+            .into_iter()
+            .filter(|artifact| storage::job_id_from_artifact_key(&artifact.key).is_some())
+            .collect(),
+        Err(e) => {
+            error!("Failed to list artifacts: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+async fn clear_gource_videos(
+    job_store: web::Data<JobStore>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn ArtifactStore>,
+) {
+    let now = Utc::now();
+
+    let mut known_jobs = HashSet::new();
+    for (job_id, status) in job_store.list().await {
+        if !status.pinned && status.expires_at.is_some_and(|e| e <= now) {
+            remove_video(storage.get_ref(), &storage::video_key(&job_id)).await;
+            remove_job_files(storage.get_ref(), &job_id).await;
+            job_store.remove(&job_id).await;
+            info!("Removed job status for job_id: {}", job_id);
+        } else {
+            known_jobs.insert(job_id);
+        }
+    }
+
+    // Videos without a job record (e.g. left over from a previous run) expire
+    // based on their modification time.
+    let orphan_cutoff = SystemTime::now() - config.video_ttl();
+    for video in list_videos(storage.get_ref()).await {
+        let has_job =
+            storage::job_id_from_key(&video.key).is_some_and(|id| known_jobs.contains(id));
+        if !has_job && video.modified < orphan_cutoff {
+            remove_video(storage.get_ref(), &video.key).await;
+        }
+    }
+    if let Ok(artifacts) = storage.list().await {
+        for artifact in artifacts {
+            let Some(job_id) = storage::job_id_from_upload_key(&artifact.key)
+                .or_else(|| storage::job_id_from_image_key(&artifact.key))
+            else {
+                continue;
+            };
+            if !known_jobs.contains(job_id) && artifact.modified < orphan_cutoff {
+                remove_job_files(storage.get_ref(), job_id).await;
+            }
+        }
+    }
+
+    if config.cleanup.max_disk_bytes.is_some() {
+        enforce_disk_quota(&job_store, &config, storage.get_ref()).await;
+    }
+}
+
+/// Evicts the files of the oldest unpinned jobs until the stored videos,
+/// uploads and images fit within `cleanup.max_disk_bytes`.
+async fn enforce_disk_quota(job_store: &JobStore, config: &Config, storage: &dyn ArtifactStore) {
+    let Some(max_disk_bytes) = config.cleanup.max_disk_bytes else {
+        return;
+    };
+
+    let artifacts = list_job_artifacts(storage).await;
+    let mut total_bytes: u64 = artifacts.iter().map(|artifact| artifact.size).sum();
+    if total_bytes <= max_disk_bytes {
+        return;
+    }
+
+    // A job's files are evicted together, so they are counted together.
+    let mut job_files: HashMap<String, (u64, SystemTime)> = HashMap::new();
+    for artifact in artifacts {
+        let Some(job_id) = storage::job_id_from_artifact_key(&artifact.key) else {
+            continue;
+        };
+        let (size, modified) = job_files
+            .entry(job_id.to_string())
+            .or_insert((0, SystemTime::UNIX_EPOCH));
+        *size += artifact.size;
+        *modified = (*modified).max(artifact.modified);
+    }
+
+    let jobs: HashMap<String, JobStatus> = job_store.list().await.into_iter().collect();
+    let mut candidates: Vec<(String, u64, SystemTime, bool)> = job_files
+        .into_iter()
+        .filter_map(|(job_id, (size, modified))| match jobs.get(&job_id) {
+            Some(status) if status.pinned || !status.state.is_terminal() => None,
+            Some(status) => Some((job_id, size, status.created_at.into(), true)),
+            None => Some((job_id, size, modified, false)),
+        })
+        .collect();
+    candidates.sort_by_key(|(_, _, age, _)| *age);
+
+    for (job_id, size, _, has_status) in candidates {
+        if total_bytes <= max_disk_bytes {
+            break;
+        }
+        info!(
+            "Stored artifacts use {} bytes (cap {}), evicting job {}",
+            total_bytes, max_disk_bytes, job_id
+        );
+        remove_video(storage, &storage::video_key(&job_id)).await;
+        remove_job_files(storage, &job_id).await;
+        total_bytes = total_bytes.saturating_sub(size);
+        if has_status {
+            job_store.remove(&job_id).await;
+            info!("Removed job status for job_id: {}", job_id);
         }
     }
 }
@@ -788,65 +3256,151 @@ async fn clear_gource_videos(job_store: web::Data<JobStore>) {
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
 
-    // Custom logger configuration
-    Builder::new()
-        .format(|buf, record| {
-            writeln!(
-                buf,
-                "{} {} [{}] {}",
-                buf.timestamp(),
-                record.level(),
-                record.target(),
-                record.args()
-            )
-        })
-        .filter(None, LevelFilter::Info)
-        .init();
+    let config = Config::load(Cli::parse()).map_err(|e| std::io::Error::other(e.to_string()))?;
+    let _telemetry = telemetry::init(
+        config.logging.format,
+        config.logging.otlp_endpoint.as_deref(),
+    );
 
-    let output_dir = Path::new("/gource_videos");
-    if !output_dir.exists() {
-        fs::create_dir_all(output_dir)?;
-    }
+    let storage: web::Data<dyn ArtifactStore> = web::Data::from(
+        storage::from_config(&config.storage).map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
 
-    if let Err(e) = check_dependencies() {
-        log_message(
-            log::Level::Error,
-            &format!("Dependency check failed: {}", e),
-            None,
-        );
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
+    if config.mode.renders() {
+        if let Err(e) = check_dependencies() {
+            error!("Dependency check failed: {}", e);
+            return Err(std::io::Error::other(e));
+        }
+
+        if config.secret_key.is_none() {
+            info!("SECRET_KEY not set, requests with access tokens will be rejected");
+        }
     }
 
-    let job_store = web::Data::new(JobStore::default());
+    metrics::init();
+
+    let redis = if config.redis.url.is_some() {
+        let client =
+            queue::redis_client(&config.redis).map_err(|e| std::io::Error::other(e.to_string()))?;
+        let connection = client
+            .get_connection_manager()
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        info!("Sharing jobs through Redis");
+        Some((client, connection))
+    } else {
+        None
+    };
+    let job_store = match &redis {
+        Some((_, connection)) => JobStore::Redis(connection.clone()),
+        None => JobStore::memory(),
+    };
+    let subscriptions = match &redis {
+        Some((_, connection)) => SubscriptionStore::Redis(connection.clone()),
+        None => SubscriptionStore::memory(),
+    };
+    let schedules = match &redis {
+        Some((_, connection)) => ScheduleStore::Redis(connection.clone()),
+        None => ScheduleStore::memory(),
+    };
+    let mode = config.mode;
+    let config = web::Data::new(config);
+    let shutdown = Shutdown::default();
+    let webhooks = WebhookSender::new(job_store.clone(), config.clone(), shutdown.clone())
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    let runner = JobRunner {
+        job_store: job_store.clone(),
+        config: config.clone(),
+        storage: storage.clone(),
+        shutdown: shutdown.clone(),
+        webhooks: webhooks.clone(),
+        subscriptions: subscriptions.clone(),
+        runtime: tokio::runtime::Handle::current(),
+    };
+    let queue = match &redis {
+        Some((_, connection)) => JobQueue::Redis(connection.clone()),
+        None => JobQueue::Local(runner.clone()),
+    };
+
+    // With Redis the queue survives restarts by itself.
+    let pending_jobs_file = redis.is_none().then(|| config.pending_jobs_file());
+    if let Some(path) = &pending_jobs_file {
+        shutdown::restore_pending_jobs(&job_store, &queue, path).await;
+    }
 
-    // Set up periodic task to clear gource_videos
-    let job_store_clone = job_store.clone();
-    tokio::spawn(async move {
-        let mut interval = interval(Duration::from_secs(3600)); // 1 hour
-        loop {
-            interval.tick().await;
-            clear_gource_videos(job_store_clone.clone()).await;
+    if let Some((client, connection)) = &redis {
+        if mode.renders() {
+            tokio::spawn(queue::run_worker(
+                client.clone(),
+                connection.clone(),
+                runner,
+                config.worker.clone(),
+            ));
         }
-    });
+    }
 
-    let api_port = env::var("API_PORT").unwrap_or_else(|_| {
-        log_message(
-            log::Level::Info,
-            "API_PORT not set, using default port 8081",
-            None,
-        );
-        "8081".to_string()
-    });
-    log_message(
-        log::Level::Info,
-        &format!("API port set to: {}", api_port),
-        None,
-    );
+    let drain_timeout = config.drain_timeout();
+    let job_store = web::Data::new(job_store);
+    let queue = web::Data::new(queue);
+    let webhooks = web::Data::new(webhooks);
+    let subscriptions = web::Data::new(subscriptions);
+    let schedules = web::Data::new(schedules);
+    let server_job_store = job_store.clone();
+
+    if mode.renders() {
+        // Previews keep their checkouts on the host that rendered them.
+        let config = config.clone();
+        tokio::spawn(async move {
+            let mut interval = interval(config.cleanup_interval());
+            loop {
+                interval.tick().await;
+                let config = config.clone();
+                let _ = tokio::task::spawn_blocking(move || {
+                    preview::remove_expired_checkouts(&config.previews, config.video_ttl())
+                })
+                .await;
+            }
+        });
+    }
+
+    if mode.serves_api() {
+        // Set up periodic task to clear gource_videos
+        let job_store_clone = job_store.clone();
+        let config_clone = config.clone();
+        let storage_clone = storage.clone();
+        tokio::spawn(async move {
+            let mut interval = interval(config_clone.cleanup_interval());
+            loop {
+                interval.tick().await;
+                clear_gource_videos(
+                    job_store_clone.clone(),
+                    config_clone.clone(),
+                    storage_clone.clone(),
+                )
+                .await;
+            }
+        });
+
+        let schedules = schedules.clone();
+        let job_store = job_store.clone();
+        let queue = queue.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let mut interval = interval(schedules::POLL_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.draining() => break,
+                }
+                run_due_schedules(&schedules, &job_store, &queue).await;
+            }
+        });
+    }
 
-    log_message(
-        log::Level::Info,
-        &format!("Starting server at http://0.0.0.0:{}", api_port),
-        None,
+    let bind_address = (config.server.host.clone(), config.server.port);
+    info!(
+        "Starting server in {:?} mode at http://{}:{}",
+        mode, bind_address.0, bind_address.1
     );
     HttpServer::new(move || {
         let cors = Cors::default()
@@ -857,18 +3411,528 @@ async fn main() -> std::io::Result<()> {
 
         App::new()
             .wrap(cors)
-            .app_data(job_store.clone())
-            .service(web::resource("/start-gource").route(web::post().to(start_gource)))
-            .service(web::resource("/job-status/{job_id}").route(web::get().to(get_job_status)))
-            .service(web::resource("/video/{job_id}").route(web::get().to(serve_video)))
+            .app_data(server_job_store.clone())
+            .app_data(queue.clone())
+            .app_data(webhooks.clone())
+            .app_data(subscriptions.clone())
+            .app_data(schedules.clone())
+            .app_data(config.clone())
+            .app_data(storage.clone())
             .service(web::resource("/health").route(web::get().to(health_check)))
-            .service(web::resource("/stop/{job_id}").route(web::get().to(stop_job)))
+            .service(web::resource("/metrics").route(web::get().to(metrics_endpoint)))
+            .configure(|cfg| {
+                // Workers only expose health and metrics.
+                if mode.serves_api() {
+                    api_routes(cfg);
+                }
+            })
     })
-    .bind(format!("0.0.0.0:{}", api_port))?
+    .bind(bind_address)?
     .run()
-    .await
+    .await?;
+
+    info!("Server stopped, shutting down");
+    shutdown.drain(drain_timeout).await;
+    if let Some(path) = &pending_jobs_file {
+        shutdown::save_pending_jobs(&job_store, path).await;
+    }
+    Ok(())
+}
+
+fn api_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/start-gource").route(web::post().to(start_gource)))
+        .service(web::resource("/start-gource/upload").route(web::post().to(start_gource_upload)))
+        .service(web::resource("/start-gource/local").route(web::post().to(start_gource_local)))
+        .service(web::resource("/job-status/{job_id}").route(web::get().to(get_job_status)))
+        .service(web::resource("/jobs").route(web::get().to(list_jobs)))
+        .service(web::resource("/jobs/{job_id}/retention").route(web::post().to(update_retention)))
+        .service(web::resource("/jobs/{job_id}/retry").route(web::post().to(retry_job)))
+        .service(web::resource("/jobs/{job_id}/promote").route(web::post().to(promote_preview)))
+        .service(web::resource("/video/{job_id}").route(web::get().to(serve_video)))
+        .service(web::resource("/stills/{job_id}").route(web::get().to(serve_stills)))
+        .service(web::resource("/poster/{job_id}").route(web::get().to(serve_poster)))
+        .service(web::resource("/contact-sheet/{job_id}").route(web::get().to(serve_contact_sheet)))
+        .service(web::resource("/stop/{job_id}").route(web::get().to(stop_job)))
+        .service(
+            web::resource("/subscriptions")
+                .route(web::get().to(list_subscriptions))
+                .route(web::post().to(create_subscription)),
+        )
+        .service(
+            web::resource("/subscriptions/{owner}/{name}")
+                .route(web::delete().to(delete_subscription)),
+        )
+        .service(
+            web::resource("/schedules")
+                .route(web::get().to(list_schedules))
+                .route(web::post().to(create_schedule)),
+        )
+        .service(web::resource("/schedules/{id}").route(web::delete().to(delete_schedule)))
+        .service(web::resource("/webhooks/github").route(web::post().to(github_webhook)))
+        .service(
+            web::resource("/repos/{owner}/{name}/latest.mp4").route(web::get().to(latest_video)),
+        );
 }
 
 async fn health_check() -> impl Responder {
     HttpResponse::Ok().json(serde_json::json!({"status": "healthy"}))
 }
+
+async fn metrics_endpoint(
+    storage: web::Data<dyn ArtifactStore>,
+    queue: web::Data<JobQueue>,
+) -> impl Responder {
+    if let Some(depth) = queue.depth().await {
+        metrics::QUEUE_DEPTH.set(depth);
+    }
+    let stored_bytes = list_job_artifacts(storage.get_ref())
+        .await
+        .iter()
+        .map(|artifact| artifact.size)
+        .sum();
+    let body = metrics::render(stored_bytes);
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use std::sync::Arc;
+
+    const VIDEO_BYTES: usize = 1024;
+
+    fn video() -> Vec<u8> {
+        (0..VIDEO_BYTES).map(|byte| byte as u8).collect()
+    }
+
+    /// A video and a poster for job `test`.
+    fn artifacts() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(storage::video_key("test")), video()).unwrap();
+        fs::write(
+            dir.path().join(storage::image_key("test", Image::Poster)),
+            b"png bytes",
+        )
+        .unwrap();
+        dir
+    }
+
+    async fn get(
+        dir: &Path,
+        uri: &str,
+        headers: &[(header::HeaderName, &str)],
+    ) -> actix_web::dev::ServiceResponse {
+        let store = storage::LocalStore::new(dir.to_path_buf()).unwrap();
+        let store: web::Data<dyn ArtifactStore> =
+            web::Data::from(Arc::new(store) as Arc<dyn ArtifactStore>);
+        let app = test::init_service(
+            App::new()
+                .app_data(store)
+                .app_data(web::Data::new(Config::default()))
+                .configure(api_routes),
+        )
+        .await;
+        let mut request = test::TestRequest::get().uri(uri);
+        for (name, value) in headers {
+            request = request.insert_header((name.clone(), *value));
+        }
+        test::call_service(&app, request.to_request()).await
+    }
+
+    /// A runner for jobs kept in memory, storing artifacts in `dir`.
+    pub(crate) fn runner(dir: &Path) -> JobRunner {
+        let mut config = Config::default();
+        config.storage.video_dir = dir.to_path_buf();
+        let config = web::Data::new(config);
+        let store = storage::LocalStore::new(dir.to_path_buf()).unwrap();
+        let job_store = JobStore::memory();
+        let shutdown = Shutdown::default();
+        JobRunner {
+            job_store: job_store.clone(),
+            config: config.clone(),
+            storage: web::Data::from(Arc::new(store) as Arc<dyn ArtifactStore>),
+            shutdown: shutdown.clone(),
+            webhooks: WebhookSender::new(job_store, config, shutdown).unwrap(),
+            subscriptions: SubscriptionStore::memory(),
+            runtime: tokio::runtime::Handle::current(),
+        }
+    }
+
+    fn header(response: &actix_web::dev::ServiceResponse, name: header::HeaderName) -> &str {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    }
+
+    #[actix_web::test]
+    async fn retries_only_transient_failures() {
+        for error in [
+            GourceError::CloneNetworkError,
+            GourceError::RenderKilled,
+            GourceError::StorageFailed,
+        ] {
+            assert!(error.is_retryable(), "{}", error.kind());
+        }
+        for error in [
+            GourceError::InvalidUrl,
+            GourceError::CloneFailed,
+            GourceError::InvalidUpload,
+            GourceError::NoCommitsInRange,
+            GourceError::GourceGenerationFailed,
+            GourceError::DecryptionFailed,
+            GourceError::VideoFitFailed,
+        ] {
+            assert!(!error.is_retryable(), "{}", error.kind());
+        }
+    }
+
+    #[actix_web::test]
+    async fn lists_jobs_only_to_admins() {
+        let job_store = JobStore::memory();
+        let settings = GourceSettings::default();
+        job_store
+            .insert(
+                "test",
+                queued_status("https://github.com/a/b", &settings, None),
+            )
+            .await;
+        let config = Config {
+            admin_token: Some("admin".to_string()),
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(job_store))
+                .app_data(web::Data::new(config))
+                .configure(api_routes),
+        )
+        .await;
+
+        for (authorization, expected) in [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some("Bearer wrong"), StatusCode::UNAUTHORIZED),
+            (Some("Bearer admin"), StatusCode::OK),
+        ] {
+            let mut request = test::TestRequest::get().uri("/jobs");
+            if let Some(authorization) = authorization {
+                request = request.insert_header((header::AUTHORIZATION, authorization));
+            }
+            let response = test::call_service(&app, request.to_request()).await;
+            assert_eq!(response.status(), expected, "{:?}", authorization);
+        }
+    }
+
+    #[actix_web::test]
+    async fn changes_retention_only_for_admins() {
+        let job_store = JobStore::memory();
+        let settings = GourceSettings::default();
+        let mut status = queued_status("https://github.com/a/b", &settings, None);
+        status.state = JobState::Completed;
+        status.expires_at = Some(Utc::now() + TimeDelta::hours(1));
+        job_store.insert("test", status).await;
+        let config = Config {
+            admin_token: Some("admin".to_string()),
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(job_store.clone()))
+                .app_data(web::Data::new(config))
+                .configure(api_routes),
+        )
+        .await;
+
+        for (authorization, expected, pinned) in [
+            (None, StatusCode::UNAUTHORIZED, false),
+            (Some("Bearer wrong"), StatusCode::UNAUTHORIZED, false),
+            (Some("Bearer admin"), StatusCode::OK, true),
+        ] {
+            let mut request = test::TestRequest::post()
+                .uri("/jobs/test/retention")
+                .set_json(serde_json::json!({ "pinned": true }));
+            if let Some(authorization) = authorization {
+                request = request.insert_header((header::AUTHORIZATION, authorization));
+            }
+            let response = test::call_service(&app, request.to_request()).await;
+            assert_eq!(response.status(), expected, "{:?}", authorization);
+            assert_eq!(job_store.get("test").await.unwrap().pinned, pinned);
+        }
+    }
+
+    #[actix_web::test]
+    async fn disk_quota_evicts_whole_jobs_including_uploads_and_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage::LocalStore::new(dir.path().to_path_buf()).unwrap();
+        let job_store = JobStore::memory();
+        let mut config = Config::default();
+        config.cleanup.max_disk_bytes = Some(1300);
+
+        let settings = GourceSettings::default();
+        for (job_id, video_bytes, upload_bytes, state, age) in [
+            ("old", 100, 400, JobState::Completed, 60),
+            ("new", 100, 100, JobState::Completed, 30),
+            ("queued", 0, 500, JobState::Queued, 90),
+        ] {
+            if video_bytes > 0 {
+                fs::write(
+                    dir.path().join(storage::video_key(job_id)),
+                    vec![0; video_bytes],
+                )
+                .unwrap();
+            }
+            fs::write(
+                dir.path().join(storage::upload_key(job_id)),
+                vec![0; upload_bytes],
+            )
+            .unwrap();
+            fs::write(
+                dir.path().join(storage::image_key(job_id, Image::Poster)),
+                vec![0; 100],
+            )
+            .unwrap();
+            let mut status = queued_status("https://github.com/a/b", &settings, None);
+            status.state = state;
+            status.created_at = Utc::now() - TimeDelta::minutes(age);
+            job_store.insert(job_id, status).await;
+        }
+
+        enforce_disk_quota(&job_store, &config, &storage).await;
+
+        assert!(!dir.path().join(storage::video_key("old")).exists());
+        assert!(!dir.path().join(storage::upload_key("old")).exists());
+        assert!(!dir
+            .path()
+            .join(storage::image_key("old", Image::Poster))
+            .exists());
+        assert!(job_store.get("old").await.is_none());
+        for job_id in ["new", "queued"] {
+            assert!(dir.path().join(storage::upload_key(job_id)).exists());
+            assert!(job_store.get(job_id).await.is_some());
+        }
+    }
+
+    #[actix_web::test]
+    async fn serves_videos_inline_with_validators() {
+        let dir = artifacts();
+        let response = get(dir.path(), "/video/test", &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, header::CONTENT_TYPE), "video/mp4");
+        assert_eq!(
+            header(&response, header::CONTENT_DISPOSITION),
+            "inline; filename=\"gource_test.mp4\""
+        );
+        assert_eq!(header(&response, header::ACCEPT_RANGES), "bytes");
+        assert!(!header(&response, header::ETAG).is_empty());
+        assert!(!header(&response, header::LAST_MODIFIED).is_empty());
+        assert_eq!(test::read_body(response).await.len(), VIDEO_BYTES);
+    }
+
+    #[actix_web::test]
+    async fn download_flag_makes_an_attachment() {
+        let dir = artifacts();
+        let response = get(dir.path(), "/video/test?download=1", &[]).await;
+        assert_eq!(
+            header(&response, header::CONTENT_DISPOSITION),
+            "attachment; filename=\"gource_test.mp4\""
+        );
+
+        let response = get(dir.path(), "/poster/test?download=0", &[]).await;
+        assert_eq!(header(&response, header::CONTENT_TYPE), "image/png");
+        assert!(header(&response, header::CONTENT_DISPOSITION).starts_with("inline"));
+    }
+
+    #[actix_web::test]
+    async fn serves_byte_ranges() {
+        let dir = artifacts();
+        let video = video();
+
+        let response = get(
+            dir.path(),
+            "/video/test",
+            &[(header::RANGE, "bytes=100-199")],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(header(&response, header::CONTENT_TYPE), "video/mp4");
+        assert_eq!(
+            header(&response, header::CONTENT_RANGE),
+            "bytes 100-199/1024"
+        );
+        assert_eq!(test::read_body(response).await, &video[100..200]);
+
+        let response = get(dir.path(), "/video/test", &[(header::RANGE, "bytes=1000-")]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            header(&response, header::CONTENT_RANGE),
+            "bytes 1000-1023/1024"
+        );
+        assert_eq!(test::read_body(response).await, &video[1000..]);
+
+        let response = get(dir.path(), "/video/test", &[(header::RANGE, "bytes=-24")]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(test::read_body(response).await, &video[1000..]);
+    }
+
+    #[actix_web::test]
+    async fn rejects_ranges_past_the_end() {
+        let dir = artifacts();
+        let response = get(
+            dir.path(),
+            "/video/test",
+            &[(header::RANGE, "bytes=2000-3000")],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(header(&response, header::CONTENT_RANGE), "bytes */1024");
+    }
+
+    #[actix_web::test]
+    async fn honours_conditional_requests() {
+        let dir = artifacts();
+        let response = get(dir.path(), "/video/test", &[]).await;
+        let etag = header(&response, header::ETAG).to_string();
+        let last_modified = header(&response, header::LAST_MODIFIED).to_string();
+
+        let response = get(dir.path(), "/video/test", &[(header::IF_NONE_MATCH, &etag)]).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let response = get(
+            dir.path(),
+            "/video/test",
+            &[(header::IF_MODIFIED_SINCE, &last_modified)],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // A range is only served from the version the client already has.
+        let response = get(
+            dir.path(),
+            "/video/test",
+            &[(header::RANGE, "bytes=0-9"), (header::IF_RANGE, &etag)],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let response = get(
+            dir.path(),
+            "/video/test",
+            &[
+                (header::RANGE, "bytes=0-9"),
+                (header::IF_RANGE, "\"stale\""),
+            ],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(test::read_body(response).await.len(), VIDEO_BYTES);
+    }
+
+    #[actix_web::test]
+    async fn missing_artifacts_are_not_found() {
+        let dir = artifacts();
+        let response = get(dir.path(), "/video/other", &[]).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = get(dir.path(), "/contact-sheet/test", &[]).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn retimes_only_videos_outside_the_tolerance() {
+        assert_eq!(retime_speed(61.0, 60.0, 2.0), None);
+        assert_eq!(retime_speed(58.0, 60.0, 2.0), None);
+        assert_eq!(retime_speed(120.0, 60.0, 2.0), Some(2.0));
+        assert_eq!(retime_speed(30.0, 60.0, 2.0), Some(0.5));
+    }
+
+    #[actix_web::test]
+    async fn limits_target_durations_to_the_longest_video() {
+        let settings = |seconds| GourceSettings {
+            target_duration_seconds: Some(seconds),
+            ..GourceSettings::default()
+        };
+        assert!(validate_settings(&settings(pacing::MAX_TARGET_SECONDS)).is_ok());
+        assert_eq!(
+            validate_settings(&settings(pacing::MAX_TARGET_SECONDS + 1.0)),
+            Err(format!(
+                "target_duration_seconds must be between 0 and {}",
+                pacing::MAX_TARGET_SECONDS
+            ))
+        );
+    }
+
+    #[actix_web::test]
+    async fn limits_the_top_contributors_shown() {
+        let settings = |top| GourceSettings {
+            top_contributors: Some(top),
+            ..GourceSettings::default()
+        };
+        let message = format!(
+            "top_contributors must be between 1 and {}",
+            overlay::MAX_TOP_CONTRIBUTORS
+        );
+        assert!(validate_settings(&settings(overlay::MAX_TOP_CONTRIBUTORS)).is_ok());
+        assert_eq!(validate_settings(&settings(0)), Err(message.clone()));
+        assert_eq!(
+            validate_settings(&settings(overlay::MAX_TOP_CONTRIBUTORS + 1)),
+            Err(message)
+        );
+    }
+
+    #[actix_web::test]
+    async fn limits_the_contact_sheet_frames() {
+        let settings = |frames| GourceSettings {
+            contact_sheet_frames: Some(frames),
+            ..GourceSettings::default()
+        };
+        let message = format!(
+            "contact_sheet_frames must be between 2 and {}",
+            frames::MAX_CONTACT_SHEET_FRAMES
+        );
+        assert!(validate_settings(&settings(frames::MAX_CONTACT_SHEET_FRAMES)).is_ok());
+        assert_eq!(validate_settings(&settings(1)), Err(message.clone()));
+        assert_eq!(
+            validate_settings(&settings(frames::MAX_CONTACT_SHEET_FRAMES + 1)),
+            Err(message)
+        );
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs ffmpeg; run with `cargo test -- --ignored`"]
+    async fn fits_videos_to_the_target_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        let video_path = dir.path().join("video.mp4");
+        let processes = JobProcesses::default();
+        let render = |path: &Path| {
+            let output = Command::new("ffmpeg")
+                .args(["-y", "-v", "error", "-f", "lavfi", "-i"])
+                .arg("testsrc=duration=4:size=160x120:rate=30")
+                .args(["-pix_fmt", "yuv420p"])
+                .arg(path)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+        };
+
+        render(&video_path);
+        let info = fit_video(&video_path, Some(4.2), 0.5, &processes)
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.target_duration_seconds, Some(4.2));
+        assert_eq!(info.playback_speed, None);
+        assert!((info.duration_seconds.unwrap() - 4.0).abs() < 0.1);
+
+        let info = fit_video(&video_path, Some(2.0), 0.5, &processes)
+            .unwrap()
+            .unwrap();
+        assert!((info.playback_speed.unwrap() - 2.0).abs() < 0.1);
+        assert!((info.duration_seconds.unwrap() - 2.0).abs() < 0.5);
+        assert!(!fitted_path(&video_path).exists());
+
+        let info = fit_video(&video_path, None, 0.5, &processes)
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.target_duration_seconds, None);
+    }
+}
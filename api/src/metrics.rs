@@ -0,0 +1,123 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+
+pub static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+pub static JOBS_STARTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new(
+        "gitmotion_jobs_started_total",
+        "Number of visualization jobs accepted",
+    ))
+});
+
+pub static JOBS_COMPLETED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new(
+        "gitmotion_jobs_completed_total",
+        "Number of visualization jobs that produced a video",
+    ))
+});
+
+pub static JOBS_FAILED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "gitmotion_jobs_failed_total",
+            "Number of visualization jobs that failed, by error kind",
+        ),
+        &["kind"],
+    ))
+});
+
+pub static JOBS_CANCELLED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new(
+        "gitmotion_jobs_cancelled_total",
+        "Number of visualization jobs stopped by the user",
+    ))
+});
+
+pub static JOBS_INTERRUPTED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(IntCounter::new(
+        "gitmotion_jobs_interrupted_total",
+        "Number of visualization jobs stopped by a server shutdown",
+    ))
+});
+
+pub static JOBS_RETRIED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "gitmotion_jobs_retried_total",
+            "Number of job retries, by whether they were automatic or requested",
+        ),
+        &["trigger"],
+    ))
+});
+
+pub static PHASE_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "gitmotion_phase_duration_seconds",
+            "Time spent in each job phase",
+        )
+        .buckets(vec![
+            0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0,
+        ]),
+        &["phase"],
+    ))
+});
+
+pub static QUEUE_DEPTH: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new(
+        "gitmotion_queue_depth",
+        "Number of accepted jobs that have not started processing",
+    ))
+});
+
+pub static ACTIVE_RENDERS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new(
+        "gitmotion_active_renders",
+        "Number of gource renders currently running",
+    ))
+});
+
+pub static VIDEO_DIR_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(IntGauge::new(
+        "gitmotion_video_dir_bytes",
+        "Space used by the videos, uploads and images of jobs in the artifact store",
+    ))
+});
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(
+    collector: prometheus::Result<T>,
+) -> T {
+    let collector = collector.expect("metric definition is valid");
+    REGISTRY
+        .register(Box::new(collector.clone()))
+        .expect("metric is registered once");
+    collector
+}
+
+/// Touches every metric so they are exported before their first update.
+pub fn init() {
+    LazyLock::force(&JOBS_STARTED);
+    LazyLock::force(&JOBS_COMPLETED);
+    LazyLock::force(&JOBS_FAILED);
+    LazyLock::force(&JOBS_CANCELLED);
+    LazyLock::force(&JOBS_INTERRUPTED);
+    LazyLock::force(&JOBS_RETRIED);
+    LazyLock::force(&PHASE_DURATION);
+    LazyLock::force(&QUEUE_DEPTH);
+    LazyLock::force(&ACTIVE_RENDERS);
+    LazyLock::force(&VIDEO_DIR_BYTES);
+}
+
+pub fn render(stored_bytes: u64) -> String {
+    VIDEO_DIR_BYTES.set(stored_bytes as i64);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("text encoding does not fail");
+    String::from_utf8(buffer).unwrap_or_default()
+}
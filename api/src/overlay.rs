@@ -0,0 +1,274 @@
+use crate::branding::VIDEO_SIZE;
+use crate::pacing::Pacing;
+use crate::vcs::{Commit, Tag};
+use std::collections::HashMap;
+
+pub const DEFAULT_TOP_CONTRIBUTORS: usize = 5;
+pub const MAX_TOP_CONTRIBUTORS: usize = 10;
+
+/// The statistics change at most this often, in seconds.
+const UPDATE_INTERVAL_SECS: f64 = 0.1;
+/// How long a milestone caption stays on screen.
+const MILESTONE_SECS: f64 = 4.0;
+/// End of the last event; the video is cut before then.
+const END_OF_VIDEO: &str = "9:59:59.99";
+
+/// Maps times in the history to times in the gource video, the way gource
+/// plays it: `seconds_per_day` while commits are close together, and at most
+/// `auto_skip_seconds` across the gap between two commits.
+pub struct Timeline {
+    commit_times: Vec<i64>,
+    video_times: Vec<f64>,
+    seconds_per_day: f64,
+    auto_skip_seconds: f64,
+}
+
+impl Timeline {
+    /// `commits` must be oldest first.
+    pub fn new(commits: &[Commit], pacing: &Pacing) -> Timeline {
+        let mut timeline = Timeline {
+            commit_times: Vec::with_capacity(commits.len()),
+            video_times: Vec::with_capacity(commits.len()),
+            seconds_per_day: pacing.seconds_per_day,
+            auto_skip_seconds: pacing.auto_skip_seconds,
+        };
+        for commit in commits {
+            let video_time = match (timeline.commit_times.last(), timeline.video_times.last()) {
+                (Some(previous), Some(previous_video_time)) => {
+                    previous_video_time + timeline.gap_duration(commit.timestamp - previous)
+                }
+                _ => 0.0,
+            };
+            timeline.commit_times.push(commit.timestamp);
+            timeline.video_times.push(video_time);
+        }
+        timeline
+    }
+
+    /// Seconds into the video at which gource shows `timestamp`. Times before
+    /// the first commit map to the start and times after the last to its end.
+    pub fn video_time(&self, timestamp: i64) -> f64 {
+        let previous = self.commit_times.partition_point(|time| *time <= timestamp);
+        match previous {
+            0 => 0.0,
+            // Nothing happens in the video after the last commit.
+            _ if previous == self.commit_times.len() => self.video_times[previous - 1],
+            _ => {
+                self.video_times[previous - 1]
+                    + self.gap_duration(timestamp - self.commit_times[previous - 1])
+            }
+        }
+    }
+
+    fn gap_duration(&self, seconds: i64) -> f64 {
+        (seconds as f64 / 86400.0 * self.seconds_per_day).min(self.auto_skip_seconds)
+    }
+}
+
+/// An ASS subtitle document drawn over the gource video: a running commit
+/// count and the `top_contributors` authors with the most commits so far in
+/// the top right corner, and a caption for each tag as it is reached.
+pub fn stats_overlay(
+    commits: &[Commit],
+    tags: &[Tag],
+    pacing: &Pacing,
+    top_contributors: usize,
+) -> String {
+    let timeline = Timeline::new(commits, pacing);
+    let (width, height) = VIDEO_SIZE.split_once('x').unwrap_or(("1920", "1200"));
+    let mut document = format!(
+        "[Script Info]\n\
+        ScriptType: v4.00+\n\
+        PlayResX: {}\n\
+        PlayResY: {}\n\
+        WrapStyle: 2\n\
+        \n\
+        [V4+ Styles]\n\
+        Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, \
+        BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, \
+        BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+        Style: Stats,Sans,28,&H00FFFFFF,&H00FFFFFF,&H00000000,&H00000000,0,0,0,0,100,100,0,0,\
+        1,2,0,9,40,40,40,1\n\
+        Style: Milestone,Sans,44,&H0000D7FF,&H0000D7FF,&H00000000,&H00000000,1,0,0,0,100,100,\
+        0,0,1,3,0,8,40,40,90,1\n\
+        \n\
+        [Events]\n\
+        Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        width, height
+    );
+
+    // One event per change of the statistics, lasting until the next.
+    let mut states: Vec<(f64, String)> = Vec::new();
+    let mut counts: HashMap<&str, u32> = HashMap::new();
+    for (index, commit) in commits.iter().enumerate() {
+        *counts.entry(commit.author.as_str()).or_default() += 1;
+        let slot = (timeline.video_times[index] / UPDATE_INTERVAL_SECS).floor();
+        let next_slot = timeline
+            .video_times
+            .get(index + 1)
+            .map(|time| (time / UPDATE_INTERVAL_SECS).floor());
+        if next_slot != Some(slot) {
+            states.push((
+                slot * UPDATE_INTERVAL_SECS,
+                stats_text(index + 1, &counts, top_contributors),
+            ));
+        }
+    }
+    for (index, (start, text)) in states.iter().enumerate() {
+        let end = states
+            .get(index + 1)
+            .map_or(END_OF_VIDEO.to_string(), |(end, _)| ass_time(*end));
+        document.push_str(&format!(
+            "Dialogue: 0,{},{},Stats,,0,0,0,,{}\n",
+            ass_time(*start),
+            end,
+            text
+        ));
+    }
+
+    for tag in tags {
+        let start = timeline.video_time(tag.timestamp);
+        document.push_str(&format!(
+            "Dialogue: 1,{},{},Milestone,,0,0,0,,{}\n",
+            ass_time(start),
+            ass_time(start + MILESTONE_SECS),
+            ass_text(&tag.name)
+        ));
+    }
+    document
+}
+
+fn stats_text(total_commits: usize, counts: &HashMap<&str, u32>, top: usize) -> String {
+    let mut authors: Vec<(&str, u32)> = counts.iter().map(|(a, c)| (*a, *c)).collect();
+    // Ties go to the name that sorts first so the order does not flicker.
+    authors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let mut text = format!(
+        "{{\\fs44\\b1}}{} {}{{\\fs28\\b0}}",
+        thousands(total_commits as u64),
+        if total_commits == 1 {
+            "commit"
+        } else {
+            "commits"
+        }
+    );
+    for (rank, (author, commits)) in authors.iter().take(top).enumerate() {
+        text.push_str(&format!(
+            "\\N{}. {}  {}",
+            rank + 1,
+            ass_text(author),
+            thousands(u64::from(*commits))
+        ));
+    }
+    text
+}
+
+/// `H:MM:SS.cc`, as ASS expects.
+fn ass_time(seconds: f64) -> String {
+    let centiseconds = (seconds.max(0.0) * 100.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centiseconds / 360_000,
+        centiseconds / 6000 % 60,
+        centiseconds / 100 % 60,
+        centiseconds % 100
+    )
+}
+
+/// Keeps names from being read as override blocks or line breaks.
+fn ass_text(text: &str) -> String {
+    text.replace('\\', "/")
+        .replace('{', "(")
+        .replace('}', ")")
+        .replace(['\n', '\r'], " ")
+}
+
+fn thousands(value: u64) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86400;
+
+    fn commit(day: i64, author: &str) -> Commit {
+        Commit {
+            timestamp: day * DAY,
+            author: author.to_string(),
+        }
+    }
+
+    fn pacing() -> Pacing {
+        Pacing {
+            seconds_per_day: 1.0,
+            auto_skip_seconds: 2.0,
+            target_duration: 3.0,
+            estimated_duration: 3.0,
+        }
+    }
+
+    #[test]
+    fn plays_days_in_real_time_and_skips_long_gaps() {
+        let commits = [commit(1, "a"), commit(2, "a"), commit(12, "a")];
+        let timeline = Timeline::new(&commits, &pacing());
+        assert_eq!(timeline.video_times, [0.0, 1.0, 3.0]);
+
+        assert_eq!(timeline.video_time(0), 0.0);
+        assert_eq!(timeline.video_time(DAY + DAY / 2), 0.5);
+        assert_eq!(timeline.video_time(7 * DAY), 3.0);
+        assert_eq!(timeline.video_time(12 * DAY), 3.0);
+        assert_eq!(timeline.video_time(100 * DAY), 3.0);
+    }
+
+    #[test]
+    fn ranks_the_top_contributors_so_far() {
+        let commits = [
+            commit(1, "bob"),
+            commit(2, "alice"),
+            commit(3, "alice"),
+            commit(4, "carol"),
+        ];
+        let tags = [Tag {
+            name: "v{1}".to_string(),
+            timestamp: 3 * DAY,
+        }];
+        let overlay = stats_overlay(&commits, &tags, &pacing(), 2);
+        let events: Vec<&str> = overlay
+            .lines()
+            .filter(|line| line.starts_with("Dialogue:"))
+            .collect();
+
+        assert_eq!(
+            events,
+            [
+                "Dialogue: 0,0:00:00.00,0:00:01.00,Stats,,0,0,0,,\
+                 {\\fs44\\b1}1 commit{\\fs28\\b0}\\N1. bob  1",
+                "Dialogue: 0,0:00:01.00,0:00:02.00,Stats,,0,0,0,,\
+                 {\\fs44\\b1}2 commits{\\fs28\\b0}\\N1. alice  1\\N2. bob  1",
+                "Dialogue: 0,0:00:02.00,0:00:03.00,Stats,,0,0,0,,\
+                 {\\fs44\\b1}3 commits{\\fs28\\b0}\\N1. alice  2\\N2. bob  1",
+                "Dialogue: 0,0:00:03.00,9:59:59.99,Stats,,0,0,0,,\
+                 {\\fs44\\b1}4 commits{\\fs28\\b0}\\N1. alice  2\\N2. bob  1",
+                "Dialogue: 1,0:00:02.00,0:00:06.00,Milestone,,0,0,0,,v(1)",
+            ]
+        );
+    }
+
+    #[test]
+    fn formats_times_and_counts() {
+        assert_eq!(ass_time(3723.456), "1:02:03.46");
+        assert_eq!(ass_time(-1.0), "0:00:00.00");
+        assert_eq!(thousands(999), "999");
+        assert_eq!(thousands(1234567), "1,234,567");
+    }
+}
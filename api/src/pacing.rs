@@ -0,0 +1,259 @@
+use crate::config::PacingConfig;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Longest video that can be requested, in seconds.
+pub const MAX_TARGET_SECONDS: f64 = 600.0;
+
+/// Idle gaps up to this percentile of all gaps between active days play in
+/// real time; longer ones are quiet periods and get skipped through.
+const QUIET_GAP_PERCENTILE: f64 = 0.75;
+
+/// How long the video should run: a number of seconds or a preset.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum TargetDuration {
+    Seconds(f64),
+    Preset(DurationPreset),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DurationPreset {
+    Short,
+    Medium,
+    Long,
+}
+
+impl TargetDuration {
+    pub fn seconds(self) -> f64 {
+        match self {
+            TargetDuration::Seconds(seconds) => seconds,
+            TargetDuration::Preset(DurationPreset::Short) => 30.0,
+            TargetDuration::Preset(DurationPreset::Medium) => 60.0,
+            TargetDuration::Preset(DurationPreset::Long) => 120.0,
+        }
+    }
+
+    pub fn validate(self) -> Result<(), String> {
+        let seconds = self.seconds();
+        if !(seconds.is_finite() && seconds > 0.0 && seconds <= MAX_TARGET_SECONDS) {
+            return Err(format!(
+                "duration must be between 0 and {} seconds",
+                MAX_TARGET_SECONDS
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How gource moves through the history.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pacing {
+    pub seconds_per_day: f64,
+    /// Idle time after which gource jumps to the next commit, which bounds
+    /// how long any quiet period lasts on screen.
+    pub auto_skip_seconds: f64,
+    pub target_duration: f64,
+    /// Expected length of the video with this pacing.
+    pub estimated_duration: f64,
+}
+
+/// Chooses the pacing that brings a history with the given commits per day
+/// closest to `requested` seconds, or to a length that grows with the number
+/// of active days when nothing is requested.
+pub fn plan(
+    commits_per_day: &BTreeMap<NaiveDate, u32>,
+    requested: Option<TargetDuration>,
+    config: &PacingConfig,
+) -> Pacing {
+    let active_days = commits_per_day.len();
+    let target_duration = requested.map_or_else(
+        || automatic_target(active_days, config),
+        TargetDuration::seconds,
+    );
+
+    let days: Vec<&NaiveDate> = commits_per_day.keys().collect();
+    let gaps: Vec<f64> = days
+        .windows(2)
+        .map(|pair| ((*pair[1] - *pair[0]).num_days() - 1) as f64)
+        .collect();
+    let quiet_gap = percentile(&gaps, QUIET_GAP_PERCENTILE);
+
+    let estimate = |seconds_per_day: f64| {
+        let auto_skip = auto_skip_seconds(seconds_per_day, quiet_gap, config);
+        let idle: f64 = gaps
+            .iter()
+            .map(|gap| (gap * seconds_per_day).min(auto_skip))
+            .sum();
+        (active_days as f64 * seconds_per_day + idle, auto_skip)
+    };
+
+    // The estimate grows with seconds per day, so bisect for the target.
+    let (mut low, mut high) = (config.min_seconds_per_day, config.max_seconds_per_day);
+    let seconds_per_day = if active_days == 0 || estimate(high).0 <= target_duration {
+        high
+    } else if estimate(low).0 >= target_duration {
+        low
+    } else {
+        for _ in 0..64 {
+            let middle = (low + high) / 2.0;
+            if estimate(middle).0 < target_duration {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        (low + high) / 2.0
+    };
+
+    let (estimated_duration, auto_skip_seconds) = estimate(seconds_per_day);
+    Pacing {
+        seconds_per_day,
+        auto_skip_seconds,
+        target_duration,
+        estimated_duration,
+    }
+}
+
+/// Scales linearly from `min_duration_secs` to `max_duration_secs` as the
+/// number of active days approaches `active_days_threshold`.
+fn automatic_target(active_days: usize, config: &PacingConfig) -> f64 {
+    let share = (active_days as f64 / config.active_days_threshold as f64).min(1.0);
+    config.min_duration_secs + (config.max_duration_secs - config.min_duration_secs) * share
+}
+
+/// Quiet periods last as long as the typical gap would play, within the
+/// configured bounds. Gource measures idle time from one commit to the next,
+/// so the day a commit is made on counts too; otherwise the time between
+/// commits on consecutive days would be skipped.
+fn auto_skip_seconds(seconds_per_day: f64, quiet_gap: f64, config: &PacingConfig) -> f64 {
+    ((quiet_gap + 1.0) * seconds_per_day)
+        .clamp(config.min_auto_skip_secs, config.max_auto_skip_secs)
+}
+
+/// Nearest-rank percentile; 0 for no values.
+fn percentile(values: &[f64], fraction: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = ((sorted.len() as f64 * fraction).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    fn history(offsets: &[i64]) -> BTreeMap<NaiveDate, u32> {
+        let start = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        offsets
+            .iter()
+            .map(|offset| (start + TimeDelta::days(*offset), 1))
+            .collect()
+    }
+
+    fn daily(days: i64) -> BTreeMap<NaiveDate, u32> {
+        history(&(0..days).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn parses_seconds_and_presets() {
+        let parse = |json: &str| serde_json::from_str::<TargetDuration>(json).unwrap();
+        assert_eq!(parse("45").seconds(), 45.0);
+        assert_eq!(parse("12.5").seconds(), 12.5);
+        assert_eq!(parse("\"short\"").seconds(), 30.0);
+        assert_eq!(parse("\"medium\"").seconds(), 60.0);
+        assert_eq!(parse("\"long\"").seconds(), 120.0);
+        assert!(serde_json::from_str::<TargetDuration>("\"epic\"").is_err());
+
+        assert!(TargetDuration::Seconds(0.0).validate().is_err());
+        assert!(TargetDuration::Seconds(601.0).validate().is_err());
+        assert!(TargetDuration::Seconds(f64::NAN).validate().is_err());
+        assert!(TargetDuration::Preset(DurationPreset::Long)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn steady_history_spreads_the_target_over_every_day() {
+        let config = PacingConfig::default();
+        let pacing = plan(&daily(100), Some(TargetDuration::Seconds(50.0)), &config);
+        assert!((pacing.seconds_per_day - 0.5).abs() < 1e-6);
+        assert!((pacing.estimated_duration - 50.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn automatic_target_grows_with_active_days() {
+        let config = PacingConfig::default();
+        let small = plan(&daily(100), None, &config);
+        let large = plan(&daily(900), None, &config);
+        let huge = plan(&daily(5000), None, &config);
+        assert!((small.target_duration - 44.0).abs() < 1e-6);
+        assert!(small.target_duration < large.target_duration);
+        assert_eq!(huge.target_duration, config.max_duration_secs);
+        assert!((large.estimated_duration - large.target_duration).abs() < 1e-3);
+    }
+
+    #[test]
+    fn quiet_periods_are_compressed() {
+        let config = PacingConfig::default();
+        // Twenty days of daily work, a year off, then another twenty days.
+        let mut offsets: Vec<i64> = (0..20).collect();
+        offsets.extend(385..405);
+        let pacing = plan(
+            &history(&offsets),
+            Some(TargetDuration::Seconds(40.0)),
+            &config,
+        );
+
+        // The year-long gap costs no more than the auto-skip, which lets a
+        // single day play out, so nearly all of the video goes to the active
+        // days.
+        assert!((pacing.auto_skip_seconds - pacing.seconds_per_day).abs() < 1e-9);
+        assert!(pacing.seconds_per_day * 365.0 > pacing.auto_skip_seconds * 10.0);
+        assert!((pacing.estimated_duration - 40.0).abs() < 1e-3);
+        assert!(pacing.seconds_per_day > 0.95);
+    }
+
+    #[test]
+    fn typical_gaps_of_sparse_history_play_in_real_time() {
+        let config = PacingConfig::default();
+        // A commit every ten days, with one three-year silence.
+        let mut offsets: Vec<i64> = (0..40).map(|i| i * 10).collect();
+        offsets.extend((0..40).map(|i| 1500 + i * 10));
+        let pacing = plan(
+            &history(&offsets),
+            Some(TargetDuration::Seconds(60.0)),
+            &config,
+        );
+
+        let typical_gap = 9.0 * pacing.seconds_per_day;
+        assert!(pacing.auto_skip_seconds >= typical_gap - 1e-9);
+        assert!(pacing.auto_skip_seconds <= config.max_auto_skip_secs);
+        assert!((pacing.estimated_duration - 60.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn clamps_to_the_configured_range() {
+        let config = PacingConfig::default();
+
+        let short = plan(&daily(3), Some(TargetDuration::Seconds(60.0)), &config);
+        assert_eq!(short.seconds_per_day, config.max_seconds_per_day);
+        assert!(short.estimated_duration < 60.0);
+
+        let mut long_history = daily(200_000);
+        long_history.remove(&NaiveDate::from_ymd_opt(2020, 1, 2).unwrap());
+        let long = plan(&long_history, Some(TargetDuration::Seconds(1.0)), &config);
+        assert_eq!(long.seconds_per_day, config.min_seconds_per_day);
+        assert!(long.auto_skip_seconds >= config.min_auto_skip_secs);
+
+        let empty = plan(&BTreeMap::new(), None, &config);
+        assert_eq!(empty.seconds_per_day, config.max_seconds_per_day);
+        assert_eq!(empty.estimated_duration, 0.0);
+    }
+}
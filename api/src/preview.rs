@@ -0,0 +1,141 @@
+use crate::config::PreviewConfig;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+
+/// How long a preview clip runs.
+pub const CLIP_SECONDS: f64 = 10.0;
+/// Size gource renders previews at.
+pub const SIZE: &str = "640x400";
+/// Frame rate of preview clips; gource still renders at 30.
+pub const FRAME_RATE: u32 = 10;
+
+/// Frames on the image of a stills preview.
+pub const STILLS: u32 = 6;
+
+/// Written into a job's checkout directory once every repository has been
+/// cloned into it.
+const COMPLETE_MARKER: &str = ".complete";
+
+/// A quick render to check the settings before the full one.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewMode {
+    /// A short, small, low frame rate video.
+    Clip,
+    /// One image of frames taken evenly across the preview clip.
+    Stills,
+}
+
+/// Where a preview keeps its checkouts for the full render.
+pub fn checkout_dir(config: &PreviewConfig, job_id: &str) -> PathBuf {
+    config.checkout_dir.join(job_id)
+}
+
+/// Whether `dir` holds the complete checkouts of an earlier run. Using them
+/// restarts their time to live.
+pub fn reuse_checkouts(dir: &Path) -> bool {
+    let marker = dir.join(COMPLETE_MARKER);
+    match File::options().write(true).open(&marker) {
+        Ok(file) => {
+            if let Err(e) = file.set_modified(SystemTime::now()) {
+                error!("Failed to refresh {:?}: {}", marker, e);
+            }
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Starts an empty checkout directory, replacing whatever an earlier run left
+/// there.
+pub fn prepare_checkouts(dir: &Path) -> io::Result<()> {
+    remove_checkouts(dir)?;
+    fs::create_dir_all(dir)
+}
+
+pub fn mark_complete(dir: &Path) -> io::Result<()> {
+    File::create(dir.join(COMPLETE_MARKER)).map(|_| ())
+}
+
+pub fn remove_checkouts(dir: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Removes the checkouts of previews that were not promoted within `ttl`.
+pub fn remove_expired_checkouts(config: &PreviewConfig, ttl: Duration) {
+    let Ok(entries) = fs::read_dir(&config.checkout_dir) else {
+        return;
+    };
+    let cutoff = SystemTime::now() - ttl;
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        // Checkouts still being cloned have no marker yet, so their directory
+        // is what ages.
+        let modified = fs::metadata(dir.join(COMPLETE_MARKER))
+            .or_else(|_| entry.metadata())
+            .and_then(|metadata| metadata.modified());
+        if modified.is_ok_and(|modified| modified < cutoff) {
+            match remove_checkouts(&dir) {
+                Ok(()) => info!("Removed expired preview checkouts {:?}", dir),
+                Err(e) => error!("Failed to remove preview checkouts {:?}: {}", dir, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(3600);
+
+    fn age(path: &Path) {
+        File::open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * TTL)
+            .unwrap();
+    }
+
+    #[test]
+    fn keeps_checkouts_until_they_expire() {
+        let root = tempfile::tempdir().unwrap();
+        let config = PreviewConfig {
+            checkout_dir: root.path().to_path_buf(),
+        };
+        let promoted = checkout_dir(&config, "promoted");
+        let expired = checkout_dir(&config, "expired");
+        let abandoned = checkout_dir(&config, "abandoned");
+        for dir in [&promoted, &expired, &abandoned] {
+            prepare_checkouts(dir).unwrap();
+            fs::write(dir.join("repository"), "").unwrap();
+        }
+
+        // Only complete checkouts are reused.
+        assert!(!reuse_checkouts(&promoted));
+        mark_complete(&promoted).unwrap();
+        mark_complete(&expired).unwrap();
+        age(&promoted.join(COMPLETE_MARKER));
+        age(&expired.join(COMPLETE_MARKER));
+        // Still being cloned, with nothing but the directory to age.
+        age(&abandoned);
+
+        // Reusing restarts the time to live.
+        assert!(reuse_checkouts(&promoted));
+        remove_expired_checkouts(&config, TTL);
+        assert!(promoted.join("repository").exists());
+        assert!(!expired.exists());
+        assert!(!abandoned.exists());
+
+        // A new run starts from an empty directory.
+        prepare_checkouts(&promoted).unwrap();
+        assert_eq!(fs::read_dir(&promoted).unwrap().count(), 0);
+        assert!(!reuse_checkouts(&promoted));
+    }
+}
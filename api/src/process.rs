@@ -0,0 +1,71 @@
+use std::collections::HashSet;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// Child processes started for one job. Each runs in its own process group so
+/// it can be killed together with everything it started (Xvfb, gource, ffmpeg).
+#[derive(Clone, Default)]
+pub struct JobProcesses {
+    state: Arc<Mutex<ProcessGroups>>,
+}
+
+#[derive(Default)]
+struct ProcessGroups {
+    running: HashSet<i32>,
+    killed: bool,
+}
+
+impl JobProcesses {
+    /// Runs `command` to completion like `Command::output`, unless the job is
+    /// killed first.
+    pub fn output(&self, command: &mut Command) -> io::Result<Output> {
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0);
+
+        // Spawning under the lock means `kill_all` cannot miss a process that
+        // is just starting.
+        let child = {
+            let mut state = self.state.lock().expect("process lock is not poisoned");
+            if state.killed {
+                return Err(io::Error::other("job was interrupted"));
+            }
+            let child = command.spawn()?;
+            state.running.insert(child.id() as i32);
+            child
+        };
+
+        let pgid = child.id() as i32;
+        let output = child.wait_with_output();
+        self.state
+            .lock()
+            .expect("process lock is not poisoned")
+            .running
+            .remove(&pgid);
+        output
+    }
+
+    /// Kills every running process group and refuses to start new ones.
+    pub fn kill_all(&self) {
+        let mut state = self.state.lock().expect("process lock is not poisoned");
+        state.killed = true;
+        for pgid in state.running.drain() {
+            // SAFETY: `kill` has no memory-safety preconditions; a negative pid
+            // addresses the whole process group.
+            if unsafe { libc::kill(-pgid, libc::SIGKILL) } == 0 {
+                info!("Killed process group {}", pgid);
+            } else {
+                warn!(
+                    "Failed to kill process group {}: {}",
+                    pgid,
+                    io::Error::last_os_error()
+                );
+            }
+        }
+    }
+}
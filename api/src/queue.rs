@@ -0,0 +1,509 @@
+use crate::config::{RedisConfig, WorkerConfig};
+use crate::job_store::JobStore;
+use crate::upload::UploadKind;
+use crate::vcs::VcsKind;
+use crate::webhook::Callback;
+use crate::{metrics, GourceSettings, JobRunner, JobState, ProgressStep};
+use chrono::Utc;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Direction, SetExpiry, SetOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::{interval, sleep};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Jobs waiting for a worker. Producers push on the left, workers take from
+/// the right.
+const QUEUE_KEY: &str = "gitmotion:queue";
+/// Jobs a worker has taken but not finished.
+const PROCESSING_KEY: &str = "gitmotion:processing";
+/// Jobs waiting out a retry backoff, scored by when they may run again (in
+/// milliseconds since the epoch).
+const DELAYED_KEY: &str = "gitmotion:delayed";
+
+/// How long a worker blocks waiting for a job before polling again.
+const POLL_TIMEOUT_SECS: f64 = 5.0;
+
+/// Moves a taken job back to the front of the queue, unless that already happened.
+static REQUEUE: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        if redis.call('LREM', KEYS[1], 1, ARGV[1]) == 1 then
+            redis.call('RPUSH', KEYS[2], ARGV[1])
+            return 1
+        end
+        return 0
+        ",
+    )
+});
+
+pub fn redis_client(config: &RedisConfig) -> redis::RedisResult<redis::Client> {
+    let url = config.url.as_deref().unwrap_or_default();
+    let mut connection_info = redis::IntoConnectionInfo::into_connection_info(url)?;
+    if let Some(password) = &config.password {
+        connection_info.redis.password = Some(password.clone());
+    }
+    redis::Client::open(connection_info)
+}
+
+/// Moves delayed jobs whose backoff has elapsed onto the queue.
+static PROMOTE_DUE: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        local due = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, 100)
+        for _, payload in ipairs(due) do
+            redis.call('ZREM', KEYS[1], payload)
+            redis.call('LPUSH', KEYS[2], payload)
+        end
+        return #due
+        ",
+    )
+});
+
+fn lease_key(job_id: &str) -> String {
+    format!("gitmotion:lease:{}", job_id)
+}
+
+/// Everything a worker needs to run a job. The access token stays encrypted
+/// until the worker decrypts it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QueuedJob {
+    pub job_id: String,
+    pub repo_url: String,
+    /// Every repository of a combined render; `repo_url` is the first.
+    #[serde(default)]
+    pub repo_urls: Vec<String>,
+    pub access_token: Option<String>,
+    pub settings: GourceSettings,
+    #[serde(default)]
+    pub callback: Option<Callback>,
+    /// Key of the subscription whose push triggered the job.
+    #[serde(default)]
+    pub subscription: Option<String>,
+    #[serde(default)]
+    pub source: RepositorySource,
+    /// Set when the request named the version control system; otherwise it is
+    /// worked out from each URL.
+    #[serde(default)]
+    pub vcs: Option<VcsKind>,
+}
+
+/// Where the repository of a job comes from.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RepositorySource {
+    /// Cloned from `repo_url` (or each of `repo_urls`).
+    #[default]
+    Clone,
+    /// Uploaded with the request and held in the artifact store under
+    /// `storage::upload_key`.
+    Upload { kind: UploadKind },
+    /// A repository on the server itself, within `local_repos.allowed_paths`.
+    LocalPath { path: PathBuf },
+}
+
+impl QueuedJob {
+    /// The repositories to render, in order.
+    pub fn repositories(&self) -> Vec<String> {
+        if self.repo_urls.is_empty() {
+            vec![self.repo_url.clone()]
+        } else {
+            self.repo_urls.clone()
+        }
+    }
+}
+
+/// Where accepted jobs go: straight to a task in this process, or onto the
+/// Redis queue for a worker process to pick up.
+#[derive(Clone)]
+pub enum JobQueue {
+    Local(JobRunner),
+    Redis(ConnectionManager),
+}
+
+impl JobQueue {
+    pub async fn enqueue(&self, job: QueuedJob) -> redis::RedisResult<()> {
+        match self {
+            JobQueue::Local(runner) => {
+                run_locally(runner.clone(), job, Duration::ZERO);
+                Ok(())
+            }
+            JobQueue::Redis(connection) => {
+                let payload = serde_json::to_string(&job).expect("queued job serializes");
+                connection.clone().lpush(QUEUE_KEY, payload).await
+            }
+        }
+    }
+
+    /// Number of jobs waiting in Redis. The local queue is tracked by the
+    /// gauge directly.
+    pub async fn depth(&self) -> Option<i64> {
+        match self {
+            JobQueue::Local(_) => None,
+            JobQueue::Redis(connection) => match connection.clone().llen(QUEUE_KEY).await {
+                Ok(depth) => Some(depth),
+                Err(e) => {
+                    error!("Failed to read queue depth: {}", e);
+                    None
+                }
+            },
+        }
+    }
+}
+
+/// Runs the job on this process after `delay`, scheduling it again if it
+/// asks to be retried. Jobs that have not started when the server shuts down
+/// stay queued.
+fn run_locally(runner: JobRunner, job: QueuedJob, delay: Duration) {
+    metrics::QUEUE_DEPTH.inc();
+    runner.runtime.clone().spawn(async move {
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = runner.shutdown.draining() => {}
+        }
+        metrics::QUEUE_DEPTH.dec();
+        if runner.shutdown.is_draining() {
+            return;
+        }
+        if let Some(backoff) = runner.run(job.clone()).await {
+            run_locally(runner, job, backoff);
+        }
+    });
+}
+
+/// Takes jobs off the Redis queue and renders up to `worker.concurrency` of
+/// them at a time. Each job holds a lease that is refreshed while it runs; jobs
+/// whose lease lapses are put back on the queue by `reap_expired_leases`.
+pub async fn run_worker(
+    client: redis::Client,
+    connection: ConnectionManager,
+    runner: JobRunner,
+    config: WorkerConfig,
+) {
+    let worker_id = Uuid::new_v4().to_string();
+    info!(
+        "Worker {} started with concurrency {}",
+        worker_id, config.concurrency
+    );
+
+    // BLMOVE holds its connection until a job arrives, so it gets its own
+    // rather than stalling the shared one.
+    let mut blocking = loop {
+        match client.get_connection_manager().await {
+            Ok(connection) => break connection,
+            Err(e) => {
+                error!("Failed to connect to Redis: {}", e);
+                sleep(Duration::from_secs(1)).await;
+            }
+        }
+    };
+    tokio::spawn(reap_expired_leases(
+        connection.clone(),
+        runner.job_store.clone(),
+        config.clone(),
+    ));
+    tokio::spawn(promote_delayed_jobs(connection.clone()));
+
+    let shutdown = runner.shutdown.clone();
+    let slots = Arc::new(Semaphore::new(config.concurrency));
+    loop {
+        let permit = tokio::select! {
+            permit = slots.clone().acquire_owned() => {
+                permit.expect("worker semaphore is never closed")
+            }
+            _ = shutdown.draining() => break,
+        };
+
+        // An entry moved by an abandoned BLMOVE has no lease yet, so the reaper
+        // puts it back on the queue.
+        let taken = tokio::select! {
+            taken = blocking.blmove(
+                QUEUE_KEY,
+                PROCESSING_KEY,
+                Direction::Right,
+                Direction::Left,
+                POLL_TIMEOUT_SECS,
+            ) => taken,
+            _ = shutdown.draining() => break,
+        };
+        let payload: Option<String> = match taken {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to take a job from the queue: {}", e);
+                sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let Some(payload) = payload else {
+            continue;
+        };
+
+        let mut connection = connection.clone();
+        if shutdown.is_draining() {
+            let _: redis::RedisResult<i32> = REQUEUE
+                .key(PROCESSING_KEY)
+                .key(QUEUE_KEY)
+                .arg(&payload)
+                .invoke_async(&mut connection)
+                .await;
+            break;
+        }
+
+        let job: QueuedJob = match serde_json::from_str(&payload) {
+            Ok(job) => job,
+            Err(e) => {
+                error!("Dropping malformed queue entry: {}", e);
+                let _: redis::RedisResult<usize> =
+                    connection.lrem(PROCESSING_KEY, 1, &payload).await;
+                continue;
+            }
+        };
+        info!("Worker {} took job {}", worker_id, job.job_id);
+
+        let lease = lease_key(&job.job_id);
+        let lease_ttl = Duration::from_secs(config.lease_ttl_secs);
+        renew_lease(&mut connection, &lease, &worker_id, lease_ttl).await;
+
+        let heartbeat = {
+            let mut connection = connection.clone();
+            let lease = lease.clone();
+            let worker_id = worker_id.clone();
+            let mut ticks = interval(Duration::from_secs(config.heartbeat_interval_secs));
+            tokio::spawn(async move {
+                loop {
+                    ticks.tick().await;
+                    renew_lease(&mut connection, &lease, &worker_id, lease_ttl).await;
+                }
+            })
+        };
+
+        let runner = runner.clone();
+        // Counted as running until the lease is released, so shutdown waits
+        // for the release too.
+        let running = runner.shutdown.track_job();
+        tokio::spawn(async move {
+            let _permit = permit;
+            let _running = running;
+            let retry_after = runner.run(job).await;
+
+            heartbeat.abort();
+            let mut release = redis::pipe();
+            release.atomic();
+            if let Some(backoff) = retry_after {
+                let due = Utc::now() + backoff;
+                release.zadd(DELAYED_KEY, &payload, due.timestamp_millis());
+            }
+            release.lrem(PROCESSING_KEY, 1, &payload).del(&lease);
+            if let Err(e) = release.exec_async(&mut connection).await {
+                error!("Failed to release lease {}: {}", lease, e);
+            }
+        });
+    }
+    info!("Worker {} stopped taking jobs", worker_id);
+}
+
+async fn promote_delayed_jobs(mut connection: ConnectionManager) {
+    let mut ticks = interval(Duration::from_secs(1));
+    loop {
+        ticks.tick().await;
+        let promoted: redis::RedisResult<usize> = PROMOTE_DUE
+            .key(DELAYED_KEY)
+            .key(QUEUE_KEY)
+            .arg(Utc::now().timestamp_millis())
+            .invoke_async(&mut connection)
+            .await;
+        if let Err(e) = promoted {
+            error!("Failed to queue jobs due for a retry: {}", e);
+        }
+    }
+}
+
+async fn renew_lease(
+    connection: &mut ConnectionManager,
+    lease: &str,
+    worker_id: &str,
+    ttl: Duration,
+) {
+    let options = SetOptions::default().with_expiration(SetExpiry::PX(ttl.as_millis() as u64));
+    if let Err(e) = connection
+        .set_options::<_, _, ()>(lease, worker_id, options)
+        .await
+    {
+        warn!("Failed to renew lease {}: {}", lease, e);
+    }
+}
+
+/// Re-queues jobs whose worker stopped renewing their lease. A job must be
+/// seen without a lease on two consecutive sweeps, so one that was just taken
+/// and has not been leased yet is left alone.
+async fn reap_expired_leases(
+    mut connection: ConnectionManager,
+    job_store: JobStore,
+    config: WorkerConfig,
+) {
+    let mut suspects: HashSet<String> = HashSet::new();
+    let mut ticks = interval(Duration::from_secs(config.reaper_interval_secs));
+    loop {
+        ticks.tick().await;
+
+        let processing: Vec<String> = match connection.lrange(PROCESSING_KEY, 0, -1).await {
+            Ok(processing) => processing,
+            Err(e) => {
+                error!("Failed to list jobs in progress: {}", e);
+                continue;
+            }
+        };
+
+        let mut unleased = Vec::new();
+        for payload in processing {
+            let Ok(job) = serde_json::from_str::<QueuedJob>(&payload) else {
+                continue;
+            };
+            let leased: bool = connection
+                .exists(lease_key(&job.job_id))
+                .await
+                .unwrap_or(true);
+            if !leased {
+                unleased.push((payload, job));
+            }
+        }
+
+        let (expired, next_suspects) = expired_leases(unleased, &suspects);
+        for (payload, job) in expired {
+            let requeued: redis::RedisResult<i32> = REQUEUE
+                .key(PROCESSING_KEY)
+                .key(QUEUE_KEY)
+                .arg(&payload)
+                .invoke_async(&mut connection)
+                .await;
+            match requeued {
+                Ok(1) => {
+                    warn!(
+                        "Re-queued job {} after its worker stopped responding",
+                        job.job_id
+                    );
+                    job_store
+                        .update(&job.job_id, |status| {
+                            if !status.state.is_terminal() {
+                                status.state = JobState::Queued;
+                                status.step = ProgressStep::InitializingProject;
+                            }
+                        })
+                        .await;
+                }
+                Ok(_) => {}
+                Err(e) => error!("Failed to re-queue job {}: {}", job.job_id, e),
+            }
+        }
+        suspects = next_suspects;
+    }
+}
+
+/// Splits the entries found without a lease on this sweep into those that
+/// were also unleased on the previous one, which have expired, and the
+/// suspects to check again on the next sweep.
+fn expired_leases<T>(
+    unleased: Vec<(String, T)>,
+    suspects: &HashSet<String>,
+) -> (Vec<(String, T)>, HashSet<String>) {
+    let (expired, fresh): (Vec<_>, Vec<_>) = unleased
+        .into_iter()
+        .partition(|(payload, _)| suspects.contains(payload));
+    let next_suspects = fresh.into_iter().map(|(payload, _)| payload).collect();
+    (expired, next_suspects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::runner;
+
+    fn job(job_id: &str, repo_url: &str) -> QueuedJob {
+        QueuedJob {
+            job_id: job_id.to_string(),
+            repo_url: repo_url.to_string(),
+            repo_urls: Vec::new(),
+            access_token: None,
+            settings: GourceSettings::default(),
+            callback: None,
+            subscription: None,
+            source: RepositorySource::Clone,
+            vcs: None,
+        }
+    }
+
+    async fn queue_job(job_store: &JobStore, queue: &JobQueue, job: QueuedJob) {
+        let settings = GourceSettings::default();
+        let status = crate::queued_status(&job.repo_url, &settings, None);
+        job_store.insert(&job.job_id, status).await;
+        queue.enqueue(job).await.unwrap();
+    }
+
+    #[test]
+    fn requeues_jobs_unleased_on_two_sweeps() {
+        let unleased = |payloads: &[&str]| -> Vec<(String, ())> {
+            payloads
+                .iter()
+                .map(|payload| (payload.to_string(), ()))
+                .collect()
+        };
+
+        // Just taken: suspected, not yet re-queued.
+        let (expired, suspects) = expired_leases(unleased(&["a", "b"]), &HashSet::new());
+        assert!(expired.is_empty());
+        assert_eq!(suspects, HashSet::from(["a".to_string(), "b".to_string()]));
+
+        // "a" got its lease, "b" is still unleased and "c" was just taken.
+        let (expired, suspects) = expired_leases(unleased(&["b", "c"]), &suspects);
+        let expired: Vec<String> = expired.into_iter().map(|(payload, _)| payload).collect();
+        assert_eq!(expired, ["b"]);
+        assert_eq!(suspects, HashSet::from(["c".to_string()]));
+
+        let (expired, suspects) = expired_leases(unleased(&[]), &suspects);
+        assert!(expired.is_empty());
+        assert!(suspects.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn runs_local_jobs_in_this_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = runner(dir.path());
+        let job_store = runner.job_store.clone();
+        let queue = JobQueue::Local(runner);
+        assert_eq!(queue.depth().await, None);
+
+        // Rejected before anything is cloned, so it fails straight away.
+        queue_job(&job_store, &queue, job("local", "file:///srv/repo")).await;
+        let mut status = job_store.get("local").await.unwrap();
+        for _ in 0..100 {
+            if status.state.is_terminal() {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+            status = job_store.get("local").await.unwrap();
+        }
+        assert_eq!(status.state, JobState::Failed);
+        assert_eq!(status.error.as_deref(), Some("Invalid URL"));
+    }
+
+    #[actix_web::test]
+    async fn keeps_local_jobs_queued_while_shutting_down() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = runner(dir.path());
+        let job_store = runner.job_store.clone();
+        runner.shutdown.drain(Duration::ZERO).await;
+        let queue = JobQueue::Local(runner);
+
+        queue_job(&job_store, &queue, job("pending", "file:///srv/repo")).await;
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            job_store.get("pending").await.unwrap().state,
+            JobState::Queued
+        );
+    }
+}
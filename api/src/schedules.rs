@@ -0,0 +1,256 @@
+use crate::job_store::{COMPARE_AND_SET, MAX_UPDATE_ATTEMPTS};
+use crate::GourceSettings;
+use chrono::{DateTime, TimeDelta, Utc};
+use croner::errors::CronError;
+use croner::Cron;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+const SCHEDULES_KEY: &str = "gitmotion:schedules";
+
+/// How often due schedules are looked for. Cron expressions have minute
+/// resolution, so runs start up to this long after their time.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A repository rendered again every time its cron expression fires.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Schedule {
+    pub id: String,
+    pub repo_url: String,
+    /// Standard five-field cron expression (or a nickname such as `@weekly`),
+    /// evaluated in UTC.
+    pub cron: String,
+    /// Only commits from this many days before each run are rendered.
+    pub window_days: Option<u32>,
+    /// Encrypted like the access token of a job request.
+    pub access_token: Option<String>,
+    pub settings: GourceSettings,
+    pub created_at: DateTime<Utc>,
+    pub next_run_at: DateTime<Utc>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_job_id: Option<String>,
+}
+
+impl Schedule {
+    /// Settings for a run starting at `now`, with the date window resolved to
+    /// absolute dates.
+    pub fn settings_at(&self, now: DateTime<Utc>) -> GourceSettings {
+        let mut settings = self.settings.clone();
+        if let Some(window_days) = self.window_days {
+            let today = now.date_naive();
+            settings.start_date = Some(today - TimeDelta::days(i64::from(window_days)));
+            settings.stop_date = Some(today);
+        }
+        settings
+    }
+}
+
+/// Shortest time allowed between two runs of a schedule.
+pub const MIN_INTERVAL: TimeDelta = TimeDelta::hours(1);
+/// Runs of a new schedule checked against `MIN_INTERVAL`, about a year of
+/// hourly runs.
+const CHECKED_RUNS: usize = 9000;
+
+#[derive(Error, Debug)]
+pub enum ScheduleError {
+    #[error("Invalid cron expression: {0}")]
+    InvalidCron(#[from] CronError),
+    #[error("Schedules may run at most once an hour")]
+    TooFrequent,
+}
+
+/// The first time after `after` that `cron` fires.
+pub fn next_run(cron: &str, after: DateTime<Utc>) -> Result<DateTime<Utc>, CronError> {
+    Cron::from_str(cron)?.find_next_occurrence(&after, false)
+}
+
+/// The first run of a new schedule, after checking that its runs are at
+/// least `MIN_INTERVAL` apart.
+pub fn first_run(cron: &str, after: DateTime<Utc>) -> Result<DateTime<Utc>, ScheduleError> {
+    let cron = Cron::from_str(cron)?;
+    let first = cron.find_next_occurrence(&after, false)?;
+    let mut previous = first;
+    for run in cron.iter_after(first).take(CHECKED_RUNS) {
+        if run - previous < MIN_INTERVAL {
+            return Err(ScheduleError::TooFrequent);
+        }
+        previous = run;
+    }
+    Ok(first)
+}
+
+/// Registered schedules, kept in process memory or in Redis alongside the
+/// jobs.
+#[derive(Clone)]
+pub enum ScheduleStore {
+    Memory(Arc<Mutex<HashMap<String, Schedule>>>),
+    Redis(ConnectionManager),
+}
+
+impl ScheduleStore {
+    pub fn memory() -> ScheduleStore {
+        ScheduleStore::Memory(Arc::default())
+    }
+
+    pub async fn insert(&self, schedule: &Schedule) {
+        match self {
+            ScheduleStore::Memory(schedules) => {
+                schedules
+                    .lock()
+                    .await
+                    .insert(schedule.id.clone(), schedule.clone());
+            }
+            ScheduleStore::Redis(connection) => {
+                let Some(value) = encode(schedule) else {
+                    return;
+                };
+                let mut connection = connection.clone();
+                if let Err(e) = connection
+                    .hset::<_, _, _, ()>(SCHEDULES_KEY, &schedule.id, value)
+                    .await
+                {
+                    error!("Failed to store schedule {}: {}", schedule.id, e);
+                }
+            }
+        }
+    }
+
+    /// Returns whether the schedule existed.
+    pub async fn remove(&self, id: &str) -> bool {
+        match self {
+            ScheduleStore::Memory(schedules) => schedules.lock().await.remove(id).is_some(),
+            ScheduleStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                match connection.hdel::<_, _, usize>(SCHEDULES_KEY, id).await {
+                    Ok(removed) => removed > 0,
+                    Err(e) => {
+                        error!("Failed to remove schedule {}: {}", id, e);
+                        false
+                    }
+                }
+            }
+        }
+    }
+
+    pub async fn list(&self) -> Vec<Schedule> {
+        match self {
+            ScheduleStore::Memory(schedules) => schedules.lock().await.values().cloned().collect(),
+            ScheduleStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                match connection
+                    .hgetall::<_, HashMap<String, String>>(SCHEDULES_KEY)
+                    .await
+                {
+                    Ok(schedules) => schedules
+                        .iter()
+                        .filter_map(|(id, value)| decode(id, value))
+                        .collect(),
+                    Err(e) => {
+                        error!("Failed to list schedules: {}", e);
+                        Vec::new()
+                    }
+                }
+            }
+        }
+    }
+
+    /// Applies `f` to the schedule and returns its result, or `None` when the
+    /// schedule does not exist. With Redis, `f` may run more than once if
+    /// another process modifies the schedule at the same time.
+    pub async fn update<R>(&self, id: &str, mut f: impl FnMut(&mut Schedule) -> R) -> Option<R> {
+        match self {
+            ScheduleStore::Memory(schedules) => schedules.lock().await.get_mut(id).map(f),
+            ScheduleStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                for _ in 0..MAX_UPDATE_ATTEMPTS {
+                    let current: String = match connection.hget(SCHEDULES_KEY, id).await {
+                        Ok(Some(value)) => value,
+                        Ok(None) => return None,
+                        Err(e) => {
+                            error!("Failed to load schedule {}: {}", id, e);
+                            return None;
+                        }
+                    };
+                    let mut schedule = decode(id, &current)?;
+                    let result = f(&mut schedule);
+                    let updated = encode(&schedule)?;
+
+                    let swapped: Result<i32, _> = COMPARE_AND_SET
+                        .key(SCHEDULES_KEY)
+                        .arg(id)
+                        .arg(&current)
+                        .arg(&updated)
+                        .invoke_async(&mut connection)
+                        .await;
+                    match swapped {
+                        Ok(1) => return Some(result),
+                        Ok(_) => continue,
+                        Err(e) => {
+                            error!("Failed to update schedule {}: {}", id, e);
+                            return None;
+                        }
+                    }
+                }
+                warn!("Gave up updating schedule {} after repeated conflicts", id);
+                None
+            }
+        }
+    }
+}
+
+fn encode(schedule: &Schedule) -> Option<String> {
+    serde_json::to_string(schedule)
+        .map_err(|e| error!("Failed to serialize schedule: {}", e))
+        .ok()
+}
+
+fn decode(id: &str, value: &str) -> Option<Schedule> {
+    serde_json::from_str(value)
+        .map_err(|e| error!("Failed to deserialize schedule {}: {}", id, e))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-03-01T10:20:00Z")
+            .unwrap()
+            .to_utc()
+    }
+
+    #[test]
+    fn accepts_hourly_and_slower_schedules() {
+        for cron in ["0 * * * *", "0 9 * * MON", "@weekly", "0 0,12 * * *"] {
+            assert!(first_run(cron, now()).is_ok(), "{}", cron);
+        }
+        assert_eq!(
+            first_run("0 9 * * *", now()).unwrap(),
+            DateTime::parse_from_rfc3339("2024-03-02T09:00:00Z").unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_schedules_that_run_more_often_than_hourly() {
+        for cron in ["* * * * *", "*/30 * * * *", "0,59 9 * * MON"] {
+            assert!(
+                matches!(first_run(cron, now()), Err(ScheduleError::TooFrequent)),
+                "{}",
+                cron
+            );
+        }
+        assert!(matches!(
+            first_run("not cron", now()),
+            Err(ScheduleError::InvalidCron(_))
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+use crate::job_store::JobStore;
+use crate::queue::{JobQueue, QueuedJob};
+use crate::{JobState, JobStatus};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::task_tracker::TaskTrackerToken;
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, warn};
+
+/// Coordinates stopping the process: first no new jobs are started
+/// (draining), then jobs still running when the drain timeout expires are
+/// interrupted.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    draining: CancellationToken,
+    interrupt: CancellationToken,
+    running: TaskTracker,
+}
+
+impl Shutdown {
+    pub fn is_draining(&self) -> bool {
+        self.draining.is_cancelled()
+    }
+
+    /// Resolves once the process starts shutting down.
+    pub async fn draining(&self) {
+        self.draining.cancelled().await
+    }
+
+    /// Resolves once running jobs must stop.
+    pub async fn interrupted(&self) {
+        self.interrupt.cancelled().await
+    }
+
+    /// Marks a job as running until the returned token is dropped.
+    pub fn track_job(&self) -> TaskTrackerToken {
+        self.running.token()
+    }
+
+    /// Stops new jobs from starting and waits up to `timeout` for running jobs
+    /// to finish before interrupting them.
+    pub async fn drain(&self, timeout: Duration) {
+        self.draining.cancel();
+        self.running.close();
+        if self.running.is_empty() {
+            return;
+        }
+
+        info!(
+            "Waiting up to {:?} for {} running job(s) to finish",
+            timeout,
+            self.running.len()
+        );
+        if tokio::time::timeout(timeout, self.running.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                "Interrupting {} job(s) still running after {:?}",
+                self.running.len(),
+                timeout
+            );
+            self.interrupt.cancel();
+            self.running.wait().await;
+        }
+    }
+}
+
+/// A queued job saved across restarts when job state is kept in memory.
+#[derive(Serialize, Deserialize)]
+struct PendingJob {
+    status: JobStatus,
+    request: QueuedJob,
+}
+
+/// Writes jobs that have not started to `path`, so `restore_pending_jobs` can
+/// queue them again on the next start.
+pub async fn save_pending_jobs(job_store: &JobStore, path: &Path) {
+    let mut pending = Vec::new();
+    for (job_id, status) in job_store.list().await {
+        if status.state != JobState::Queued {
+            continue;
+        }
+        if let Some(request) = job_store.request(&job_id).await {
+            pending.push(PendingJob { status, request });
+        }
+    }
+    if pending.is_empty() {
+        return;
+    }
+
+    let contents = serde_json::to_vec(&pending).expect("pending jobs serialize");
+    match tokio::fs::write(path, contents).await {
+        Ok(()) => info!("Saved {} queued job(s) to {:?}", pending.len(), path),
+        Err(e) => error!("Failed to save queued jobs to {:?}: {}", path, e),
+    }
+}
+
+pub async fn restore_pending_jobs(job_store: &JobStore, queue: &JobQueue, path: &Path) {
+    let contents = match tokio::fs::read(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!("Failed to read queued jobs from {:?}: {}", path, e);
+            return;
+        }
+    };
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        error!("Failed to remove {:?}: {}", path, e);
+    }
+
+    let pending: Vec<PendingJob> = match serde_json::from_slice(&contents) {
+        Ok(pending) => pending,
+        Err(e) => {
+            error!("Failed to parse queued jobs in {:?}: {}", path, e);
+            return;
+        }
+    };
+
+    info!("Restoring {} queued job(s) from {:?}", pending.len(), path);
+    for PendingJob { status, request } in pending {
+        job_store.insert(&request.job_id, status).await;
+        job_store.save_request(&request).await;
+        let job_id = request.job_id.clone();
+        if let Err(e) = queue.enqueue(request).await {
+            error!("Failed to queue restored job {}: {}", job_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::JobProcesses;
+    use crate::queue::RepositorySource;
+    use crate::storage;
+    use crate::tests::runner;
+    use crate::GourceSettings;
+
+    fn job(job_id: &str) -> QueuedJob {
+        QueuedJob {
+            job_id: job_id.to_string(),
+            repo_url: "https://example.com/repo.git".to_string(),
+            repo_urls: Vec::new(),
+            access_token: None,
+            settings: GourceSettings::default(),
+            callback: None,
+            subscription: None,
+            source: RepositorySource::Clone,
+            vcs: None,
+        }
+    }
+
+    async fn insert(job_store: &JobStore, job: QueuedJob, state: JobState) {
+        let mut status = crate::queued_status(&job.repo_url, &job.settings, None);
+        status.state = state;
+        job_store.insert(&job.job_id, status).await;
+        job_store.save_request(&job).await;
+    }
+
+    #[actix_web::test]
+    async fn restores_saved_queued_jobs_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pending.json");
+        let saved = JobStore::memory();
+        insert(&saved, job("queued"), JobState::Queued).await;
+        insert(&saved, job("running"), JobState::Running).await;
+        save_pending_jobs(&saved, &path).await;
+
+        // A draining runner leaves restored jobs queued instead of running them.
+        let runner = runner(dir.path());
+        let job_store = runner.job_store.clone();
+        runner.shutdown.drain(Duration::ZERO).await;
+        let queue = JobQueue::Local(runner);
+        restore_pending_jobs(&job_store, &queue, &path).await;
+
+        let restored = job_store.list().await;
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].0, "queued");
+        assert_eq!(restored[0].1.state, JobState::Queued);
+        let request = job_store.request("queued").await.unwrap();
+        assert_eq!(request.repo_url, "https://example.com/repo.git");
+        assert!(!path.exists());
+
+        // Nothing is restored twice.
+        restore_pending_jobs(&job_store, &queue, &path).await;
+        assert_eq!(job_store.list().await.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn waits_for_running_jobs_to_finish() {
+        let shutdown = Shutdown::default();
+        let token = shutdown.track_job();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(token);
+        });
+
+        shutdown.drain(Duration::from_secs(5)).await;
+        assert!(shutdown.is_draining());
+        assert!(!shutdown.interrupt.is_cancelled());
+    }
+
+    #[actix_web::test]
+    async fn interrupts_jobs_still_running_after_the_timeout() {
+        let shutdown = Shutdown::default();
+        let job = shutdown.clone();
+        let token = shutdown.track_job();
+        tokio::spawn(async move {
+            job.interrupted().await;
+            drop(token);
+        });
+
+        shutdown.drain(Duration::from_millis(20)).await;
+        assert!(shutdown.interrupt.is_cancelled());
+    }
+
+    #[actix_web::test]
+    async fn marks_interrupted_jobs_and_removes_partial_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let runner = runner(dir.path());
+        insert(&runner.job_store, job("job"), JobState::Running).await;
+        let video_path = runner.storage.staging_path(&storage::video_key("job"));
+        std::fs::create_dir_all(video_path.parent().unwrap()).unwrap();
+        std::fs::write(&video_path, b"partial").unwrap();
+
+        runner.interrupt("job", &JobProcesses::default()).await;
+
+        let status = runner.job_store.get("job").await.unwrap();
+        assert_eq!(status.state, JobState::Interrupted);
+        assert_eq!(
+            status.error.as_deref(),
+            Some("The server shut down before the job finished")
+        );
+        assert!(!video_path.exists());
+    }
+}
@@ -0,0 +1,463 @@
+use crate::config::{S3Config, StorageBackend, StorageConfig};
+use async_trait::async_trait;
+use chrono::DateTime;
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// Presigned URLs cannot outlive this (the SigV4 limit is seven days).
+const MAX_PRESIGN_EXPIRY_SECS: u64 = 7 * 24 * 3600;
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Storage I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Object storage error: {0}")]
+    S3(#[from] s3::error::S3Error),
+    #[error("Invalid object storage credentials: {0}")]
+    Credentials(#[from] s3::creds::error::CredentialsError),
+    #[error("Invalid object storage region: {0}")]
+    InvalidRegion(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredArtifact {
+    pub key: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Where rendered artifacts live once a job has finished.
+#[async_trait]
+pub trait ArtifactStore: Send + Sync {
+    /// Local path an artifact should be rendered to before it is handed to `put`.
+    fn staging_path(&self, key: &str) -> PathBuf;
+
+    /// Stores the file at `source` under `key`. The staging file is consumed.
+    async fn put(&self, key: &str, source: &Path, content_type: &str) -> Result<(), StorageError>;
+
+    /// Copies the artifact to `destination` on this host.
+    async fn get(&self, key: &str, destination: &Path) -> Result<(), StorageError>;
+
+    /// Removes the artifact. Deleting a missing artifact is not an error.
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    async fn list(&self) -> Result<Vec<StoredArtifact>, StorageError>;
+
+    /// Path of the artifact on this host, for backends that keep files locally.
+    fn local_path(&self, key: &str) -> Option<PathBuf>;
+
+    /// A time-limited URL clients can fetch the artifact from directly, for
+    /// backends that support it.
+    async fn presigned_url(
+        &self,
+        key: &str,
+        expires_in: Duration,
+    ) -> Result<Option<String>, StorageError>;
+
+    /// Like `presigned_url`, but the response asks the client to save the
+    /// artifact rather than show it.
+    async fn presigned_download_url(
+        &self,
+        key: &str,
+        expires_in: Duration,
+    ) -> Result<Option<String>, StorageError> {
+        self.presigned_url(key, expires_in).await
+    }
+}
+
+/// MIME type an artifact is served with, from the extension of its key.
+pub fn content_type(key: &str) -> &'static str {
+    match Path::new(key)
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("mp4") => "video/mp4",
+        Some("png") => "image/png",
+        _ => "application/octet-stream",
+    }
+}
+
+pub fn video_key(job_id: &str) -> String {
+    format!("gource_{}.mp4", job_id)
+}
+
+pub fn job_id_from_key(key: &str) -> Option<&str> {
+    key.strip_prefix("gource_")?.strip_suffix(".mp4")
+}
+
+/// PNG images rendered from a job's video.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Image {
+    /// Frames across a preview clip, which is not kept.
+    Stills,
+    /// The final frame of the visualization.
+    Poster,
+    /// Frames taken evenly across the visualization.
+    ContactSheet,
+}
+
+impl Image {
+    pub const ALL: [Image; 3] = [Image::Stills, Image::Poster, Image::ContactSheet];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Image::Stills => "stills",
+            Image::Poster => "poster",
+            Image::ContactSheet => "contact_sheet",
+        }
+    }
+}
+
+pub fn image_key(job_id: &str, image: Image) -> String {
+    format!("{}_{}.png", image.name(), job_id)
+}
+
+pub fn job_id_from_image_key(key: &str) -> Option<&str> {
+    Image::ALL.iter().find_map(|image| {
+        key.strip_prefix(image.name())?
+            .strip_prefix('_')?
+            .strip_suffix(".png")
+    })
+}
+
+/// Key of the repository uploaded for a job, kept until the job is removed so
+/// it can be retried.
+pub fn upload_key(job_id: &str) -> String {
+    format!("upload_{}", job_id)
+}
+
+pub fn job_id_from_upload_key(key: &str) -> Option<&str> {
+    key.strip_prefix("upload_")
+}
+
+/// The job a stored video, upload or image belongs to.
+pub fn job_id_from_artifact_key(key: &str) -> Option<&str> {
+    job_id_from_key(key)
+        .or_else(|| job_id_from_upload_key(key))
+        .or_else(|| job_id_from_image_key(key))
+}
+
+pub fn from_config(config: &StorageConfig) -> Result<Arc<dyn ArtifactStore>, StorageError> {
+    match config.backend {
+        StorageBackend::Local => Ok(Arc::new(LocalStore::new(config.video_dir.clone())?)),
+        StorageBackend::S3 => Ok(Arc::new(S3Store::new(&config.s3)?)),
+    }
+}
+
+pub struct LocalStore {
+    dir: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(dir: PathBuf) -> Result<LocalStore, StorageError> {
+        fs::create_dir_all(&dir)?;
+        Ok(LocalStore { dir })
+    }
+}
+
+#[async_trait]
+impl ArtifactStore for LocalStore {
+    fn staging_path(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+
+    async fn put(&self, key: &str, source: &Path, _content_type: &str) -> Result<(), StorageError> {
+        let destination = self.dir.join(key);
+        // Rename fails across filesystems; fall back to copying.
+        if source != destination && tokio::fs::rename(source, &destination).await.is_err() {
+            tokio::fs::copy(source, &destination).await?;
+            tokio::fs::remove_file(source).await?;
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, destination: &Path) -> Result<(), StorageError> {
+        tokio::fs::copy(self.dir.join(key), destination).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.dir.join(key)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<StoredArtifact>, StorageError> {
+        let mut artifacts = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            if let Some(key) = entry.file_name().to_str() {
+                artifacts.push(StoredArtifact {
+                    key: key.to_string(),
+                    size: metadata.len(),
+                    modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                });
+            }
+        }
+        Ok(artifacts)
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.dir.join(key))
+    }
+
+    async fn presigned_url(
+        &self,
+        _key: &str,
+        _expires_in: Duration,
+    ) -> Result<Option<String>, StorageError> {
+        Ok(None)
+    }
+}
+
+/// Stores artifacts in an S3-compatible bucket (AWS S3, MinIO, R2, ...).
+pub struct S3Store {
+    bucket: Box<Bucket>,
+    staging_dir: PathBuf,
+}
+
+impl S3Store {
+    pub fn new(config: &S3Config) -> Result<S3Store, StorageError> {
+        let region = match &config.endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => config
+                .region
+                .parse()
+                .map_err(|_| StorageError::InvalidRegion(config.region.clone()))?,
+        };
+        let credentials = Credentials::new(
+            config.access_key_id.as_deref(),
+            config.secret_access_key.as_deref(),
+            None,
+            None,
+            None,
+        )?;
+
+        let mut bucket = Bucket::new(&config.bucket, region, credentials)?;
+        if config.path_style {
+            bucket = bucket.with_path_style();
+        }
+
+        let staging_dir = std::env::temp_dir().join("gitmotion-staging");
+        fs::create_dir_all(&staging_dir)?;
+
+        Ok(S3Store {
+            bucket,
+            staging_dir,
+        })
+    }
+}
+
+#[async_trait]
+impl ArtifactStore for S3Store {
+    fn staging_path(&self, key: &str) -> PathBuf {
+        self.staging_dir.join(key)
+    }
+
+    async fn put(&self, key: &str, source: &Path, content_type: &str) -> Result<(), StorageError> {
+        let mut file = tokio::fs::File::open(source).await?;
+        let result = self
+            .bucket
+            .put_object_stream_with_content_type(&mut file, key, content_type)
+            .await;
+        drop(file);
+        tokio::fs::remove_file(source).await?;
+        result?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, destination: &Path) -> Result<(), StorageError> {
+        let mut file = tokio::fs::File::create(destination).await?;
+        self.bucket.get_object_to_writer(key, &mut file).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.bucket.delete_object(key).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<StoredArtifact>, StorageError> {
+        let results = self.bucket.list(String::new(), None).await?;
+        Ok(results
+            .into_iter()
+            .flat_map(|result| result.contents)
+            .map(|object| StoredArtifact {
+                modified: DateTime::parse_from_rfc3339(&object.last_modified)
+                    .map(SystemTime::from)
+                    .unwrap_or(SystemTime::UNIX_EPOCH),
+                key: object.key,
+                size: object.size,
+            })
+            .collect())
+    }
+
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+
+    async fn presigned_url(
+        &self,
+        key: &str,
+        expires_in: Duration,
+    ) -> Result<Option<String>, StorageError> {
+        let expiry_secs = expires_in.as_secs().clamp(1, MAX_PRESIGN_EXPIRY_SECS) as u32;
+        Ok(Some(self.bucket.presign_get(key, expiry_secs, None).await?))
+    }
+
+    async fn presigned_download_url(
+        &self,
+        key: &str,
+        expires_in: Duration,
+    ) -> Result<Option<String>, StorageError> {
+        let expiry_secs = expires_in.as_secs().clamp(1, MAX_PRESIGN_EXPIRY_SECS) as u32;
+        let queries = HashMap::from([(
+            "response-content-disposition".to_string(),
+            format!("attachment; filename=\"{}\"", key),
+        )]);
+        Ok(Some(
+            self.bucket
+                .presign_get(key, expiry_secs, Some(queries))
+                .await?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_name_the_job_they_belong_to() {
+        assert_eq!(video_key("abc"), "gource_abc.mp4");
+        assert_eq!(upload_key("abc"), "upload_abc");
+        assert_eq!(
+            image_key("abc", Image::ContactSheet),
+            "contact_sheet_abc.png"
+        );
+
+        for key in [
+            video_key("abc"),
+            upload_key("abc"),
+            image_key("abc", Image::Stills),
+            image_key("abc", Image::Poster),
+            image_key("abc", Image::ContactSheet),
+        ] {
+            assert_eq!(job_id_from_artifact_key(&key), Some("abc"), "{}", key);
+        }
+        assert_eq!(job_id_from_artifact_key("queued-jobs.json"), None);
+        assert_eq!(job_id_from_image_key("poster_abc.mp4"), None);
+
+        assert_eq!(content_type(&video_key("abc")), "video/mp4");
+        assert_eq!(content_type(&image_key("abc", Image::Poster)), "image/png");
+        assert_eq!(content_type(&upload_key("abc")), "application/octet-stream");
+    }
+
+    #[tokio::test]
+    async fn local_store_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalStore::new(dir.path().join("artifacts")).unwrap();
+        let key = video_key("storage-test");
+
+        // Rendered in place, so storing it leaves it where it is.
+        let staging = store.staging_path(&key);
+        tokio::fs::write(&staging, b"not really a video")
+            .await
+            .unwrap();
+        store.put(&key, &staging, "video/mp4").await.unwrap();
+        assert_eq!(store.local_path(&key), Some(staging.clone()));
+        assert!(staging.exists());
+
+        // Anywhere else, the file is moved in.
+        let upload = dir.path().join("upload.tar");
+        tokio::fs::write(&upload, b"bundle").await.unwrap();
+        store
+            .put(&upload_key("storage-test"), &upload, "application/x-tar")
+            .await
+            .unwrap();
+        assert!(!upload.exists());
+
+        let mut listed: Vec<(String, u64)> = store
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|artifact| (artifact.key, artifact.size))
+            .collect();
+        listed.sort();
+        assert_eq!(listed, [(key.clone(), 18), (upload_key("storage-test"), 6)]);
+
+        let copy = dir.path().join("copy.mp4");
+        store.get(&key, &copy).await.unwrap();
+        assert_eq!(tokio::fs::read(&copy).await.unwrap(), b"not really a video");
+        assert_eq!(
+            store
+                .presigned_url(&key, Duration::from_secs(60))
+                .await
+                .unwrap(),
+            None
+        );
+
+        store.delete(&key).await.unwrap();
+        store.delete(&key).await.unwrap();
+        assert_eq!(store.list().await.unwrap().len(), 1);
+    }
+
+    /// Round-trips an artifact through a real S3-compatible server. Start one with
+    /// `docker compose --profile s3 up minio` and run
+    /// `S3_TEST_ENDPOINT=http://localhost:9000 cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn s3_store_round_trip() {
+        let endpoint =
+            std::env::var("S3_TEST_ENDPOINT").unwrap_or_else(|_| "http://localhost:9000".into());
+        let config = S3Config {
+            bucket: std::env::var("S3_TEST_BUCKET").unwrap_or_else(|_| "gitmotion".into()),
+            region: "us-east-1".into(),
+            endpoint: Some(endpoint),
+            access_key_id: Some("minioadmin".into()),
+            secret_access_key: Some("minioadmin".into()),
+            path_style: true,
+        };
+        let store = S3Store::new(&config).unwrap();
+
+        let key = video_key("storage-test");
+        let staging = store.staging_path(&key);
+        tokio::fs::write(&staging, b"not really a video")
+            .await
+            .unwrap();
+        store.put(&key, &staging, "video/mp4").await.unwrap();
+        assert!(!staging.exists());
+
+        let listed = store.list().await.unwrap();
+        let artifact = listed.iter().find(|a| a.key == key).unwrap();
+        assert_eq!(artifact.size, 18);
+
+        let url = store
+            .presigned_url(&key, Duration::from_secs(60))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(url.contains("X-Amz-Signature"));
+
+        store.delete(&key).await.unwrap();
+        assert!(!store.list().await.unwrap().iter().any(|a| a.key == key));
+    }
+}
@@ -0,0 +1,204 @@
+use crate::GourceSettings;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::error;
+
+const SUBSCRIPTIONS_KEY: &str = "gitmotion:subscriptions";
+/// The job holding each subscription's most recent video.
+const LATEST_KEY: &str = "gitmotion:latest";
+
+/// A repository whose video is re-rendered whenever its branch is pushed to.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Subscription {
+    pub owner: String,
+    pub name: String,
+    pub repo_url: String,
+    pub branch: String,
+    /// Encrypted like the access token of a job request.
+    pub access_token: Option<String>,
+    pub settings: GourceSettings,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Subscription {
+    pub fn key(&self) -> String {
+        subscription_key(&self.owner, &self.name)
+    }
+}
+
+/// GitHub treats owner and repository names case-insensitively.
+pub fn subscription_key(owner: &str, name: &str) -> String {
+    format!("{}/{}", owner, name).to_lowercase()
+}
+
+/// Registered subscriptions, kept in process memory or in Redis alongside the
+/// jobs.
+#[derive(Clone)]
+pub enum SubscriptionStore {
+    Memory(Arc<Mutex<MemorySubscriptions>>),
+    Redis(ConnectionManager),
+}
+
+#[derive(Default)]
+pub struct MemorySubscriptions {
+    subscriptions: HashMap<String, Subscription>,
+    latest: HashMap<String, String>,
+}
+
+impl SubscriptionStore {
+    pub fn memory() -> SubscriptionStore {
+        SubscriptionStore::Memory(Arc::default())
+    }
+
+    pub async fn insert(&self, subscription: &Subscription) {
+        match self {
+            SubscriptionStore::Memory(state) => {
+                state
+                    .lock()
+                    .await
+                    .subscriptions
+                    .insert(subscription.key(), subscription.clone());
+            }
+            SubscriptionStore::Redis(connection) => {
+                let value = serde_json::to_string(subscription).expect("subscription serializes");
+                let mut connection = connection.clone();
+                if let Err(e) = connection
+                    .hset::<_, _, _, ()>(SUBSCRIPTIONS_KEY, subscription.key(), value)
+                    .await
+                {
+                    error!("Failed to store subscription {}: {}", subscription.key(), e);
+                }
+            }
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Option<Subscription> {
+        match self {
+            SubscriptionStore::Memory(state) => state.lock().await.subscriptions.get(key).cloned(),
+            SubscriptionStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                match connection
+                    .hget::<_, _, Option<String>>(SUBSCRIPTIONS_KEY, key)
+                    .await
+                {
+                    Ok(value) => value.and_then(|value| decode(key, &value)),
+                    Err(e) => {
+                        error!("Failed to load subscription {}: {}", key, e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes the subscription and returns the job holding its latest video.
+    pub async fn remove(&self, key: &str) -> Option<String> {
+        match self {
+            SubscriptionStore::Memory(state) => {
+                let mut state = state.lock().await;
+                state.subscriptions.remove(key);
+                state.latest.remove(key)
+            }
+            SubscriptionStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                let removed: redis::RedisResult<(Option<String>,)> = redis::pipe()
+                    .atomic()
+                    .hget(LATEST_KEY, key)
+                    .hdel(SUBSCRIPTIONS_KEY, key)
+                    .ignore()
+                    .hdel(LATEST_KEY, key)
+                    .ignore()
+                    .query_async(&mut connection)
+                    .await;
+                match removed {
+                    Ok((latest,)) => latest,
+                    Err(e) => {
+                        error!("Failed to remove subscription {}: {}", key, e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    pub async fn list(&self) -> Vec<Subscription> {
+        match self {
+            SubscriptionStore::Memory(state) => {
+                state.lock().await.subscriptions.values().cloned().collect()
+            }
+            SubscriptionStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                match connection
+                    .hgetall::<_, HashMap<String, String>>(SUBSCRIPTIONS_KEY)
+                    .await
+                {
+                    Ok(subscriptions) => subscriptions
+                        .iter()
+                        .filter_map(|(key, value)| decode(key, value))
+                        .collect(),
+                    Err(e) => {
+                        error!("Failed to list subscriptions: {}", e);
+                        Vec::new()
+                    }
+                }
+            }
+        }
+    }
+
+    /// The job holding the subscription's most recent video.
+    pub async fn latest(&self, key: &str) -> Option<String> {
+        match self {
+            SubscriptionStore::Memory(state) => state.lock().await.latest.get(key).cloned(),
+            SubscriptionStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                match connection.hget(LATEST_KEY, key).await {
+                    Ok(job_id) => job_id,
+                    Err(e) => {
+                        error!("Failed to load latest video of {}: {}", key, e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Points the subscription at a new video, returning the job that held
+    /// the previous one.
+    pub async fn set_latest(&self, key: &str, job_id: &str) -> Option<String> {
+        match self {
+            SubscriptionStore::Memory(state) => state
+                .lock()
+                .await
+                .latest
+                .insert(key.to_string(), job_id.to_string()),
+            SubscriptionStore::Redis(connection) => {
+                let mut connection = connection.clone();
+                let replaced: redis::RedisResult<(Option<String>,)> = redis::pipe()
+                    .atomic()
+                    .hget(LATEST_KEY, key)
+                    .hset(LATEST_KEY, key, job_id)
+                    .ignore()
+                    .query_async(&mut connection)
+                    .await;
+                match replaced {
+                    Ok((previous,)) => previous,
+                    Err(e) => {
+                        error!("Failed to store latest video of {}: {}", key, e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn decode(key: &str, value: &str) -> Option<Subscription> {
+    serde_json::from_str(value)
+        .map_err(|e| error!("Failed to deserialize subscription {}: {}", key, e))
+        .ok()
+}
@@ -0,0 +1,115 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+#[derive(Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Keeps the OTLP exporter alive; spans still buffered are flushed when dropped.
+pub struct TelemetryGuard {
+    #[cfg(feature = "otlp")]
+    tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = self.tracer_provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to shut down OTLP exporter: {}", e);
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber. `RUST_LOG` controls the filter (default `info`).
+/// When `otlp_endpoint` is set and the `otlp` feature is enabled, spans are also
+/// exported over OTLP/HTTP.
+pub fn init(log_format: LogFormat, otlp_endpoint: Option<&str>) -> TelemetryGuard {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let fmt_layer = match log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    #[cfg(feature = "otlp")]
+    {
+        let tracer_provider = otlp_endpoint.and_then(|endpoint| match otlp::provider(endpoint) {
+            Ok(provider) => Some(provider),
+            Err(e) => {
+                eprintln!("Failed to configure OTLP exporter: {}", e);
+                None
+            }
+        });
+        let otel_layer = tracer_provider.as_ref().map(otlp::layer);
+
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt_layer)
+            .with(otel_layer)
+            .init();
+
+        TelemetryGuard { tracer_provider }
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt_layer)
+            .init();
+
+        if otlp_endpoint.is_some() {
+            tracing::warn!("OTLP endpoint configured but the `otlp` feature is not enabled");
+        }
+
+        TelemetryGuard {}
+    }
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use tracing_subscriber::registry::LookupSpan;
+
+    pub fn provider(
+        endpoint: &str,
+    ) -> Result<SdkTracerProvider, opentelemetry_otlp::ExporterBuildError> {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()?;
+
+        Ok(SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name("gitmotion-api")
+                    .build(),
+            )
+            .build())
+    }
+
+    pub fn layer<S>(
+        provider: &SdkTracerProvider,
+    ) -> tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("gitmotion-api"))
+    }
+}
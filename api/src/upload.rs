@@ -0,0 +1,299 @@
+use crate::custom_log::{self, CustomLogError};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, EntryType};
+use thiserror::Error;
+
+const BUNDLE_SIGNATURES: &[&[u8]] = &[b"# v2 git bundle\n", b"# v3 git bundle\n"];
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+/// Offset of the `ustar` magic in a tar header.
+const TAR_MAGIC_OFFSET: usize = 257;
+
+/// What a repository upload contains.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadKind {
+    /// Created with `git bundle create`.
+    Bundle,
+    /// A tar archive, optionally gzipped, of a `.git` directory or bare
+    /// repository.
+    Tarball,
+    /// A gource custom log, rendered as is without cloning anything.
+    Log,
+}
+
+#[derive(Error, Debug)]
+pub enum UploadError {
+    #[error("Failed to read the upload: {0}")]
+    Io(#[from] io::Error),
+    #[error("The upload is neither a git bundle, a tar archive nor a gource log")]
+    UnknownFormat,
+    #[error(transparent)]
+    InvalidLog(CustomLogError),
+    #[error("The archive entry {0:?} would be extracted outside of the repository")]
+    UnsafePath(PathBuf),
+    #[error("The archive entry {0:?} is not a regular file or directory")]
+    UnsupportedEntry(PathBuf),
+    #[error("The archive does not contain a git repository")]
+    NoRepository,
+    #[error("The archive unpacks to more than {0} bytes")]
+    TooLarge(u64),
+}
+
+/// Works out what the uploaded file is and checks that it can be extracted
+/// safely. Archives may unpack to at most `max_bytes`.
+pub fn inspect(path: &Path, max_bytes: u64) -> Result<UploadKind, UploadError> {
+    let mut header = [0u8; TAR_MAGIC_OFFSET + 5];
+    let read = read_prefix(&mut File::open(path)?, &mut header)?;
+    let header = &header[..read];
+
+    if BUNDLE_SIGNATURES
+        .iter()
+        .any(|signature| header.starts_with(signature))
+    {
+        return Ok(UploadKind::Bundle);
+    }
+    if header.starts_with(GZIP_MAGIC) || header.ends_with(b"ustar") {
+        repository_root(path, max_bytes)?;
+        return Ok(UploadKind::Tarball);
+    }
+    match custom_log::validate(path) {
+        Ok(()) => Ok(UploadKind::Log),
+        Err(CustomLogError::NotText) => Err(UploadError::UnknownFormat),
+        Err(e) => Err(UploadError::InvalidLog(e)),
+    }
+}
+
+/// Extracts a tarball accepted by [`inspect`] into `destination` and returns
+/// the git directory inside it.
+pub fn extract_tarball(
+    archive: &Path,
+    destination: &Path,
+    max_bytes: u64,
+) -> Result<PathBuf, UploadError> {
+    // Checked again because the stored upload is not trusted any more than
+    // the request was.
+    let root = repository_root(archive, max_bytes)?;
+    open_archive(archive)?.unpack(destination)?;
+    Ok(destination.join(root))
+}
+
+/// Validates every entry of the archive and returns the directory holding
+/// the repository, relative to the archive root.
+fn repository_root(path: &Path, max_bytes: u64) -> Result<PathBuf, UploadError> {
+    let mut archive = open_archive(path)?;
+    let mut files = Vec::new();
+    let mut directories = Vec::new();
+    let mut total_bytes: u64 = 0;
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        let safe = entry_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !safe {
+            return Err(UploadError::UnsafePath(entry_path));
+        }
+        let normalized: PathBuf = entry_path.components().collect();
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous => files.push(normalized),
+            EntryType::Directory => directories.push(normalized),
+            _ => return Err(UploadError::UnsupportedEntry(entry_path)),
+        }
+        total_bytes = total_bytes.saturating_add(entry.size());
+        if total_bytes > max_bytes {
+            return Err(UploadError::TooLarge(max_bytes));
+        }
+    }
+
+    // A git directory has a HEAD file next to an objects directory; the
+    // shallowest one wins so a `.git` inside a checkout is found too.
+    let mut roots: Vec<PathBuf> = files
+        .iter()
+        .filter(|file| file.file_name().is_some_and(|name| name == "HEAD"))
+        .map(|head| head.parent().unwrap_or(Path::new("")).to_path_buf())
+        .filter(|root| {
+            let objects = root.join("objects");
+            directories.contains(&objects) || files.iter().any(|file| file.starts_with(&objects))
+        })
+        .collect();
+    roots.sort_by_key(|root| root.components().count());
+    roots.into_iter().next().ok_or(UploadError::NoRepository)
+}
+
+fn open_archive(path: &Path) -> Result<Archive<Box<dyn Read>>, UploadError> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 2];
+    let read = read_prefix(&mut file, &mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    let reader: Box<dyn Read> = if magic[..read] == *GZIP_MAGIC {
+        Box::new(GzDecoder::new(BufReader::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    Ok(Archive::new(reader))
+}
+
+/// Fills as much of `buffer` as the file allows.
+fn read_prefix(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+/// Resolves `path` and returns it if it lies inside one of the allowed
+/// directories. Symlinks are followed first so they cannot point elsewhere.
+pub fn allowed_local_path(path: &Path, allowed: &[PathBuf]) -> Option<PathBuf> {
+    let resolved = path.canonicalize().ok()?;
+    allowed
+        .iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .any(|root| resolved.starts_with(root))
+        .then_some(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tar::{Builder, Header};
+
+    const MAX_BYTES: u64 = 1024;
+
+    /// A header written as is, so the names may be ones `tar` refuses to
+    /// build archives with.
+    fn header(name: &str, kind: EntryType, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(kind);
+        header.set_mode(0o644);
+        header.set_size(size);
+        header.set_cksum();
+        header
+    }
+
+    fn link(name: &str, kind: EntryType, target: &str) -> Header {
+        let mut header = header(name, kind, 0);
+        header.set_link_name(target).unwrap();
+        header.set_cksum();
+        header
+    }
+
+    /// A tarball of a bare repository under `repo/`, followed by `extra`.
+    fn tarball(dir: &tempfile::TempDir, extra: Vec<(Header, Vec<u8>)>) -> PathBuf {
+        let path = dir.path().join("upload.tar");
+        let mut builder = Builder::new(File::create(&path).unwrap());
+        let mut entries = vec![
+            (header("repo/", EntryType::Directory, 0), Vec::new()),
+            (header("repo/objects/", EntryType::Directory, 0), Vec::new()),
+            (
+                header("repo/HEAD", EntryType::Regular, 21),
+                b"ref: refs/heads/main\n".to_vec(),
+            ),
+        ];
+        entries.extend(extra);
+        for (header, data) in entries {
+            builder.append(&header, data.as_slice()).unwrap();
+        }
+        builder.finish().unwrap();
+        path
+    }
+
+    fn extract(archive: &Path) -> Result<PathBuf, UploadError> {
+        let destination = archive.with_file_name("extracted");
+        extract_tarball(archive, &destination, MAX_BYTES)
+    }
+
+    #[test]
+    fn extracts_a_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tarball(&dir, Vec::new());
+        assert_eq!(inspect(&archive, MAX_BYTES).unwrap(), UploadKind::Tarball);
+        let root = extract(&archive).unwrap();
+        assert_eq!(root, dir.path().join("extracted/repo"));
+        assert!(root.join("HEAD").is_file());
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_repository() {
+        for name in ["../evil", "repo/../../evil", "/tmp/evil"] {
+            let dir = tempfile::tempdir().unwrap();
+            let archive = tarball(
+                &dir,
+                vec![(header(name, EntryType::Regular, 4), b"evil".to_vec())],
+            );
+            assert!(
+                matches!(
+                    inspect(&archive, MAX_BYTES),
+                    Err(UploadError::UnsafePath(_))
+                ),
+                "{}",
+                name
+            );
+            assert!(matches!(extract(&archive), Err(UploadError::UnsafePath(_))));
+            assert!(!dir.path().join("evil").exists());
+            assert!(!dir.path().join("extracted").exists());
+        }
+    }
+
+    #[test]
+    fn rejects_links() {
+        for kind in [EntryType::Symlink, EntryType::Link] {
+            let dir = tempfile::tempdir().unwrap();
+            let archive = tarball(
+                &dir,
+                vec![(link("repo/config", kind, "/etc/passwd"), Vec::new())],
+            );
+            assert!(
+                matches!(
+                    inspect(&archive, MAX_BYTES),
+                    Err(UploadError::UnsupportedEntry(_))
+                ),
+                "{:?}",
+                kind
+            );
+            assert!(matches!(
+                extract(&archive),
+                Err(UploadError::UnsupportedEntry(_))
+            ));
+            assert!(!dir.path().join("extracted").exists());
+        }
+    }
+
+    #[test]
+    fn rejects_entries_larger_than_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = tarball(
+            &dir,
+            vec![(
+                header("repo/objects/pack", EntryType::Regular, MAX_BYTES),
+                vec![0; MAX_BYTES as usize],
+            )],
+        );
+        assert!(matches!(
+            inspect(&archive, MAX_BYTES),
+            Err(UploadError::TooLarge(MAX_BYTES))
+        ));
+
+        // A header may claim far more than the archive holds.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("upload.tar");
+        let mut archive = Vec::new();
+        archive.extend_from_slice(header("repo/HEAD", EntryType::Regular, 1 << 40).as_bytes());
+        fs::write(&path, archive).unwrap();
+        assert!(matches!(
+            inspect(&path, MAX_BYTES),
+            Err(UploadError::TooLarge(MAX_BYTES))
+        ));
+        assert!(matches!(extract(&path), Err(UploadError::TooLarge(_))));
+    }
+}
@@ -0,0 +1,920 @@
+use crate::process::JobProcesses;
+use crate::GourceError;
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{Command, Output};
+use tracing::{error, info};
+use url::Url;
+
+/// A version control system repositories can be rendered from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VcsKind {
+    #[default]
+    Git,
+    #[serde(alias = "mercurial")]
+    Hg,
+    #[serde(alias = "subversion")]
+    Svn,
+}
+
+impl VcsKind {
+    /// The system a URL names through its scheme: `svn://` and `svn+ssh://`,
+    /// or a `git+`, `hg+` or `svn+` prefix on another scheme, as in
+    /// `hg+https://example.com/repo`.
+    pub fn from_url(repo_url: &str) -> Option<VcsKind> {
+        let scheme = repo_url.split_once("://")?.0.to_ascii_lowercase();
+        match scheme
+            .split_once('+')
+            .map_or(scheme.as_str(), |(vcs, _)| vcs)
+        {
+            "git" => Some(VcsKind::Git),
+            "hg" => Some(VcsKind::Hg),
+            "svn" => Some(VcsKind::Svn),
+            _ => None,
+        }
+    }
+
+    /// `explicit` when given, otherwise what the URL names, otherwise git.
+    pub fn resolve(explicit: Option<VcsKind>, repo_url: &str) -> VcsKind {
+        explicit
+            .or_else(|| VcsKind::from_url(repo_url))
+            .unwrap_or_default()
+    }
+
+    pub fn implementation(self) -> &'static dyn Vcs {
+        match self {
+            VcsKind::Git => &Git,
+            VcsKind::Hg => &Mercurial,
+            VcsKind::Svn => &Subversion,
+        }
+    }
+}
+
+/// The URL to hand to the VCS client, without a `git+`, `hg+` or `svn+`
+/// prefix. `svn+ssh://` is understood by svn itself and kept.
+pub fn client_url(repo_url: &str) -> &str {
+    if repo_url.starts_with("svn+ssh://") {
+        return repo_url;
+    }
+    ["git+", "hg+", "svn+"]
+        .iter()
+        .find_map(|prefix| repo_url.strip_prefix(prefix))
+        .unwrap_or(repo_url)
+}
+
+/// Whether `repo_url` reaches a repository on another host: https, http,
+/// ssh, git or svn, optionally behind a `git+`, `hg+` or `svn+` prefix.
+/// `file://` and other schemes would clone from the server itself.
+pub fn is_network_url(repo_url: &str) -> bool {
+    const NETWORK_SCHEMES: [&str; 5] = ["https", "http", "ssh", "git", "svn"];
+    let Ok(url) = Url::parse(repo_url) else {
+        return false;
+    };
+    let transport = match url.scheme().split_once('+') {
+        Some(("git" | "hg" | "svn", transport)) => transport,
+        Some(_) => return false,
+        None => url.scheme(),
+    };
+    NETWORK_SCHEMES.contains(&transport) && url.host_str().is_some_and(|host| !host.is_empty())
+}
+
+/// Commits made on each day of a history.
+pub type CommitsPerDay = BTreeMap<NaiveDate, u32>;
+
+/// A single commit, by Unix time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Commit {
+    pub timestamp: i64,
+    pub author: String,
+}
+
+/// Commit counts of a history, by (UTC) day and by author, and its commits
+/// oldest first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HistoryStats {
+    pub commits_per_day: CommitsPerDay,
+    pub commits_per_author: BTreeMap<String, u32>,
+    pub commits: Vec<Commit>,
+}
+
+impl HistoryStats {
+    /// Counts a commit. Commits may be added in any order.
+    pub fn add_commit(&mut self, timestamp: i64, author: &str) {
+        let Some(day) = utc_day(timestamp) else {
+            return;
+        };
+        *self.commits_per_day.entry(day).or_default() += 1;
+        *self
+            .commits_per_author
+            .entry(author.to_string())
+            .or_default() += 1;
+        self.commits.push(Commit {
+            timestamp,
+            author: author.to_string(),
+        });
+    }
+
+    /// Adds the commits of another repository's history.
+    pub fn merge(&mut self, other: HistoryStats) {
+        for (day, commits) in other.commits_per_day {
+            *self.commits_per_day.entry(day).or_default() += commits;
+        }
+        for (author, commits) in other.commits_per_author {
+            *self.commits_per_author.entry(author).or_default() += commits;
+        }
+        self.commits.extend(other.commits);
+        self.sort();
+    }
+
+    fn sort(&mut self) {
+        self.commits.sort_by_key(|commit| commit.timestamp);
+    }
+
+    pub fn total_commits(&self) -> u32 {
+        self.commits_per_day.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commits_per_day.is_empty()
+    }
+
+    /// The first and last days with commits.
+    pub fn span(&self) -> Option<(NaiveDate, NaiveDate)> {
+        let first = self.commits_per_day.keys().next()?;
+        let last = self.commits_per_day.keys().next_back()?;
+        Some((*first, *last))
+    }
+}
+
+/// A tagged revision, such as a release.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub timestamp: i64,
+}
+
+/// Days to count commits in, both inclusive. Unbounded ends are open.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DateRange {
+    pub start: Option<NaiveDate>,
+    pub stop: Option<NaiveDate>,
+}
+
+impl DateRange {
+    pub fn is_bounded(&self) -> bool {
+        self.start.is_some() || self.stop.is_some()
+    }
+
+    pub fn contains(&self, day: NaiveDate) -> bool {
+        self.start.is_none_or(|start| day >= start) && self.stop.is_none_or(|stop| day <= stop)
+    }
+
+    /// Whether the UTC day of `timestamp` is in the range.
+    pub fn contains_time(&self, timestamp: i64) -> bool {
+        utc_day(timestamp).is_some_and(|day| self.contains(day))
+    }
+}
+
+pub fn utc_day(timestamp: i64) -> Option<NaiveDate> {
+    DateTime::from_timestamp(timestamp, 0).map(|time| time.date_naive())
+}
+
+/// What the render pipeline needs from a version control system.
+pub trait Vcs: Sync {
+    /// Checks out `url`, which already carries any credentials, into
+    /// `destination`.
+    fn clone_repository(
+        &self,
+        url: &str,
+        destination: &Path,
+        processes: &JobProcesses,
+    ) -> Result<(), GourceError>;
+
+    /// The number of commits made within `range`, by day and by author.
+    fn history_stats(
+        &self,
+        checkout: &Path,
+        range: &DateRange,
+    ) -> Result<HistoryStats, GourceError>;
+
+    /// The tags of the checkout, in no particular order.
+    fn tags(&self, checkout: &Path) -> Result<Vec<Tag>, GourceError>;
+
+    /// The history of the checkout as gource custom log lines
+    /// (`timestamp|username|type|/path`), oldest first.
+    fn custom_log(&self, checkout: &Path, processes: &JobProcesses) -> Result<String, GourceError>;
+}
+
+pub struct Git;
+pub struct Mercurial;
+pub struct Subversion;
+
+impl Vcs for Git {
+    fn clone_repository(
+        &self,
+        url: &str,
+        destination: &Path,
+        processes: &JobProcesses,
+    ) -> Result<(), GourceError> {
+        run_clone(
+            Command::new("git").arg("clone").arg(url).arg(destination),
+            processes,
+        )
+    }
+
+    fn history_stats(
+        &self,
+        checkout: &Path,
+        range: &DateRange,
+    ) -> Result<HistoryStats, GourceError> {
+        let mut date_args = Vec::new();
+        if let Some(start) = range.start {
+            date_args.push(format!("--since={} 00:00:00", start));
+        }
+        if let Some(stop) = range.stop {
+            date_args.push(format!("--until={} 23:59:59", stop));
+        }
+
+        let log_output = run_query(
+            Command::new("git")
+                .args(["log", "--format=%at%x09%aN"])
+                .args(&date_args)
+                .current_dir(checkout),
+        )?;
+        // git applies the range in local time, which may differ from UTC.
+        Ok(tally(log_output.lines().filter_map(parse_commit), range))
+    }
+
+    fn tags(&self, checkout: &Path) -> Result<Vec<Tag>, GourceError> {
+        // The creator date is the tagger's for annotated tags and the
+        // commit's otherwise.
+        let output = run_query(
+            Command::new("git")
+                .args([
+                    "for-each-ref",
+                    "--format=%(creatordate:unix)%09%(refname:short)",
+                    "refs/tags",
+                ])
+                .current_dir(checkout),
+        )?;
+        Ok(output.lines().filter_map(parse_tag).collect())
+    }
+
+    fn custom_log(&self, checkout: &Path, processes: &JobProcesses) -> Result<String, GourceError> {
+        let output = run_log(
+            Command::new("git")
+                .args([
+                    "-c",
+                    "core.quotepath=off",
+                    "log",
+                    "--reverse",
+                    "--no-renames",
+                    "--name-status",
+                    "--encoding=UTF-8",
+                    "--pretty=format:user:%aN%n%ct",
+                ])
+                .current_dir(checkout),
+            processes,
+        )?;
+
+        // Each commit is `user:NAME`, its timestamp, then `STATUS\tPATH` lines.
+        let mut log = String::new();
+        let mut username = "";
+        let mut timestamp: Option<i64> = None;
+        for line in output.lines() {
+            if let Some(name) = line.strip_prefix("user:") {
+                username = name;
+                timestamp = None;
+            } else if timestamp.is_none() {
+                timestamp = line.trim().parse().ok();
+            } else if let (Some(timestamp), Some((status, path))) =
+                (timestamp, line.split_once('\t'))
+            {
+                let action = match status {
+                    "A" => "A",
+                    "D" => "D",
+                    _ => "M",
+                };
+                push_entry(&mut log, timestamp, username, action, path);
+            }
+        }
+        Ok(log)
+    }
+}
+
+impl Vcs for Mercurial {
+    fn clone_repository(
+        &self,
+        url: &str,
+        destination: &Path,
+        processes: &JobProcesses,
+    ) -> Result<(), GourceError> {
+        // The history is all that is rendered, so no working copy is needed.
+        run_clone(
+            hg().args(["clone", "--noupdate", "--quiet", "--"])
+                .arg(url)
+                .arg(destination),
+            processes,
+        )
+    }
+
+    fn history_stats(
+        &self,
+        checkout: &Path,
+        range: &DateRange,
+    ) -> Result<HistoryStats, GourceError> {
+        let output = run_query(
+            hg().args(["log", "--template", "{date|hgdate}\\t{author|person}\\n"])
+                .current_dir(checkout),
+        )?;
+        Ok(tally(output.lines().filter_map(parse_commit), range))
+    }
+
+    fn tags(&self, checkout: &Path) -> Result<Vec<Tag>, GourceError> {
+        // `tip` is not a real tag but is listed like one.
+        let output = run_query(
+            hg().args([
+                "log",
+                "--rev",
+                "tag()",
+                "--template",
+                "{tags % '{date|hgdate}\\t{tag}\\n'}",
+            ])
+            .current_dir(checkout),
+        )?;
+        Ok(output
+            .lines()
+            .filter_map(parse_tag)
+            .filter(|tag| tag.name != "tip")
+            .collect())
+    }
+
+    fn custom_log(&self, checkout: &Path, processes: &JobProcesses) -> Result<String, GourceError> {
+        const TEMPLATE: &str = "commit {date|hgdate}\\t{author|person}\\n\
+            {file_adds % 'A\\t{file}\\n'}\
+            {file_mods % 'M\\t{file}\\n'}\
+            {file_dels % 'D\\t{file}\\n'}";
+        let output = run_log(
+            hg().args(["log", "--rev", "all()", "--template", TEMPLATE])
+                .current_dir(checkout),
+            processes,
+        )?;
+
+        let mut log = String::new();
+        let mut commit: Option<(i64, &str)> = None;
+        for line in output.lines() {
+            if let Some(header) = line.strip_prefix("commit ") {
+                // `hgdate` is the Unix time followed by the UTC offset.
+                commit = header.split_once('\t').and_then(|(date, person)| {
+                    let timestamp = date.split_whitespace().next()?.parse().ok()?;
+                    Some((timestamp, person))
+                });
+            } else if let (Some((timestamp, person)), Some((action, path))) =
+                (commit, line.split_once('\t'))
+            {
+                push_entry(&mut log, timestamp, person, action, path);
+            }
+        }
+        Ok(log)
+    }
+}
+
+impl Vcs for Subversion {
+    fn clone_repository(
+        &self,
+        url: &str,
+        destination: &Path,
+        processes: &JobProcesses,
+    ) -> Result<(), GourceError> {
+        run_clone(
+            Command::new("svn")
+                .args(["checkout", "--quiet", "--non-interactive", "--"])
+                .arg(url)
+                .arg(destination),
+            processes,
+        )
+    }
+
+    fn history_stats(
+        &self,
+        checkout: &Path,
+        range: &DateRange,
+    ) -> Result<HistoryStats, GourceError> {
+        let output = run_query(
+            Command::new("svn")
+                .args(["log", "--xml", "--quiet", "--non-interactive"])
+                .current_dir(checkout),
+        )?;
+        let entries = svn_log_entries(&output, None)?;
+        Ok(tally(
+            entries
+                .iter()
+                .map(|entry| (entry.timestamp, entry.author.as_str())),
+            range,
+        ))
+    }
+
+    fn tags(&self, _checkout: &Path) -> Result<Vec<Tag>, GourceError> {
+        // Subversion tags are copies in a directory outside the checkout.
+        Ok(Vec::new())
+    }
+
+    fn custom_log(&self, checkout: &Path, processes: &JobProcesses) -> Result<String, GourceError> {
+        // Paths in the log are relative to the repository root; only those
+        // under the checked-out directory are kept, relative to it.
+        let relative_url = run_log(
+            Command::new("svn")
+                .args(["info", "--show-item", "relative-url", "--non-interactive"])
+                .current_dir(checkout),
+            processes,
+        )?;
+        let root = relative_url.trim().trim_start_matches('^').to_string();
+
+        let output = run_log(
+            Command::new("svn")
+                .args(["log", "--xml", "--verbose", "--non-interactive"])
+                .current_dir(checkout),
+            processes,
+        )?;
+        let mut entries = svn_log_entries(&output, Some(&root))?;
+        // `svn log` lists the newest revision first.
+        entries.reverse();
+
+        let mut log = String::new();
+        for entry in &entries {
+            for (action, path) in &entry.paths {
+                push_entry(&mut log, entry.timestamp, &entry.author, action, path);
+            }
+        }
+        Ok(log)
+    }
+}
+
+struct SvnLogEntry {
+    timestamp: i64,
+    author: String,
+    /// Changed files (not directories) with their action.
+    paths: Vec<(&'static str, String)>,
+}
+
+/// Parses `svn log --xml`. With `root`, changed paths are made relative to it
+/// and paths outside it are dropped.
+fn svn_log_entries(xml: &str, root: Option<&str>) -> Result<Vec<SvnLogEntry>, GourceError> {
+    let document = roxmltree::Document::parse(xml).map_err(|e| {
+        error!("Failed to parse svn log: {}", e);
+        GourceError::CommitCountFailed
+    })?;
+    let root = root.map(|root| format!("{}/", root.trim_end_matches('/')));
+
+    let mut entries = Vec::new();
+    for node in document
+        .descendants()
+        .filter(|node| node.has_tag_name("logentry"))
+    {
+        let child_text = |name: &str| {
+            node.children()
+                .find(|child| child.has_tag_name(name))
+                .and_then(|child| child.text())
+        };
+        // Revisions without a date (such as revision 0) changed nothing.
+        let Some(timestamp) = child_text("date")
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+            .map(|date| date.timestamp())
+        else {
+            continue;
+        };
+        let author = child_text("author").unwrap_or("unknown").to_string();
+
+        let paths = node
+            .descendants()
+            .filter(|path| path.has_tag_name("path") && path.attribute("kind") != Some("dir"))
+            .filter_map(|path| {
+                let action = match path.attribute("action")? {
+                    "A" => "A",
+                    "D" => "D",
+                    _ => "M",
+                };
+                let text = path.text()?;
+                let relative = match &root {
+                    Some(root) if root != "/" => text.strip_prefix(root.as_str())?,
+                    _ => text,
+                };
+                Some((action, relative.to_string()))
+            })
+            .collect();
+        entries.push(SvnLogEntry {
+            timestamp,
+            author,
+            paths,
+        });
+    }
+    Ok(entries)
+}
+
+/// Mercurial with user configuration that could change its output disabled.
+fn hg() -> Command {
+    let mut command = Command::new("hg");
+    command.env("HGPLAIN", "1");
+    command
+}
+
+/// Tallies the individual commits, given by Unix time and author, that fall
+/// within `range`.
+fn tally<'a>(commits: impl Iterator<Item = (i64, &'a str)>, range: &DateRange) -> HistoryStats {
+    let mut stats = HistoryStats::default();
+    for (timestamp, author) in commits.filter(|(timestamp, _)| range.contains_time(*timestamp)) {
+        stats.add_commit(timestamp, author);
+    }
+    stats.sort();
+    stats
+}
+
+/// Parses a `TIMESTAMP<TAB>text` line. Mercurial's timestamps are followed by
+/// the UTC offset, which is ignored.
+fn parse_commit(line: &str) -> Option<(i64, &str)> {
+    let (date, text) = line.split_once('\t')?;
+    Some((date.split_whitespace().next()?.parse().ok()?, text))
+}
+
+fn parse_tag(line: &str) -> Option<Tag> {
+    let (timestamp, name) = parse_commit(line)?;
+    Some(Tag {
+        name: name.to_string(),
+        timestamp,
+    })
+}
+
+fn push_entry(log: &mut String, timestamp: i64, username: &str, action: &str, path: &str) {
+    // `|` separates the fields, so it cannot appear inside them.
+    let username = username.replace('|', " ");
+    let path = path.trim_start_matches('/').replace('|', " ");
+    log.push_str(&format!(
+        "{}|{}|{}|/{}\n",
+        timestamp, username, action, path
+    ));
+}
+
+fn run_clone(command: &mut Command, processes: &JobProcesses) -> Result<(), GourceError> {
+    let output = processes
+        .output(command)
+        .map_err(|_| GourceError::CloneFailed)?;
+
+    if !output.status.success() {
+        let error_message = String::from_utf8_lossy(&output.stderr);
+        error!("Clone failed: {}", error_message);
+        if is_transient_clone_error(&error_message) {
+            return Err(GourceError::CloneNetworkError);
+        }
+        return Err(GourceError::CloneFailed);
+    }
+
+    info!("Successfully cloned repository");
+    Ok(())
+}
+
+/// Recognizes clone errors caused by the network rather than the repository
+/// or its credentials.
+fn is_transient_clone_error(stderr: &str) -> bool {
+    const TRANSIENT_ERRORS: &[&str] = &[
+        "Could not resolve host",
+        "Connection timed out",
+        "Connection reset",
+        "Connection refused",
+        "Operation timed out",
+        "Failed to connect",
+        "early EOF",
+        "RPC failed",
+        "remote end hung up unexpectedly",
+        "gnutls_handshake() failed",
+        "returned error: 502",
+        "returned error: 503",
+        "returned error: 504",
+        "Temporary failure in name resolution",
+        "Unable to connect to a repository",
+        "timed out",
+    ];
+    TRANSIENT_ERRORS
+        .iter()
+        .any(|pattern| stderr.contains(pattern))
+}
+
+/// Runs a quick history query and returns its output.
+fn run_query(command: &mut Command) -> Result<String, GourceError> {
+    let output = command
+        .output()
+        .map_err(|_| GourceError::CommitCountFailed)?;
+    command_stdout(command, output, GourceError::CommitCountFailed)
+}
+
+/// Runs a history export as part of the job, so it is killed with it.
+fn run_log(command: &mut Command, processes: &JobProcesses) -> Result<String, GourceError> {
+    let output = processes
+        .output(command)
+        .map_err(|_| GourceError::CombinedLogFailed)?;
+    command_stdout(command, output, GourceError::CombinedLogFailed)
+}
+
+fn command_stdout(
+    command: &Command,
+    output: Output,
+    failure: GourceError,
+) -> Result<String, GourceError> {
+    if !output.status.success() {
+        error!(
+            "{:?} failed: {}",
+            command.get_program(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(failure);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn run(command: &mut Command) {
+        let output = command.output().expect("command starts");
+        assert!(
+            output.status.success(),
+            "{:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    fn day(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    /// Adds README.md and a.rs on 2024-01-02, changes a.rs later that day and
+    /// removes README.md on 2024-01-05.
+    fn write_history(commit: impl Fn(&str, &str)) {
+        commit("alice", "2024-01-02T10:00:00Z");
+        commit("bob", "2024-01-02T16:00:00Z");
+        commit("alice", "2024-01-05T09:30:00Z");
+    }
+
+    fn change_files(repo: &Path, step: usize) {
+        match step {
+            0 => {
+                fs::write(repo.join("README.md"), "hello").unwrap();
+                fs::write(repo.join("a.rs"), "fn main() {}").unwrap();
+            }
+            1 => fs::write(repo.join("a.rs"), "fn main() { run() }").unwrap(),
+            _ => fs::remove_file(repo.join("README.md")).unwrap(),
+        }
+    }
+
+    const EXPECTED_LOG: &str = "1704189600|alice|A|/README.md\n\
+        1704189600|alice|A|/a.rs\n\
+        1704211200|bob|M|/a.rs\n\
+        1704447000|alice|D|/README.md\n";
+
+    fn assert_history(vcs: &dyn Vcs, origin: &str, checkout: &Path) {
+        let processes = JobProcesses::default();
+        vcs.clone_repository(origin, checkout, &processes).unwrap();
+
+        let stats = vcs.history_stats(checkout, &DateRange::default()).unwrap();
+        assert_eq!(
+            stats.commits_per_day,
+            CommitsPerDay::from([(day("2024-01-02"), 2), (day("2024-01-05"), 1)])
+        );
+        assert_eq!(
+            stats.commits_per_author,
+            BTreeMap::from([("alice".to_string(), 2), ("bob".to_string(), 1)])
+        );
+        let timestamps: Vec<i64> = stats.commits.iter().map(|c| c.timestamp).collect();
+        assert_eq!(timestamps, [1704189600, 1704211200, 1704447000]);
+
+        let range = DateRange {
+            start: Some(day("2024-01-03")),
+            stop: None,
+        };
+        let stats = vcs.history_stats(checkout, &range).unwrap();
+        assert_eq!(
+            stats.commits_per_day,
+            CommitsPerDay::from([(day("2024-01-05"), 1)])
+        );
+        assert_eq!(
+            stats.commits_per_author,
+            BTreeMap::from([("alice".to_string(), 1)])
+        );
+
+        let mut log: Vec<&str> = Vec::new();
+        let output = vcs.custom_log(checkout, &processes).unwrap();
+        log.extend(output.lines());
+        // Files changed in one commit may be listed in any order.
+        log.sort();
+        let mut expected: Vec<&str> = EXPECTED_LOG.lines().collect();
+        expected.sort();
+        assert_eq!(log, expected);
+    }
+
+    #[test]
+    fn git_clones_counts_and_exports_history() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().join("origin");
+        fs::create_dir(&repo).unwrap();
+        run(Command::new("git")
+            .args(["init", "--quiet"])
+            .current_dir(&repo));
+
+        let step = std::cell::Cell::new(0);
+        write_history(|author, date| {
+            change_files(&repo, step.get());
+            step.set(step.get() + 1);
+            run(Command::new("git")
+                .args(["add", "--all"])
+                .current_dir(&repo));
+            run(Command::new("git")
+                .args(["-c", &format!("user.name={}", author)])
+                .args(["-c", "user.email=dev@example.com"])
+                .args(["commit", "--quiet", "--message", "change"])
+                .env("GIT_AUTHOR_DATE", date)
+                .env("GIT_COMMITTER_DATE", date)
+                .current_dir(&repo));
+        });
+
+        run(Command::new("git")
+            .args(["tag", "v1.0", "HEAD~1"])
+            .current_dir(&repo));
+
+        let checkout = dir.path().join("checkout");
+        assert_history(&Git, repo.to_str().unwrap(), &checkout);
+        assert_eq!(
+            Git.tags(&checkout).unwrap(),
+            vec![Tag {
+                name: "v1.0".to_string(),
+                timestamp: 1704211200,
+            }]
+        );
+    }
+
+    #[test]
+    #[ignore = "needs hg; run with `cargo test -- --ignored`"]
+    fn mercurial_clones_counts_and_exports_history() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().join("origin");
+        run(hg().arg("init").arg(&repo));
+
+        let step = std::cell::Cell::new(0);
+        write_history(|author, date| {
+            change_files(&repo, step.get());
+            step.set(step.get() + 1);
+            let date = DateTime::parse_from_rfc3339(date).unwrap().timestamp();
+            run(hg()
+                .args(["commit", "--addremove", "--message", "change"])
+                .args(["--user", author, "--date", &format!("{} 0", date)])
+                .current_dir(&repo));
+        });
+
+        assert_history(
+            &Mercurial,
+            repo.to_str().unwrap(),
+            &dir.path().join("checkout"),
+        );
+    }
+
+    #[test]
+    #[ignore = "needs svn and svnadmin; run with `cargo test -- --ignored`"]
+    fn subversion_clones_counts_and_exports_history() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().join("origin");
+        run(Command::new("svnadmin").arg("create").arg(&repo));
+        // Commit dates can only be set after the fact, through a revision
+        // property change the repository must allow.
+        let hook = repo.join("hooks/pre-revprop-change");
+        fs::write(&hook, "#!/bin/sh\nexit 0\n").unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let url = format!("file://{}", repo.display());
+        let work = dir.path().join("work");
+        run(Command::new("svn")
+            .args(["checkout", "--quiet"])
+            .arg(&url)
+            .arg(&work));
+
+        let step = std::cell::Cell::new(0);
+        write_history(|author, date| {
+            change_files(&work, step.get());
+            match step.get() {
+                0 => run(Command::new("svn")
+                    .args(["add", "--quiet", "README.md", "a.rs"])
+                    .current_dir(&work)),
+                2 => run(Command::new("svn")
+                    .args(["delete", "--quiet", "README.md"])
+                    .current_dir(&work)),
+                _ => {}
+            }
+            step.set(step.get() + 1);
+            run(Command::new("svn")
+                .args(["commit", "--quiet", "--message", "change"])
+                .args(["--username", author])
+                .current_dir(&work));
+            let revision = step.get().to_string();
+            let svn_date = format!("{}.000000Z", date.trim_end_matches('Z'));
+            for (property, value) in [("svn:date", svn_date.as_str()), ("svn:author", author)] {
+                run(Command::new("svn")
+                    .args(["propset", "--quiet", "--revprop", "-r", &revision])
+                    .args([property, value])
+                    .arg(&url));
+            }
+        });
+
+        assert_history(&Subversion, &url, &dir.path().join("checkout"));
+    }
+
+    #[test]
+    fn detects_the_vcs_from_the_url() {
+        assert_eq!(VcsKind::from_url("https://github.com/a/b.git"), None);
+        assert_eq!(
+            VcsKind::from_url("svn://svn.example.com/repo"),
+            Some(VcsKind::Svn)
+        );
+        assert_eq!(
+            VcsKind::from_url("svn+ssh://example.com/repo"),
+            Some(VcsKind::Svn)
+        );
+        assert_eq!(
+            VcsKind::from_url("hg+https://example.com/repo"),
+            Some(VcsKind::Hg)
+        );
+        assert_eq!(
+            VcsKind::from_url("git+ssh://example.com/repo"),
+            Some(VcsKind::Git)
+        );
+        assert_eq!(
+            VcsKind::resolve(Some(VcsKind::Hg), "https://example.com/repo"),
+            VcsKind::Hg
+        );
+        assert_eq!(
+            VcsKind::resolve(None, "https://example.com/repo"),
+            VcsKind::Git
+        );
+
+        assert_eq!(
+            client_url("hg+https://example.com/repo"),
+            "https://example.com/repo"
+        );
+        assert_eq!(
+            client_url("svn+ssh://example.com/repo"),
+            "svn+ssh://example.com/repo"
+        );
+        assert_eq!(
+            client_url("https://example.com/repo"),
+            "https://example.com/repo"
+        );
+    }
+
+    #[test]
+    fn accepts_only_network_urls() {
+        for url in [
+            "https://github.com/a/b.git",
+            "http://example.com/repo",
+            "ssh://git@example.com/repo",
+            "git://example.com/repo",
+            "svn://svn.example.com/repo",
+            "svn+ssh://example.com/repo",
+            "hg+https://example.com/repo",
+            "git+ssh://example.com/repo",
+        ] {
+            assert!(is_network_url(url), "{}", url);
+        }
+        for url in [
+            "file:///srv/repo",
+            "FILE:///srv/repo",
+            "git+file:///srv/repo",
+            "hg+file:///srv/repo",
+            "svn+file:///srv/repo",
+            "ext::sh -c touch% /tmp/pwned",
+            "/srv/repo",
+            "git@github.com:a/b.git",
+            "git:///srv/repo",
+        ] {
+            assert!(!is_network_url(url), "{}", url);
+        }
+    }
+
+    #[test]
+    fn recognizes_transient_clone_errors() {
+        assert!(is_transient_clone_error(
+            "fatal: unable to access 'https://github.com/a/b/': Could not resolve host: github.com"
+        ));
+        assert!(is_transient_clone_error(
+            "error: RPC failed; curl 56 GnuTLS recv error\nfatal: early EOF"
+        ));
+        assert!(!is_transient_clone_error(
+            "remote: Repository not found.\nfatal: repository 'https://github.com/a/b/' not found"
+        ));
+        assert!(!is_transient_clone_error(
+            "fatal: Authentication failed for 'https://github.com/a/b/'"
+        ));
+    }
+}
@@ -0,0 +1,559 @@
+use crate::config::Config;
+use crate::job_store::JobStore;
+use crate::shutdown::Shutdown;
+use crate::{JobState, JobStatus, JobTimings};
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tracing::{info, info_span, warn, Instrument};
+use url::{Host, Url};
+use uuid::Uuid;
+
+/// `sha256=` followed by the hex HMAC-SHA256 of the request body, keyed with
+/// the callback secret.
+pub const SIGNATURE_HEADER: &str = "X-Gitmotion-Signature";
+pub const EVENT_HEADER: &str = "X-Gitmotion-Event";
+/// Identifies one notification; retries of it reuse the same id.
+pub const DELIVERY_HEADER: &str = "X-Gitmotion-Delivery";
+
+/// Where to send a notification when a job finishes. Kept with the job's
+/// request rather than its status so the secret is never returned by the API.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Callback {
+    pub url: String,
+    pub secret: String,
+}
+
+/// One attempt at delivering a notification, as reported in job status.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WebhookDelivery {
+    pub delivery_id: String,
+    pub event: String,
+    pub attempt: u32,
+    pub attempted_at: DateTime<Utc>,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    event: &'static str,
+    job_id: &'a str,
+    state: JobState,
+    repo_url: &'a str,
+    error: Option<&'a str>,
+    artifacts: Artifacts,
+    timings: &'a JobTimings,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct Artifacts {
+    video_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stills_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    poster_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contact_sheet_url: Option<String>,
+}
+
+fn event_name(state: JobState) -> &'static str {
+    match state {
+        JobState::Completed => "job.completed",
+        JobState::Failed => "job.failed",
+        JobState::Cancelled => "job.cancelled",
+        JobState::Interrupted => "job.interrupted",
+        JobState::Queued | JobState::Running => "job.updated",
+    }
+}
+
+/// Whether callbacks may be sent to `url`. Hosts in `allowed_hosts` always
+/// may; other addresses must be public, and names are checked again when
+/// they are resolved.
+pub fn is_allowed_callback_url(url: &Url, allowed_hosts: &[String]) -> bool {
+    let Some(host) = url.host() else {
+        return false;
+    };
+    let name = host.to_string();
+    if is_allowed_host(
+        name.trim_start_matches('[').trim_end_matches(']'),
+        allowed_hosts,
+    ) {
+        return true;
+    }
+    match host {
+        Host::Domain(domain) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain != "localhost" && !domain.ends_with(".localhost")
+        }
+        Host::Ipv4(ip) => is_public(IpAddr::V4(ip)),
+        Host::Ipv6(ip) => is_public(IpAddr::V6(ip)),
+    }
+}
+
+fn is_allowed_host(host: &str, allowed_hosts: &[String]) -> bool {
+    allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+}
+
+/// Whether `ip` is reachable on the internet, rather than a loopback,
+/// private, link-local, shared, documentation or reserved address.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (18..20).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || first == 0x2001 && ip.segments()[1] == 0x0db8)
+        }
+    }
+}
+
+/// Resolves callback hosts to their public addresses only, so a name cannot
+/// point deliveries at internal services. Hosts in `allowed_hosts` resolve
+/// to all of their addresses.
+struct PublicResolver {
+    allowed_hosts: Vec<String>,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let allowed = is_allowed_host(&host, &self.allowed_hosts);
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|address| allowed || is_public(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+    mac.input(body);
+    format!("sha256={}", hex::encode(mac.result().code()))
+}
+
+/// Notifies callback URLs when jobs finish, retrying failed deliveries with
+/// backoff.
+#[derive(Clone)]
+pub struct WebhookSender {
+    client: reqwest::Client,
+    job_store: JobStore,
+    config: web::Data<Config>,
+    shutdown: Shutdown,
+    runtime: tokio::runtime::Handle,
+}
+
+impl WebhookSender {
+    pub fn new(
+        job_store: JobStore,
+        config: web::Data<Config>,
+        shutdown: Shutdown,
+    ) -> reqwest::Result<WebhookSender> {
+        // A redirect could lead anywhere, so it counts as a failed delivery.
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.webhooks.timeout_secs))
+            .user_agent(concat!("gitmotion-api/", env!("CARGO_PKG_VERSION")))
+            .redirect(Policy::none())
+            .dns_resolver(Arc::new(PublicResolver {
+                allowed_hosts: config.webhooks.allowed_hosts.clone(),
+            }))
+            .build()?;
+        Ok(WebhookSender {
+            client,
+            job_store,
+            config,
+            shutdown,
+            runtime: tokio::runtime::Handle::current(),
+        })
+    }
+
+    /// Reports the job's current (final) state to `callback` in the background.
+    pub fn send(&self, job_id: &str, callback: Callback) {
+        let sender = self.clone();
+        let job_id = job_id.to_string();
+        let span = info_span!(parent: None, "webhook", job_id = %job_id);
+        // Tracked so shutdown waits for the attempt in flight.
+        let running = self.shutdown.track_job();
+        self.runtime.spawn(
+            async move {
+                let _running = running;
+                sender.deliver(&job_id, &callback).await;
+            }
+            .instrument(span),
+        );
+    }
+
+    async fn deliver(&self, job_id: &str, callback: &Callback) {
+        let Some(status) = self.job_store.get(job_id).await else {
+            return;
+        };
+        // Requests queued before the check existed are refused here too.
+        let allowed = Url::parse(&callback.url)
+            .is_ok_and(|url| is_allowed_callback_url(&url, &self.config.webhooks.allowed_hosts));
+        if !allowed {
+            warn!("Not sending a webhook to a callback URL that is not allowed");
+            return;
+        }
+        let event = event_name(status.state);
+        let body = serde_json::to_vec(&self.payload(job_id, event, &status))
+            .expect("webhook payload serializes");
+        let signature = sign(&callback.secret, &body);
+        let delivery_id = Uuid::new_v4().to_string();
+        let max_attempts = self.config.webhooks.max_attempts;
+
+        for attempt in 1..=max_attempts {
+            let attempted_at = Utc::now();
+            let started = Instant::now();
+            let result = self
+                .client
+                .post(&callback.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, &signature)
+                .header(EVENT_HEADER, event)
+                .header(DELIVERY_HEADER, &delivery_id)
+                .body(body.clone())
+                .send()
+                .await;
+            let (status_code, error) = match result {
+                Ok(response) if response.status().is_success() => {
+                    (Some(response.status().as_u16()), None)
+                }
+                Ok(response) => (
+                    Some(response.status().as_u16()),
+                    Some(format!("Callback responded with {}", response.status())),
+                ),
+                Err(e) => (None, Some(e.to_string())),
+            };
+
+            let delivered = error.is_none();
+            let delivery = WebhookDelivery {
+                delivery_id: delivery_id.clone(),
+                event: event.to_string(),
+                attempt,
+                attempted_at,
+                status_code,
+                error,
+                duration_ms: started.elapsed().as_millis() as u64,
+            };
+            self.job_store
+                .update(job_id, |status| {
+                    status.webhook_deliveries.push(delivery.clone())
+                })
+                .await;
+
+            if delivered {
+                info!("Delivered {} webhook on attempt {}", event, attempt);
+                return;
+            }
+            if attempt == max_attempts {
+                warn!(
+                    "Giving up on {} webhook after {} attempts",
+                    event, max_attempts
+                );
+                return;
+            }
+
+            let backoff = self.config.webhook_backoff(attempt);
+            warn!(
+                "Webhook attempt {} failed, retrying in {:?}",
+                attempt, backoff
+            );
+            tokio::select! {
+                _ = sleep(backoff) => {}
+                _ = self.shutdown.draining() => {
+                    warn!("Abandoning webhook delivery because the server is shutting down");
+                    return;
+                }
+            }
+        }
+    }
+
+    fn payload<'a>(
+        &self,
+        job_id: &'a str,
+        event: &'static str,
+        status: &'a JobStatus,
+    ) -> WebhookPayload<'a> {
+        // A stills preview has no video, and a promoted one keeps its stills.
+        // Without a public URL the links are paths on this API, never where
+        // the store keeps the file.
+        let public_url = self.config.server.public_url.as_deref().unwrap_or_default();
+        let artifact_url = |route: &str, url: &Option<String>| {
+            (status.state == JobState::Completed && url.is_some())
+                .then(|| format!("{}/{}/{}", public_url.trim_end_matches('/'), route, job_id))
+        };
+        let video_url = artifact_url("video", &status.video_url);
+        let stills_url = artifact_url("stills", &status.stills_url);
+        let poster_url = artifact_url("poster", &status.poster_url);
+        let contact_sheet_url = artifact_url("contact-sheet", &status.contact_sheet_url);
+
+        WebhookPayload {
+            event,
+            job_id,
+            state: status.state,
+            repo_url: &status.repo_url,
+            error: status.error.as_deref(),
+            artifacts: Artifacts {
+                video_url,
+                stills_url,
+                poster_url,
+                contact_sheet_url,
+            },
+            timings: &status.timings,
+            created_at: status.created_at,
+            finished_at: status.finished_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn allowed(url: &str, allowed_hosts: &[&str]) -> bool {
+        let allowed_hosts: Vec<String> =
+            allowed_hosts.iter().map(|host| host.to_string()).collect();
+        is_allowed_callback_url(&Url::parse(url).unwrap(), &allowed_hosts)
+    }
+
+    #[test]
+    fn refuses_callbacks_to_internal_addresses() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://localhost:8080/hook",
+            "http://api.localhost/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.1.2.3/hook",
+            "http://172.16.0.1/hook",
+            "http://192.168.1.1/hook",
+            "http://100.64.0.1/hook",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            assert!(!allowed(url, &[]), "{}", url);
+        }
+        for url in [
+            "https://hooks.example.com/gitmotion",
+            "http://93.184.216.34/hook",
+            "http://[2606:4700::1111]/hook",
+        ] {
+            assert!(allowed(url, &[]), "{}", url);
+        }
+    }
+
+    #[actix_web::test]
+    async fn resolves_names_to_public_addresses_only() {
+        let resolve = |allowed_hosts: Vec<String>| {
+            PublicResolver { allowed_hosts }.resolve(Name::from_str("localhost").unwrap())
+        };
+        assert!(resolve(Vec::new()).await.is_err());
+        let addresses: Vec<SocketAddr> = resolve(vec!["localhost".to_string()])
+            .await
+            .unwrap()
+            .collect();
+        assert!(addresses.iter().all(|address| address.ip().is_loopback()));
+    }
+
+    #[test]
+    fn allows_listed_internal_hosts() {
+        assert!(allowed("http://10.0.0.5:9000/hook", &["10.0.0.5"]));
+        assert!(allowed("http://LOCALHOST/hook", &["localhost"]));
+        assert!(allowed("http://[::1]/hook", &["::1"]));
+        assert!(!allowed("http://10.0.0.6/hook", &["10.0.0.5"]));
+    }
+
+    /// Accepts one connection per response in `responses`, answering with
+    /// that status, and returns each request's head and body.
+    async fn receiver(responses: &'static [u16]) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let requests = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                loop {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_ascii_lowercase()
+                                .strip_prefix("content-length: ")
+                                .map(str::to_string)
+                        })
+                        .and_then(|length| length.parse::<usize>().ok())
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8_lossy(&request).to_string());
+            }
+            requests
+        });
+        (url, requests)
+    }
+
+    fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+        request.lines().find_map(|line| {
+            let (header, value) = line.split_once(": ")?;
+            header.eq_ignore_ascii_case(name).then_some(value)
+        })
+    }
+
+    #[actix_web::test]
+    async fn delivers_signed_payloads_and_records_each_attempt() {
+        let (url, requests) = receiver(&[500, 200]).await;
+        let mut config = Config::default();
+        config.webhooks.allowed_hosts = vec!["127.0.0.1".to_string()];
+        config.webhooks.max_attempts = 3;
+        config.webhooks.initial_backoff_secs = 0;
+        let job_store = JobStore::memory();
+        let settings = crate::GourceSettings::default();
+        let mut status = crate::queued_status("https://github.com/a/b", &settings, None);
+        status.state = JobState::Completed;
+        status.video_url = Some("/srv/videos/gource_test.mp4".to_string());
+        job_store.insert("test", status).await;
+        let sender = WebhookSender::new(
+            job_store.clone(),
+            web::Data::new(config),
+            Shutdown::default(),
+        )
+        .unwrap();
+
+        let callback = Callback {
+            url,
+            secret: "secret".to_string(),
+        };
+        sender.deliver("test", &callback).await;
+
+        let requests = requests.await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let delivery_ids: Vec<&str> = requests
+            .iter()
+            .map(|request| header(request, DELIVERY_HEADER).unwrap())
+            .collect();
+        assert_eq!(delivery_ids[0], delivery_ids[1]);
+        for request in &requests {
+            let (_, body) = request.split_once("\r\n\r\n").unwrap();
+            assert_eq!(
+                header(request, SIGNATURE_HEADER),
+                Some(sign("secret", body.as_bytes()).as_str())
+            );
+            assert_eq!(header(request, EVENT_HEADER), Some("job.completed"));
+            let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(payload["job_id"], "test");
+            assert_eq!(payload["state"], "Completed");
+            assert_eq!(payload["artifacts"]["video_url"], "/video/test");
+        }
+
+        let deliveries = job_store.get("test").await.unwrap().webhook_deliveries;
+        let attempts: Vec<(u32, Option<u16>, bool)> = deliveries
+            .iter()
+            .map(|delivery| {
+                (
+                    delivery.attempt,
+                    delivery.status_code,
+                    delivery.error.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(attempts, [(1, Some(500), true), (2, Some(200), false)]);
+        assert!(deliveries
+            .iter()
+            .all(|delivery| delivery.delivery_id == delivery_ids[0]));
+    }
+
+    #[actix_web::test]
+    async fn links_artifacts_under_the_public_url() {
+        let mut config = Config::default();
+        config.server.public_url = Some("https://gitmotion.example.com/".to_string());
+        let sender = WebhookSender::new(
+            JobStore::memory(),
+            web::Data::new(config),
+            Shutdown::default(),
+        )
+        .unwrap();
+        let settings = crate::GourceSettings::default();
+        let mut status = crate::queued_status("https://github.com/a/b", &settings, None);
+        status.video_url = Some("/srv/videos/gource_test.mp4".to_string());
+        status.poster_url = Some("/srv/videos/gource_test_poster.png".to_string());
+
+        let payload = serde_json::to_value(sender.payload("test", "job.failed", &status)).unwrap();
+        assert!(payload["artifacts"]["video_url"].is_null());
+
+        status.state = JobState::Completed;
+        let payload =
+            serde_json::to_value(sender.payload("test", "job.completed", &status)).unwrap();
+        assert_eq!(
+            payload["artifacts"]["video_url"],
+            "https://gitmotion.example.com/video/test"
+        );
+        assert_eq!(
+            payload["artifacts"]["poster_url"],
+            "https://gitmotion.example.com/poster/test"
+        );
+        assert!(payload["artifacts"].get("stills_url").is_none());
+    }
+}
@@ -3,7 +3,7 @@
 import { useParams, useRouter } from "next/navigation";
 import GourceInput, { GourceSettings } from "@/components/gource-input";
 import { ProgressStep } from "@/components/gource-progress";
-import GourceVideo from "@/components/gource-video";
+import GourceVideo, { RepoStats } from "@/components/gource-video";
 import { useState, useRef, useEffect } from "react";
 import useSWR from "swr";
 import { Icons } from "@/components/ui/icons";
@@ -14,6 +14,7 @@ interface JobStatus {
   repo_url: string;
   error: string | null;
   settings: GourceSettings;
+  stats: RepoStats | null;
 }
 
 const LoadingIndicator = () => (
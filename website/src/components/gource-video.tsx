@@ -3,11 +3,19 @@
 import { ArrowDownTrayIcon } from "@heroicons/react/20/solid";
 import GourceProgress, { ProgressStep } from "./gource-progress";
 
+export interface RepoStats {
+  total_commits: number;
+  days_with_commits: number;
+  seconds_per_day: number;
+  hide_filenames: boolean;
+}
+
 interface GourceVideoProps {
   jobStatus: {
     step: ProgressStep;
     video_url: string | null;
     error: string | null;
+    stats?: RepoStats | null;
   } | null;
   jobId: string | null;
   error: Error | null;
@@ -65,6 +73,13 @@ export default function GourceVideo({
                     src={videoUrl || undefined}
                   />
                 </div>
+                {jobStatus.stats && (
+                  <p className="mt-3 text-center text-sm text-white/50">
+                    {jobStatus.stats.total_commits.toLocaleString()} commits
+                    over {jobStatus.stats.days_with_commits.toLocaleString()}{" "}
+                    active days
+                  </p>
+                )}
               </div>
             )}
         </div>